@@ -11,35 +11,9 @@
 //!
 //! Connect with: sftp -P 2224 -i ~/.ssh/id_ed25519 user@localhost
 
-use sftp_s3::{MemoryBackend, Server, ServerConfig};
-use std::path::Path;
+use sftp_s3::{AuthorizedKeys, MemoryBackend, Server, ServerConfig};
 use tracing_subscriber::EnvFilter;
 
-/// Parse an OpenSSH public key line (e.g., "ssh-ed25519 AAAA... comment")
-fn parse_pubkey(line: &str) -> Option<russh::keys::PublicKey> {
-    let line = line.trim();
-    if line.is_empty() || line.starts_with('#') {
-        return None;
-    }
-    // Parse OpenSSH format: "algorithm base64-key [comment]"
-    let parts: Vec<&str> = line.split_whitespace().collect();
-    if parts.len() < 2 {
-        return None;
-    }
-    russh::keys::parse_public_key_base64(parts[1]).ok()
-}
-
-/// Load authorized keys from a file
-fn load_authorized_keys(path: &Path) -> Vec<russh::keys::PublicKey> {
-    match std::fs::read_to_string(path) {
-        Ok(contents) => contents.lines().filter_map(parse_pubkey).collect(),
-        Err(e) => {
-            eprintln!("Warning: could not read {}: {}", path.display(), e);
-            Vec::new()
-        }
-    }
-}
-
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     tracing_subscriber::fmt()
@@ -47,10 +21,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         .init();
 
     // Load authorized keys from file argument or AUTHORIZED_KEYS env var
-    let keys: Vec<russh::keys::PublicKey> = if let Some(path) = std::env::args().nth(1) {
-        load_authorized_keys(Path::new(&path))
+    let keys = if let Some(path) = std::env::args().nth(1) {
+        AuthorizedKeys::load(&path).unwrap_or_else(|e| {
+            eprintln!("Warning: could not read {}: {}", path, e);
+            AuthorizedKeys::default()
+        })
     } else if let Ok(keys_str) = std::env::var("AUTHORIZED_KEYS") {
-        keys_str.lines().filter_map(parse_pubkey).collect()
+        AuthorizedKeys::parse(&keys_str)
     } else {
         eprintln!("Usage: pubkey_server <authorized_keys_file>");
         eprintln!("   or: AUTHORIZED_KEYS=\"ssh-ed25519 ...\" pubkey_server");
@@ -63,11 +40,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     }
 
     println!("Loaded {} authorized key(s)", keys.len());
-    for key in &keys {
+    for entry in keys.entries() {
         println!(
             "  - {} {}",
-            key.algorithm(),
-            key.fingerprint(Default::default())
+            entry.key.algorithm(),
+            entry.key.fingerprint(Default::default())
         );
     }
 
@@ -84,10 +61,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         port
     );
 
-    // All loaded keys authorize any username
+    // All loaded keys authorize any username; options like from= still apply
     Server::new(MemoryBackend::new())
         .config(config)
-        .with_pubkey_auth(move |_user, key| keys.iter().any(|k| k == key))
+        .with_pubkey_authenticator(keys)
         .run()
         .await
 }
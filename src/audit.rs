@@ -0,0 +1,372 @@
+//! Structured audit events for logins and file operations
+
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// Lines a [`JsonLinesSink`] holds for its writer before dropping events
+const JSON_LINES_QUEUE_SIZE: usize = 4096;
+
+/// Whether a handle was opened for downloading or uploading
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferMode {
+    Read,
+    Write,
+}
+
+/// What happened
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditKind {
+    /// A login attempt with one method; multi-step logins record each step
+    Auth {
+        method: String,
+    },
+    Open {
+        path: String,
+        mode: TransferMode,
+    },
+    /// A file handle was closed after moving `bytes` bytes
+    Close {
+        path: String,
+        mode: TransferMode,
+        bytes: u64,
+    },
+    Remove {
+        path: String,
+    },
+    Rename {
+        from: String,
+        to: String,
+    },
+    Mkdir {
+        path: String,
+    },
+    Rmdir {
+        path: String,
+    },
+    Setstat {
+        path: String,
+    },
+    Symlink {
+        path: String,
+        target: String,
+    },
+    Hardlink {
+        from: String,
+        to: String,
+    },
+    /// A server-side `copy-file` request
+    Copy {
+        from: String,
+        to: String,
+    },
+}
+
+/// One audit record
+///
+/// Serializes flat, e.g. `{"timestamp":1700000000000,"user":"alice",
+/// "peer":"10.0.0.5:50022","event":"close","path":"/in/a.csv",
+/// "mode":"write","bytes":1024,"success":true}`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuditEvent {
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+    /// Authenticated user, or the name a login was attempted with
+    pub user: Option<String>,
+    pub peer: Option<SocketAddr>,
+    #[serde(flatten)]
+    pub kind: AuditKind,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl AuditEvent {
+    /// Event happening now; it succeeded unless `error` is set
+    pub fn new(
+        user: Option<String>,
+        peer: Option<SocketAddr>,
+        kind: AuditKind,
+        error: Option<String>,
+    ) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        Self {
+            timestamp,
+            user,
+            peer,
+            kind,
+            success: error.is_none(),
+            error,
+        }
+    }
+}
+
+/// Destination for audit events
+///
+/// Called inline on the session's task, so implementations should be quick;
+/// hand slow work to a channel. Any `Fn(&AuditEvent)` closure is a sink.
+pub trait AuditSink: Send + Sync + 'static {
+    fn record(&self, event: &AuditEvent);
+}
+
+impl<F> AuditSink for F
+where
+    F: Fn(&AuditEvent) + Send + Sync + 'static,
+{
+    fn record(&self, event: &AuditEvent) {
+        self(event)
+    }
+}
+
+/// Appends one JSON object per line to a file
+///
+/// Lines are written by a dedicated thread so a slow disk never blocks a
+/// session. If the writer falls too far behind, new events are dropped with
+/// a warning. Dropping the sink waits for queued lines to be written.
+pub struct JsonLinesSink {
+    lines: Option<SyncSender<Vec<u8>>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl JsonLinesSink {
+    /// Open `path` for appending, creating it if needed
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let (lines, queued) = mpsc::sync_channel(JSON_LINES_QUEUE_SIZE);
+        let writer = std::thread::Builder::new()
+            .name("sftp-audit-log".into())
+            .spawn(move || write_lines(file, queued))?;
+        Ok(Self {
+            lines: Some(lines),
+            writer: Some(writer),
+        })
+    }
+}
+
+/// Write queued lines to `file` until the sink is dropped, flushing whenever
+/// the queue runs dry
+fn write_lines(file: File, queued: Receiver<Vec<u8>>) {
+    let mut file = BufWriter::new(file);
+    while let Ok(mut line) = queued.recv() {
+        loop {
+            if let Err(err) = file.write_all(&line) {
+                warn!(error = %err, "Could not write audit event");
+            }
+            match queued.try_recv() {
+                Ok(next) => line = next,
+                Err(_) => break,
+            }
+        }
+        if let Err(err) = file.flush() {
+            warn!(error = %err, "Could not write audit event");
+        }
+    }
+}
+
+impl Drop for JsonLinesSink {
+    fn drop(&mut self) {
+        drop(self.lines.take());
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+impl AuditSink for JsonLinesSink {
+    fn record(&self, event: &AuditEvent) {
+        let mut line = match serde_json::to_vec(event) {
+            Ok(line) => line,
+            Err(err) => {
+                warn!(error = %err, "Could not serialize audit event");
+                return;
+            }
+        };
+        line.push(b'\n');
+        let Some(ref lines) = self.lines else {
+            return;
+        };
+        match lines.try_send(line) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => warn!("Audit log writer is behind, dropping event"),
+            Err(TrySendError::Disconnected(_)) => warn!("Audit log writer has stopped"),
+        }
+    }
+}
+
+/// Emits each event as an `INFO` tracing event with target `sftp_s3::audit`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TracingSink;
+
+impl AuditSink for TracingSink {
+    fn record(&self, event: &AuditEvent) {
+        let details = serde_json::to_string(&event.kind).unwrap_or_default();
+        info!(
+            target: "sftp_s3::audit",
+            user = event.user.as_deref(),
+            peer = ?event.peer,
+            success = event.success,
+            error = event.error.as_deref(),
+            "{}",
+            details
+        );
+    }
+}
+
+/// Fans events out to every configured sink
+#[derive(Clone, Default)]
+pub struct AuditLog {
+    sinks: Vec<Arc<dyn AuditSink>>,
+}
+
+impl AuditLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a sink
+    pub fn with_sink(mut self, sink: impl AuditSink) -> Self {
+        self.sinks.push(Arc::new(sink));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    pub fn record(&self, event: &AuditEvent) {
+        for sink in &self.sinks {
+            sink.record(event);
+        }
+    }
+}
+
+/// An audit log bound to one session's user and address
+#[derive(Clone)]
+pub(crate) struct SessionAudit {
+    log: Arc<AuditLog>,
+    user: Option<String>,
+    peer: Option<SocketAddr>,
+}
+
+impl SessionAudit {
+    pub(crate) fn new(log: Arc<AuditLog>, user: Option<String>, peer: Option<SocketAddr>) -> Self {
+        Self { log, user, peer }
+    }
+
+    /// Record `kind`, failed if `error` is set
+    pub(crate) fn record(&self, kind: AuditKind, error: Option<String>) {
+        let event = AuditEvent::new(self.user.clone(), self.peer, kind, error);
+        self.log.record(&event);
+    }
+}
+
+impl std::fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuditLog")
+            .field("sinks", &self.sinks.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+
+    fn event(kind: AuditKind, error: Option<&str>) -> AuditEvent {
+        AuditEvent::new(
+            Some("alice".into()),
+            Some("10.0.0.5:50022".parse().unwrap()),
+            kind,
+            error.map(String::from),
+        )
+    }
+
+    #[test]
+    fn test_event_json_is_flat() {
+        let mut event = event(
+            AuditKind::Close {
+                path: "/in/a.csv".into(),
+                mode: TransferMode::Write,
+                bytes: 1024,
+            },
+            None,
+        );
+        event.timestamp = 1;
+        let json: serde_json::Value = serde_json::to_value(&event).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "timestamp": 1,
+                "user": "alice",
+                "peer": "10.0.0.5:50022",
+                "event": "close",
+                "path": "/in/a.csv",
+                "mode": "write",
+                "bytes": 1024,
+                "success": true,
+            })
+        );
+    }
+
+    #[test]
+    fn test_error_marks_failure() {
+        let event = event(
+            AuditKind::Remove { path: "/x".into() },
+            Some("PermissionDenied"),
+        );
+        assert!(!event.success);
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["error"], "PermissionDenied");
+    }
+
+    #[test]
+    fn test_json_lines_sink_appends() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let sink = JsonLinesSink::open(file.path()).unwrap();
+        sink.record(&event(AuditKind::Mkdir { path: "/a".into() }, None));
+        sink.record(&event(
+            AuditKind::Rename {
+                from: "/a".into(),
+                to: "/b".into(),
+            },
+            None,
+        ));
+
+        // Dropping the sink waits for its writer
+        drop(sink);
+        let contents = std::fs::read_to_string(file.path()).unwrap();
+        let lines: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["event"], "mkdir");
+        assert_eq!(lines[1]["to"], "/b");
+    }
+
+    #[test]
+    fn test_audit_log_fans_out() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let first = seen.clone();
+        let second = seen.clone();
+        let log = AuditLog::new()
+            .with_sink(move |e: &AuditEvent| first.lock().push(e.kind.clone()))
+            .with_sink(move |e: &AuditEvent| second.lock().push(e.kind.clone()));
+
+        log.record(&event(AuditKind::Rmdir { path: "/a".into() }, None));
+        assert_eq!(seen.lock().len(), 2);
+    }
+}
@@ -0,0 +1,546 @@
+use super::{is_sftp_command, Authenticator};
+use async_trait::async_trait;
+use ipnet::IpNet;
+use russh::keys::PublicKey;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+/// Options that may precede a key in an authorized_keys line
+///
+/// Only options that matter to an SFTP-only server are enforced; the
+/// forwarding and pty restrictions are accepted and have nothing to restrict.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyOptions {
+    /// `from="..."` host patterns the client address must match
+    pub from: Option<Vec<String>>,
+    /// `expiry-time="..."` as a Unix timestamp
+    pub expiry_time: Option<u64>,
+    /// `command="..."`, only accepted for SFTP servers
+    pub command: Option<String>,
+    /// `cert-authority`: the key signs certificates and cannot log in itself
+    pub cert_authority: bool,
+}
+
+/// Options that are accepted but have no effect on SFTP sessions
+const IGNORED_OPTIONS: &[&str] = &[
+    "restrict",
+    "no-agent-forwarding",
+    "no-port-forwarding",
+    "no-pty",
+    "no-user-rc",
+    "no-X11-forwarding",
+    "agent-forwarding",
+    "port-forwarding",
+    "pty",
+    "user-rc",
+    "X11-forwarding",
+    "no-touch-required",
+    "verify-required",
+    "permitopen",
+    "permitlisten",
+    "environment",
+    "principals",
+    "tunnel",
+];
+
+impl KeyOptions {
+    /// Parse a comma-separated option list
+    fn parse(options: &str) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        for option in split_options(options)? {
+            let (name, value) = match option.split_once('=') {
+                Some((name, value)) => (name, Some(unquote(value)?)),
+                None => (option.as_str(), None),
+            };
+            match (name.to_ascii_lowercase().as_str(), value) {
+                ("from", Some(value)) => {
+                    parsed.from = Some(value.split(',').map(|p| p.trim().to_string()).collect())
+                }
+                ("expiry-time", Some(value)) => {
+                    parsed.expiry_time = Some(
+                        parse_expiry_time(&value)
+                            .ok_or_else(|| format!("invalid expiry-time \"{value}\""))?,
+                    )
+                }
+                ("command", Some(value)) => parsed.command = Some(value),
+                ("cert-authority", None) => parsed.cert_authority = true,
+                (name, _) if IGNORED_OPTIONS.iter().any(|o| o.eq_ignore_ascii_case(name)) => {}
+                (name, _) => return Err(format!("unsupported option \"{name}\"")),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Whether a key with these options may log in from `peer` at `now`
+    fn permits(&self, peer: Option<IpAddr>, now: u64) -> bool {
+        if self.cert_authority {
+            return false;
+        }
+        if self.expiry_time.is_some_and(|expiry| now >= expiry) {
+            return false;
+        }
+        if self.command.as_deref().is_some_and(|c| !is_sftp_command(c)) {
+            return false;
+        }
+        match (&self.from, peer) {
+            (Some(patterns), Some(peer)) => match_from(patterns, peer),
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+}
+
+/// One parsed authorized_keys line
+#[derive(Debug, Clone)]
+pub struct AuthorizedKey {
+    pub key: PublicKey,
+    pub options: KeyOptions,
+}
+
+impl AuthorizedKey {
+    /// Parse a single line, returning `Ok(None)` for blanks and comments
+    pub fn parse(line: &str) -> Result<Option<Self>, String> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+
+        // Lines without options start directly with the key type
+        if let Ok(key) = PublicKey::from_openssh(line) {
+            return Ok(Some(Self {
+                key,
+                options: KeyOptions::default(),
+            }));
+        }
+
+        let (options, rest) = split_leading_options(line);
+        let options = KeyOptions::parse(options)?;
+        let key = PublicKey::from_openssh(rest.trim_start()).map_err(|e| e.to_string())?;
+        Ok(Some(Self { key, options }))
+    }
+}
+
+/// Keys parsed from an authorized_keys file
+#[derive(Debug, Clone, Default)]
+pub struct AuthorizedKeys {
+    entries: Vec<AuthorizedKey>,
+}
+
+impl AuthorizedKeys {
+    /// Parse authorized_keys content, skipping lines that cannot be used
+    ///
+    /// As in sshd, a line with an unknown option or malformed key is ignored
+    /// with a warning rather than failing the whole file.
+    pub fn parse(contents: &str) -> Self {
+        let mut entries = Vec::new();
+        for (number, line) in contents.lines().enumerate() {
+            match AuthorizedKey::parse(line) {
+                Ok(Some(entry)) => entries.push(entry),
+                Ok(None) => {}
+                Err(err) => warn!(line = number + 1, error = %err, "Skipping authorized_keys line"),
+            }
+        }
+        Self { entries }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+
+    pub fn entries(&self) -> &[AuthorizedKey] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Whether `key` may log in from `peer`
+    pub fn authorizes(&self, key: &PublicKey, peer: Option<IpAddr>) -> bool {
+        self.authorizes_at(key, peer, unix_now())
+    }
+
+    fn authorizes_at(&self, key: &PublicKey, peer: Option<IpAddr>, now: u64) -> bool {
+        self.entries
+            .iter()
+            .any(|e| e.key.key_data() == key.key_data() && e.options.permits(peer, now))
+    }
+}
+
+/// Keys shared by every user
+#[async_trait]
+impl Authenticator for AuthorizedKeys {
+    async fn check_publickey(&self, _user: &str, key: &PublicKey) -> bool {
+        self.authorizes(key, None)
+    }
+
+    async fn check_publickey_from(
+        &self,
+        _user: &str,
+        key: &PublicKey,
+        peer: Option<IpAddr>,
+    ) -> bool {
+        self.authorizes(key, peer)
+    }
+}
+
+/// authorized_keys file path, optionally templated per user
+///
+/// `%u` in the path is replaced by the login name and `%%` by a literal `%`,
+/// as in sshd's `AuthorizedKeysFile`. The file is read on every login, so
+/// edits take effect without a restart.
+#[derive(Debug, Clone)]
+pub struct AuthorizedKeysFile {
+    template: String,
+}
+
+impl AuthorizedKeysFile {
+    pub fn new(template: impl Into<String>) -> Self {
+        Self {
+            template: template.into(),
+        }
+    }
+
+    /// Path of the file for `user`, or `None` if the name is unsafe in a path
+    pub fn path_for(&self, user: &str) -> Option<PathBuf> {
+        if self.template.contains("%u")
+            && (user.is_empty() || user == "." || user == ".." || user.contains(['/', '\0']))
+        {
+            return None;
+        }
+
+        let mut path = String::new();
+        let mut chars = self.template.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                path.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('u') => path.push_str(user),
+                Some('%') => path.push('%'),
+                Some(other) => {
+                    path.push('%');
+                    path.push(other);
+                }
+                None => path.push('%'),
+            }
+        }
+        Some(PathBuf::from(path))
+    }
+
+    /// Load the keys for `user`; a missing file means no keys
+    pub async fn keys_for(&self, user: &str) -> io::Result<AuthorizedKeys> {
+        let Some(path) = self.path_for(user) else {
+            return Ok(AuthorizedKeys::default());
+        };
+        match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => Ok(AuthorizedKeys::parse(&contents)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                debug!(user, path = %path.display(), "No authorized_keys file");
+                Ok(AuthorizedKeys::default())
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[async_trait]
+impl Authenticator for AuthorizedKeysFile {
+    async fn check_publickey(&self, user: &str, key: &PublicKey) -> bool {
+        self.check_publickey_from(user, key, None).await
+    }
+
+    async fn check_publickey_from(
+        &self,
+        user: &str,
+        key: &PublicKey,
+        peer: Option<IpAddr>,
+    ) -> bool {
+        match self.keys_for(user).await {
+            Ok(keys) => keys.authorizes(key, peer),
+            Err(err) => {
+                warn!(user, error = %err, "Could not read authorized_keys file");
+                false
+            }
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Split off the leading option list at the first unquoted whitespace
+fn split_leading_options(line: &str) -> (&str, &str) {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => return (&line[..i], &line[i..]),
+            _ => {}
+        }
+    }
+    (line, "")
+}
+
+/// Split an option list on commas outside quotes
+fn split_options(options: &str) -> Result<Vec<String>, String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in options.chars() {
+        match c {
+            _ if escaped => {
+                current.push(c);
+                escaped = false;
+            }
+            '\\' if quoted => {
+                current.push(c);
+                escaped = true;
+            }
+            '"' => {
+                current.push(c);
+                quoted = !quoted;
+            }
+            ',' if !quoted => parts.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    if quoted {
+        return Err("unterminated quote in options".into());
+    }
+    parts.push(current);
+    Ok(parts)
+}
+
+/// Strip surrounding quotes from an option value and unescape `\"`
+fn unquote(value: &str) -> Result<String, String> {
+    let Some(inner) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) else {
+        return Err(format!("option value must be quoted: {value}"));
+    };
+    Ok(inner.replace("\\\"", "\""))
+}
+
+/// Match `peer` against sshd-style `from=` patterns
+///
+/// Patterns are CIDR ranges or address globs using `*` and `?`; a leading `!`
+/// negates, and any negated match rejects outright. Hostnames are compared
+/// against the textual address since no reverse lookup is done.
+fn match_from(patterns: &[String], peer: IpAddr) -> bool {
+    let text = peer.to_string();
+    let mut allowed = false;
+    for pattern in patterns {
+        let (negated, pattern) = match pattern.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, pattern.as_str()),
+        };
+        let matches = match pattern.parse::<IpNet>() {
+            Ok(net) => net.contains(&peer),
+            Err(_) => glob_match(pattern, &text),
+        };
+        if matches && negated {
+            return false;
+        }
+        allowed |= matches;
+    }
+    allowed
+}
+
+/// Wildcard match supporting `*` and `?`
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((bp, bt)) = backtrack {
+            p = bp + 1;
+            t = bt + 1;
+            backtrack = Some((bp, bt + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Parse `YYYYMMDD[HHMM[SS]]` (optionally suffixed with `Z`) as UTC
+fn parse_expiry_time(value: &str) -> Option<u64> {
+    let value = value.strip_suffix(['Z', 'z']).unwrap_or(value);
+    if !matches!(value.len(), 8 | 12 | 14) || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let field = |range: std::ops::Range<usize>| value.get(range)?.parse::<u64>().ok();
+    let year = field(0..4)?;
+    let month = field(4..6)?;
+    let day = field(6..8)?;
+    let hour = if value.len() >= 12 { field(8..10)? } else { 0 };
+    let minute = if value.len() >= 12 { field(10..12)? } else { 0 };
+    let second = if value.len() == 14 { field(12..14)? } else { 0 };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+    if year < 1970 || second > 60 {
+        return None;
+    }
+
+    // Days since the epoch for a proleptic Gregorian date
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y / 400;
+    let yoe = y % 400;
+    let doy = (153 * m + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+
+    Some(days * 86_400 + hour * 3_600 + minute * 60 + second)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use russh::keys::ssh_key::rand_core::OsRng;
+    use russh::keys::{Algorithm, PrivateKey};
+    use std::io::Write;
+
+    fn key_line() -> (PublicKey, String) {
+        let key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519)
+            .unwrap()
+            .public_key()
+            .clone();
+        let line = key.to_openssh().unwrap();
+        // Incoming keys carry no comment, so strip it like a client would
+        (PublicKey::new(key.key_data().clone(), ""), line)
+    }
+
+    fn ip(addr: &str) -> Option<IpAddr> {
+        Some(addr.parse().unwrap())
+    }
+
+    #[test]
+    fn test_plain_key_with_comment() {
+        let (key, line) = key_line();
+        let keys = AuthorizedKeys::parse(&format!("# comment\n\n{line} alice@laptop\n"));
+        assert_eq!(keys.len(), 1);
+        assert!(keys.authorizes(&key, None));
+    }
+
+    #[test]
+    fn test_options_are_parsed() {
+        let (key, line) = key_line();
+        let keys = AuthorizedKeys::parse(&format!(
+            "from=\"10.0.0.0/8,!10.0.0.1\",restrict,command=\"internal-sftp -d /x\" {line}"
+        ));
+        assert_eq!(keys.len(), 1);
+        let options = &keys.entries()[0].options;
+        assert_eq!(
+            options.from.as_deref(),
+            Some(&["10.0.0.0/8".to_string(), "!10.0.0.1".to_string()][..])
+        );
+        assert_eq!(options.command.as_deref(), Some("internal-sftp -d /x"));
+
+        assert!(keys.authorizes(&key, ip("10.1.2.3")));
+        assert!(!keys.authorizes(&key, ip("10.0.0.1")));
+        assert!(!keys.authorizes(&key, ip("192.168.0.1")));
+        assert!(!keys.authorizes(&key, None));
+    }
+
+    #[test]
+    fn test_from_wildcards() {
+        let patterns = vec!["192.168.1.*".to_string(), "::1".to_string()];
+        assert!(match_from(&patterns, "192.168.1.20".parse().unwrap()));
+        assert!(match_from(&patterns, "::1".parse().unwrap()));
+        assert!(!match_from(&patterns, "192.168.2.20".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_unknown_option_skips_line() {
+        let (key, line) = key_line();
+        let keys = AuthorizedKeys::parse(&format!("bogus-option {line}"));
+        assert!(keys.is_empty());
+        assert!(!keys.authorizes(&key, None));
+    }
+
+    #[test]
+    fn test_shell_command_rejected() {
+        let (key, line) = key_line();
+        let keys = AuthorizedKeys::parse(&format!("command=\"/bin/bash\" {line}"));
+        assert_eq!(keys.len(), 1);
+        assert!(!keys.authorizes(&key, None));
+    }
+
+    #[test]
+    fn test_cert_authority_cannot_log_in() {
+        let (key, line) = key_line();
+        let keys = AuthorizedKeys::parse(&format!("cert-authority {line}"));
+        assert!(!keys.authorizes(&key, None));
+    }
+
+    #[test]
+    fn test_expiry_time() {
+        assert_eq!(parse_expiry_time("19700101"), Some(0));
+        assert_eq!(parse_expiry_time("20240229123045Z"), Some(1_709_209_845));
+        assert_eq!(parse_expiry_time("2024"), None);
+        assert_eq!(parse_expiry_time("20241301"), None);
+
+        let (key, line) = key_line();
+        let keys = AuthorizedKeys::parse(&format!("expiry-time=\"20240101\" {line}"));
+        let expiry = parse_expiry_time("20240101").unwrap();
+        assert!(keys.authorizes_at(&key, None, expiry - 1));
+        assert!(!keys.authorizes_at(&key, None, expiry));
+    }
+
+    #[test]
+    fn test_path_template() {
+        let file = AuthorizedKeysFile::new("/etc/sftp/keys/%u");
+        assert_eq!(
+            file.path_for("alice"),
+            Some(PathBuf::from("/etc/sftp/keys/alice"))
+        );
+        assert_eq!(file.path_for("../root"), None);
+        assert_eq!(file.path_for(".."), None);
+
+        let file = AuthorizedKeysFile::new("/keys/100%%/%u.pub");
+        assert_eq!(
+            file.path_for("bob"),
+            Some(PathBuf::from("/keys/100%/bob.pub"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_per_user_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let (alice_key, alice_line) = key_line();
+        let mut file = std::fs::File::create(dir.path().join("alice")).unwrap();
+        writeln!(file, "{alice_line}").unwrap();
+
+        let template = format!("{}/%u", dir.path().display());
+        let keys = AuthorizedKeysFile::new(template);
+        assert!(keys.check_publickey("alice", &alice_key).await);
+        assert!(!keys.check_publickey("bob", &alice_key).await);
+    }
+}
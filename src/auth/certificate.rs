@@ -0,0 +1,297 @@
+use super::is_sftp_command;
+use ipnet::IpNet;
+use russh::keys::ssh_key::certificate::CertType;
+use russh::keys::ssh_key::{Fingerprint, HashAlg};
+use russh::keys::{Certificate, PublicKey};
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Why a user certificate was not accepted
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum CertificateError {
+    #[error("not a user certificate")]
+    NotUserCertificate,
+    #[error("certificate is not signed by a trusted CA, has a bad signature or is outside its validity window")]
+    Invalid,
+    #[error("user is not among the certificate principals")]
+    PrincipalNotAllowed,
+    #[error("client address is not allowed by source-address")]
+    SourceAddressNotAllowed,
+    #[error("unsupported critical option: {0}")]
+    UnsupportedCriticalOption(String),
+}
+
+/// CA keys trusted to sign user certificates, like sshd's `TrustedUserCAKeys`
+#[derive(Debug, Clone, Default)]
+pub struct TrustedUserCaKeys {
+    keys: Vec<PublicKey>,
+    fingerprints: Vec<Fingerprint>,
+}
+
+impl TrustedUserCaKeys {
+    pub fn new(keys: Vec<PublicKey>) -> Self {
+        let fingerprints = keys
+            .iter()
+            .map(|k| k.fingerprint(HashAlg::Sha256))
+            .collect();
+        Self { keys, fingerprints }
+    }
+
+    /// Parse one OpenSSH public key per line, skipping blanks and comments
+    pub fn parse(contents: &str) -> io::Result<Self> {
+        let mut keys = Vec::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let key = PublicKey::from_openssh(line).map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", number + 1, err),
+                )
+            })?;
+            keys.push(key);
+        }
+        Ok(Self::new(keys))
+    }
+
+    /// Load CA keys from a file in the same format as sshd's `TrustedUserCAKeys`
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn keys(&self) -> &[PublicKey] {
+        &self.keys
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Check that `certificate` lets `user` log in from `peer`
+    ///
+    /// Checks the CA signature and validity window, requires `user` to be
+    /// listed in the principals (an empty list is rejected, as sshd does) and
+    /// enforces the `source-address` critical option. `force-command` is only
+    /// accepted when it names an SFTP server, since that is all we run.
+    pub fn verify(
+        &self,
+        user: &str,
+        certificate: &Certificate,
+        peer: Option<IpAddr>,
+    ) -> Result<(), CertificateError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        self.verify_at(user, certificate, peer, now)
+    }
+
+    fn verify_at(
+        &self,
+        user: &str,
+        certificate: &Certificate,
+        peer: Option<IpAddr>,
+        now: u64,
+    ) -> Result<(), CertificateError> {
+        if certificate.cert_type() != CertType::User {
+            return Err(CertificateError::NotUserCertificate);
+        }
+
+        certificate
+            .validate_at(now, &self.fingerprints)
+            .map_err(|_| CertificateError::Invalid)?;
+
+        if !certificate.valid_principals().iter().any(|p| p == user) {
+            return Err(CertificateError::PrincipalNotAllowed);
+        }
+
+        for (name, value) in certificate.critical_options().iter() {
+            match name.as_str() {
+                "source-address" => check_source_address(value, peer)?,
+                "force-command" if is_sftp_command(value) => {}
+                _ => return Err(CertificateError::UnsupportedCriticalOption(name.clone())),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Check `peer` against a comma-separated list of addresses and CIDR ranges
+fn check_source_address(allowed: &str, peer: Option<IpAddr>) -> Result<(), CertificateError> {
+    let Some(peer) = peer else {
+        return Err(CertificateError::SourceAddressNotAllowed);
+    };
+    let matches = allowed.split(',').map(str::trim).any(|entry| {
+        if let Ok(net) = entry.parse::<IpNet>() {
+            net.contains(&peer)
+        } else if let Ok(addr) = entry.parse::<IpAddr>() {
+            addr == peer
+        } else {
+            false
+        }
+    });
+    if matches {
+        Ok(())
+    } else {
+        Err(CertificateError::SourceAddressNotAllowed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use russh::keys::ssh_key::certificate::Builder;
+    use russh::keys::ssh_key::rand_core::OsRng;
+    use russh::keys::{Algorithm, PrivateKey};
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn key() -> PrivateKey {
+        PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap()
+    }
+
+    fn sign(
+        ca: &PrivateKey,
+        cert_type: CertType,
+        principals: &[&str],
+        options: &[(&str, &str)],
+    ) -> Certificate {
+        let user_key = key();
+        let mut builder = Builder::new(
+            [0u8; 16],
+            user_key.public_key().key_data().clone(),
+            NOW - 60,
+            NOW + 60,
+        )
+        .unwrap();
+        builder.cert_type(cert_type).unwrap();
+        builder.key_id("test").unwrap();
+        if principals.is_empty() {
+            builder.all_principals_valid().unwrap();
+        }
+        for principal in principals {
+            builder.valid_principal(*principal).unwrap();
+        }
+        for (name, value) in options {
+            builder.critical_option(*name, *value).unwrap();
+        }
+        builder.sign(ca).unwrap()
+    }
+
+    fn trusted(ca: &PrivateKey) -> TrustedUserCaKeys {
+        TrustedUserCaKeys::new(vec![ca.public_key().clone()])
+    }
+
+    #[test]
+    fn test_valid_certificate() {
+        let ca = key();
+        let cert = sign(&ca, CertType::User, &["alice"], &[]);
+        assert_eq!(trusted(&ca).verify_at("alice", &cert, None, NOW), Ok(()));
+    }
+
+    #[test]
+    fn test_untrusted_ca() {
+        let cert = sign(&key(), CertType::User, &["alice"], &[]);
+        let result = trusted(&key()).verify_at("alice", &cert, None, NOW);
+        assert_eq!(result, Err(CertificateError::Invalid));
+    }
+
+    #[test]
+    fn test_expired_certificate() {
+        let ca = key();
+        let cert = sign(&ca, CertType::User, &["alice"], &[]);
+        let result = trusted(&ca).verify_at("alice", &cert, None, NOW + 3600);
+        assert_eq!(result, Err(CertificateError::Invalid));
+    }
+
+    #[test]
+    fn test_principals() {
+        let ca = key();
+        let cert = sign(&ca, CertType::User, &["alice"], &[]);
+        let result = trusted(&ca).verify_at("bob", &cert, None, NOW);
+        assert_eq!(result, Err(CertificateError::PrincipalNotAllowed));
+
+        let cert = sign(&ca, CertType::User, &[], &[]);
+        let result = trusted(&ca).verify_at("alice", &cert, None, NOW);
+        assert_eq!(result, Err(CertificateError::PrincipalNotAllowed));
+    }
+
+    #[test]
+    fn test_host_certificate_rejected() {
+        let ca = key();
+        let cert = sign(&ca, CertType::Host, &["alice"], &[]);
+        let result = trusted(&ca).verify_at("alice", &cert, None, NOW);
+        assert_eq!(result, Err(CertificateError::NotUserCertificate));
+    }
+
+    #[test]
+    fn test_source_address() {
+        let ca = key();
+        let cert = sign(
+            &ca,
+            CertType::User,
+            &["alice"],
+            &[("source-address", "10.0.0.0/8,192.168.1.5")],
+        );
+        let cas = trusted(&ca);
+        assert!(cas
+            .verify_at("alice", &cert, Some("10.1.2.3".parse().unwrap()), NOW)
+            .is_ok());
+        assert!(cas
+            .verify_at("alice", &cert, Some("192.168.1.5".parse().unwrap()), NOW)
+            .is_ok());
+        assert_eq!(
+            cas.verify_at("alice", &cert, Some("192.168.1.6".parse().unwrap()), NOW),
+            Err(CertificateError::SourceAddressNotAllowed)
+        );
+    }
+
+    #[test]
+    fn test_critical_options() {
+        let ca = key();
+        let cas = trusted(&ca);
+        let cert = sign(
+            &ca,
+            CertType::User,
+            &["alice"],
+            &[("force-command", "internal-sftp")],
+        );
+        assert!(cas.verify_at("alice", &cert, None, NOW).is_ok());
+
+        let cert = sign(
+            &ca,
+            CertType::User,
+            &["alice"],
+            &[("force-command", "/bin/sh")],
+        );
+        assert!(cas.verify_at("alice", &cert, None, NOW).is_err());
+
+        let cert = sign(&ca, CertType::User, &["alice"], &[("verify-required", "")]);
+        assert_eq!(
+            cas.verify_at("alice", &cert, None, NOW),
+            Err(CertificateError::UnsupportedCriticalOption(
+                "verify-required".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_ca_file() {
+        let ca = key();
+        let line = ca.public_key().to_openssh().unwrap();
+        let contents = format!("# CAs\n\n{line}\n");
+        let cas = TrustedUserCaKeys::parse(&contents).unwrap();
+        assert_eq!(cas.len(), 1);
+
+        assert!(TrustedUserCaKeys::parse("not a key").is_err());
+    }
+}
@@ -0,0 +1,165 @@
+use async_trait::async_trait;
+use russh::server::Auth;
+use std::borrow::Cow;
+
+/// A single prompt shown to the user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prompt {
+    pub text: String,
+    /// Whether the client should echo what the user types
+    pub echo: bool,
+}
+
+impl Prompt {
+    /// Prompt whose answer is echoed, e.g. a username
+    pub fn visible(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            echo: true,
+        }
+    }
+
+    /// Prompt whose answer is hidden, e.g. a password or one-time code
+    pub fn hidden(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            echo: false,
+        }
+    }
+}
+
+/// One round of keyboard-interactive prompts
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Challenge {
+    pub name: String,
+    pub instructions: String,
+    pub prompts: Vec<Prompt>,
+}
+
+impl Challenge {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_instructions(mut self, instructions: impl Into<String>) -> Self {
+        self.instructions = instructions.into();
+        self
+    }
+
+    pub fn with_prompt(mut self, prompt: Prompt) -> Self {
+        self.prompts.push(prompt);
+        self
+    }
+
+    /// Convert into the russh response that sends these prompts
+    pub(crate) fn into_auth(self) -> Auth {
+        Auth::Partial {
+            name: Cow::Owned(self.name),
+            instructions: Cow::Owned(self.instructions),
+            prompts: Cow::Owned(
+                self.prompts
+                    .into_iter()
+                    .map(|p| (Cow::Owned(p.text), p.echo))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Outcome of checking the answers to a challenge
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChallengeResult {
+    Accept,
+    Reject,
+    /// Ask another round of questions
+    Continue(Challenge),
+}
+
+/// Drives keyboard-interactive authentication
+///
+/// The session remembers the last challenge it sent, so handlers can stay
+/// stateless and tell rounds apart by [`Challenge::name`].
+#[async_trait]
+pub trait ChallengeHandler: Send + Sync + 'static {
+    /// First challenge for `user`, or `None` to reject immediately
+    async fn begin(&self, user: &str) -> Option<Challenge>;
+
+    /// Check the answers to `challenge`, one per prompt in order
+    async fn respond(
+        &self,
+        user: &str,
+        challenge: &Challenge,
+        responses: &[String],
+    ) -> ChallengeResult;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Asks for a PIN, then a one-time code
+    struct TwoStep;
+
+    #[async_trait]
+    impl ChallengeHandler for TwoStep {
+        async fn begin(&self, _user: &str) -> Option<Challenge> {
+            Some(Challenge::new("pin").with_prompt(Prompt::hidden("PIN: ")))
+        }
+
+        async fn respond(
+            &self,
+            _user: &str,
+            challenge: &Challenge,
+            responses: &[String],
+        ) -> ChallengeResult {
+            match (challenge.name.as_str(), responses) {
+                ("pin", [pin]) if pin == "1234" => ChallengeResult::Continue(
+                    Challenge::new("otp").with_prompt(Prompt::hidden("Code: ")),
+                ),
+                ("otp", [code]) if code == "000000" => ChallengeResult::Accept,
+                _ => ChallengeResult::Reject,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_multi_round_challenge() {
+        let handler = TwoStep;
+        let pin = handler.begin("alice").await.unwrap();
+
+        let otp = match handler.respond("alice", &pin, &["1234".into()]).await {
+            ChallengeResult::Continue(next) => next,
+            other => panic!("unexpected {other:?}"),
+        };
+        assert_eq!(otp.name, "otp");
+
+        let result = handler.respond("alice", &otp, &["000000".into()]).await;
+        assert_eq!(result, ChallengeResult::Accept);
+
+        let result = handler.respond("alice", &pin, &["0000".into()]).await;
+        assert_eq!(result, ChallengeResult::Reject);
+    }
+
+    #[test]
+    fn test_challenge_into_auth() {
+        let auth = Challenge::new("otp")
+            .with_instructions("Enter your code")
+            .with_prompt(Prompt::hidden("Code: "))
+            .into_auth();
+
+        let Auth::Partial {
+            name,
+            instructions,
+            prompts,
+        } = auth
+        else {
+            panic!("expected partial auth");
+        };
+        assert_eq!(name, "otp");
+        assert_eq!(instructions, "Enter your code");
+        assert_eq!(prompts.as_ref(), &[(Cow::Borrowed("Code: "), false)]);
+    }
+}
@@ -0,0 +1,210 @@
+pub mod authorized_keys;
+pub mod certificate;
+pub mod keyboard;
+pub mod password;
+
+pub use authorized_keys::{AuthorizedKey, AuthorizedKeys, AuthorizedKeysFile, KeyOptions};
+pub use certificate::{CertificateError, TrustedUserCaKeys};
+pub use keyboard::{Challenge, ChallengeHandler, ChallengeResult, Prompt};
+pub use password::PasswordFile;
+
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use russh::keys::{Certificate, PublicKey};
+use russh::MethodSet;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+/// Password authentication callback type
+pub type PasswordAuthCallback = Arc<dyn Fn(&str, &str) -> bool + Send + Sync>;
+
+/// Public key authentication callback type
+/// Returns true if the given public key is authorized for the user
+pub type PubkeyAuthCallback = Arc<dyn Fn(&str, &PublicKey) -> bool + Send + Sync>;
+
+/// Async password authentication callback type
+pub type AsyncPasswordAuthCallback =
+    Arc<dyn Fn(String, String) -> BoxFuture<'static, bool> + Send + Sync>;
+
+/// Async public key authentication callback type
+pub type AsyncPubkeyAuthCallback =
+    Arc<dyn Fn(String, PublicKey) -> BoxFuture<'static, bool> + Send + Sync>;
+
+/// Checks user credentials
+///
+/// Implement this to authenticate against a database or remote service
+/// without blocking the runtime. Methods default to rejecting, so an
+/// implementation only needs the ones it supports.
+#[async_trait]
+pub trait Authenticator: Send + Sync + 'static {
+    /// Returns true if `password` is valid for `user`
+    async fn check_password(&self, _user: &str, _password: &str) -> bool {
+        false
+    }
+
+    /// Returns true if `key` is authorized for `user`
+    async fn check_publickey(&self, _user: &str, _key: &PublicKey) -> bool {
+        false
+    }
+
+    /// Like [`Authenticator::check_publickey`], with the client address for
+    /// source restrictions. Defaults to ignoring the address.
+    async fn check_publickey_from(
+        &self,
+        user: &str,
+        key: &PublicKey,
+        _peer: Option<IpAddr>,
+    ) -> bool {
+        self.check_publickey(user, key).await
+    }
+}
+
+#[async_trait]
+impl Authenticator for PasswordAuthCallback {
+    async fn check_password(&self, user: &str, password: &str) -> bool {
+        self(user, password)
+    }
+}
+
+#[async_trait]
+impl Authenticator for PubkeyAuthCallback {
+    async fn check_publickey(&self, user: &str, key: &PublicKey) -> bool {
+        self(user, key)
+    }
+}
+
+#[async_trait]
+impl Authenticator for AsyncPasswordAuthCallback {
+    async fn check_password(&self, user: &str, password: &str) -> bool {
+        self(user.to_string(), password.to_string()).await
+    }
+}
+
+#[async_trait]
+impl Authenticator for AsyncPubkeyAuthCallback {
+    async fn check_publickey(&self, user: &str, key: &PublicKey) -> bool {
+        self(user.to_string(), key.clone()).await
+    }
+}
+
+/// Whether a forced command would just start SFTP, which is all we serve
+pub(crate) fn is_sftp_command(command: &str) -> bool {
+    let program = command.split_whitespace().next().unwrap_or_default();
+    program == "internal-sftp" || program == "sftp-server" || program.ends_with("/sftp-server")
+}
+
+/// Box an async password closure into an [`AsyncPasswordAuthCallback`]
+pub fn async_password_callback<F, Fut>(callback: F) -> AsyncPasswordAuthCallback
+where
+    F: Fn(String, String) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = bool> + Send + 'static,
+{
+    Arc::new(move |user, password| Box::pin(callback(user, password)))
+}
+
+/// Box an async public key closure into an [`AsyncPubkeyAuthCallback`]
+pub fn async_pubkey_callback<F, Fut>(callback: F) -> AsyncPubkeyAuthCallback
+where
+    F: Fn(String, PublicKey) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = bool> + Send + 'static,
+{
+    Arc::new(move |user, key| Box::pin(callback(user, key)))
+}
+
+/// How a user proved their identity
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthMethod {
+    Password,
+    PublicKey(PublicKey),
+    /// OpenSSH user certificate signed by a trusted CA
+    Certificate(Box<Certificate>),
+    KeyboardInteractive,
+}
+
+impl AuthMethod {
+    /// The SSH method this corresponds to
+    pub fn method_set(&self) -> MethodSet {
+        match self {
+            Self::Password => MethodSet::PASSWORD,
+            Self::PublicKey(_) | Self::Certificate(_) => MethodSet::PUBLICKEY,
+            Self::KeyboardInteractive => MethodSet::KEYBOARD_INTERACTIVE,
+        }
+    }
+
+    /// Short name for logs, e.g. `"publickey"`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Password => "password",
+            Self::PublicKey(_) => "publickey",
+            Self::Certificate(_) => "certificate",
+            Self::KeyboardInteractive => "keyboard-interactive",
+        }
+    }
+}
+
+/// Details about a successful login, passed to per-user hooks
+#[derive(Debug, Clone)]
+pub struct AuthContext {
+    /// Authenticated username
+    pub user: String,
+    /// Remote address of the client, if known
+    pub peer_addr: Option<SocketAddr>,
+    /// Method that authenticated the user
+    pub method: AuthMethod,
+}
+
+impl AuthContext {
+    pub fn new(user: impl Into<String>, peer_addr: Option<SocketAddr>, method: AuthMethod) -> Self {
+        Self {
+            user: user.into(),
+            peer_addr,
+            method,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use russh::keys::ssh_key::rand_core::OsRng;
+    use russh::keys::{Algorithm, PrivateKey};
+
+    fn random_key() -> PublicKey {
+        PrivateKey::random(&mut OsRng, Algorithm::Ed25519)
+            .unwrap()
+            .public_key()
+            .clone()
+    }
+
+    #[tokio::test]
+    async fn test_sync_password_adapter() {
+        let callback: PasswordAuthCallback = Arc::new(|user, pass| user == "alice" && pass == "pw");
+        assert!(callback.check_password("alice", "pw").await);
+        assert!(!callback.check_password("alice", "wrong").await);
+        // Not a public key authenticator
+        assert!(!callback.check_publickey("alice", &random_key()).await);
+    }
+
+    #[tokio::test]
+    async fn test_async_password_adapter() {
+        let callback = async_password_callback(|user, pass| async move {
+            tokio::task::yield_now().await;
+            user == "alice" && pass == "pw"
+        });
+        assert!(callback.check_password("alice", "pw").await);
+        assert!(!callback.check_password("bob", "pw").await);
+    }
+
+    #[tokio::test]
+    async fn test_async_pubkey_adapter() {
+        let allowed = random_key();
+        let expected = allowed.clone();
+        let callback = async_pubkey_callback(move |_user, key| {
+            let expected = expected.clone();
+            async move { key == expected }
+        });
+        assert!(callback.check_publickey("alice", &allowed).await);
+        assert!(!callback.check_publickey("alice", &random_key()).await);
+    }
+}
@@ -0,0 +1,278 @@
+use super::Authenticator;
+use argon2::password_hash::PasswordHash;
+use argon2::{Argon2, PasswordVerifier};
+use async_trait::async_trait;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+use tracing::{info, warn};
+
+/// bcrypt hash, at the default cost, checked in place of unknown users
+///
+/// Verifying it takes as long as a real check, so response times don't reveal
+/// which usernames exist.
+const DUMMY_HASH: &str = "$2b$12$6JGtLcJQ0/ojTu13OrtiPe0hQ8bewZhbv9lzLQAkC0W5RnqM.PyCm";
+
+/// Supported password hash formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HashFormat {
+    Bcrypt,
+    Argon2id,
+    Sha512Crypt,
+}
+
+impl HashFormat {
+    fn detect(hash: &str) -> Option<Self> {
+        if ["$2a$", "$2b$", "$2x$", "$2y$"]
+            .iter()
+            .any(|p| hash.starts_with(p))
+        {
+            Some(Self::Bcrypt)
+        } else if hash.starts_with("$argon2id$") {
+            Some(Self::Argon2id)
+        } else if hash.starts_with("$6$") {
+            Some(Self::Sha512Crypt)
+        } else {
+            None
+        }
+    }
+}
+
+/// Check `password` against a stored hash in any supported format
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    match HashFormat::detect(hash) {
+        // bcrypt and argon2 compare the derived hashes in constant time
+        Some(HashFormat::Bcrypt) => bcrypt::verify(password, hash).unwrap_or(false),
+        Some(HashFormat::Argon2id) => PasswordHash::new(hash)
+            .map(|parsed| {
+                Argon2::default()
+                    .verify_password(password.as_bytes(), &parsed)
+                    .is_ok()
+            })
+            .unwrap_or(false),
+        Some(HashFormat::Sha512Crypt) => pwhash::sha512_crypt::hash_with(hash, password)
+            .map(|computed| computed.as_bytes().ct_eq(hash.as_bytes()).into())
+            .unwrap_or(false),
+        None => false,
+    }
+}
+
+/// Check `password` against a user's stored hash, `None` for unknown users
+///
+/// Unknown users are checked against a dummy hash and always fail. Hashing is
+/// deliberately slow, so it runs off the async workers.
+pub(crate) async fn check_user_password(hash: Option<String>, password: &str) -> bool {
+    let known = hash.is_some();
+    let hash = hash.unwrap_or_else(|| DUMMY_HASH.to_string());
+    let password = password.to_string();
+    let matches = tokio::task::spawn_blocking(move || verify_password(&password, &hash))
+        .await
+        .unwrap_or(false);
+    known && matches
+}
+
+/// Whether `hash` is in a format [`verify_password`] understands
+pub(crate) fn is_supported_hash(hash: &str) -> bool {
+    HashFormat::detect(hash).is_some()
+}
+
+/// Parse htpasswd-style `user:hash` lines
+///
+/// Blank lines and `#` comments are skipped. Entries with an unsupported hash
+/// format are skipped with a warning so one bad line cannot lock out everyone.
+fn parse(contents: &str) -> HashMap<String, String> {
+    let mut entries = HashMap::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((user, hash)) = line.split_once(':') else {
+            warn!(line = number + 1, "Skipping password entry without ':'");
+            continue;
+        };
+        if HashFormat::detect(hash).is_none() {
+            warn!(
+                line = number + 1,
+                user, "Skipping password entry with unsupported hash format"
+            );
+            continue;
+        }
+        entries.insert(user.to_string(), hash.to_string());
+    }
+    entries
+}
+
+/// Password hashes loaded from an htpasswd-style file
+///
+/// Each line is `user:hash`, where the hash is bcrypt (`$2b$...`), argon2id
+/// (`$argon2id$...`) or sha512-crypt (`$6$...`). Call [`PasswordFile::reload`]
+/// to pick up changes; the binary does this on SIGHUP.
+pub struct PasswordFile {
+    path: PathBuf,
+    entries: RwLock<HashMap<String, String>>,
+}
+
+impl PasswordFile {
+    /// Load a password file from `path`
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let entries = parse(&std::fs::read_to_string(&path)?);
+        info!(path = %path.display(), users = entries.len(), "Loaded password file");
+        Ok(Self {
+            path,
+            entries: RwLock::new(entries),
+        })
+    }
+
+    /// Path the file was loaded from
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Number of users with a usable hash
+    pub fn len(&self) -> usize {
+        self.entries.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.read().is_empty()
+    }
+
+    /// Re-read the file, keeping the old entries if it cannot be read
+    pub fn reload(&self) -> io::Result<()> {
+        let entries = parse(&std::fs::read_to_string(&self.path)?);
+        info!(path = %self.path.display(), users = entries.len(), "Reloaded password file");
+        *self.entries.write() = entries;
+        Ok(())
+    }
+
+    /// Check `password` for `user`
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        let hash = self.entries.read().get(user).cloned();
+        match hash {
+            Some(hash) => verify_password(password, &hash),
+            None => false,
+        }
+    }
+
+    /// Reload the file whenever the process receives SIGHUP
+    ///
+    /// Must be called from within a tokio runtime. A no-op on non-Unix
+    /// platforms.
+    pub fn reload_on_sighup(self: &Arc<Self>) {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            let mut hangups = match signal(SignalKind::hangup()) {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!(error = %err, "Could not install SIGHUP handler");
+                    return;
+                }
+            };
+            let file = Arc::downgrade(self);
+            tokio::spawn(async move {
+                while hangups.recv().await.is_some() {
+                    let Some(file) = file.upgrade() else {
+                        break;
+                    };
+                    if let Err(err) = file.reload() {
+                        warn!(path = %file.path.display(), error = %err, "Could not reload password file");
+                    }
+                }
+            });
+        }
+    }
+}
+
+#[async_trait]
+impl Authenticator for PasswordFile {
+    async fn check_password(&self, user: &str, password: &str) -> bool {
+        let hash = self.entries.read().get(user).cloned();
+        check_user_password(hash, password).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use argon2::password_hash::{PasswordHasher, SaltString};
+    use std::io::Write;
+
+    fn argon2_hash(password: &str) -> String {
+        let salt = SaltString::encode_b64(b"0123456789abcdef").unwrap();
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_verify_bcrypt() {
+        let hash = bcrypt::hash("secret", 4).unwrap();
+        assert!(verify_password("secret", &hash));
+        assert!(!verify_password("wrong", &hash));
+    }
+
+    #[test]
+    fn test_verify_argon2id() {
+        let hash = argon2_hash("secret");
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password("secret", &hash));
+        assert!(!verify_password("wrong", &hash));
+    }
+
+    #[test]
+    fn test_verify_sha512_crypt() {
+        let hash = pwhash::sha512_crypt::hash("secret").unwrap();
+        assert!(verify_password("secret", &hash));
+        assert!(!verify_password("wrong", &hash));
+    }
+
+    #[test]
+    fn test_plaintext_is_rejected() {
+        assert!(!verify_password("secret", "secret"));
+        assert!(parse("alice:secret").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_user_checks_dummy_hash() {
+        // The dummy is a real hash, so unknown users cost a full check
+        assert!(verify_password("unused", DUMMY_HASH));
+        assert!(!check_user_password(None, "unused").await);
+        let hash = bcrypt::hash("secret", 4).unwrap();
+        assert!(check_user_password(Some(hash), "secret").await);
+    }
+
+    #[test]
+    fn test_parse_skips_comments_and_bad_lines() {
+        let bcrypt = bcrypt::hash("pw", 4).unwrap();
+        let contents = format!("# users\n\nalice:{bcrypt}\nbroken line\nbob:$1$md5\n");
+        let entries = parse(&contents);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries.get("alice"), Some(&bcrypt));
+    }
+
+    #[tokio::test]
+    async fn test_password_file_reload() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "alice:{}", bcrypt::hash("old", 4).unwrap()).unwrap();
+
+        let passwords = PasswordFile::load(file.path()).unwrap();
+        assert!(passwords.check_password("alice", "old").await);
+        assert!(!passwords.check_password("bob", "old").await);
+
+        let mut file = file.reopen().unwrap();
+        file.set_len(0).unwrap();
+        writeln!(file, "alice:{}", pwhash::sha512_crypt::hash("new").unwrap()).unwrap();
+        passwords.reload().unwrap();
+
+        assert!(!passwords.check_password("alice", "old").await);
+        assert!(passwords.check_password("alice", "new").await);
+    }
+}
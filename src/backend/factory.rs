@@ -0,0 +1,86 @@
+use super::{Backend, BackendResult};
+use crate::auth::AuthContext;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// Resolves the backend a user is served from after they log in
+///
+/// Lets one server route users to different storage, e.g. some to S3 and
+/// others to local disk. Called once per SFTP session.
+#[async_trait]
+pub trait BackendFactory: Send + Sync + 'static {
+    async fn backend(&self, user: &str, context: &AuthContext) -> BackendResult<Arc<dyn Backend>>;
+}
+
+/// Factory that serves every user from the same backend
+pub struct SharedBackend(Arc<dyn Backend>);
+
+impl SharedBackend {
+    pub fn new(backend: Arc<dyn Backend>) -> Self {
+        Self(backend)
+    }
+}
+
+#[async_trait]
+impl BackendFactory for SharedBackend {
+    async fn backend(
+        &self,
+        _user: &str,
+        _context: &AuthContext,
+    ) -> BackendResult<Arc<dyn Backend>> {
+        Ok(self.0.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::AuthMethod;
+    use crate::backend::{BackendError, MemoryBackend};
+    use bytes::Bytes;
+
+    struct PerUser {
+        alice: Arc<dyn Backend>,
+    }
+
+    #[async_trait]
+    impl BackendFactory for PerUser {
+        async fn backend(
+            &self,
+            user: &str,
+            _context: &AuthContext,
+        ) -> BackendResult<Arc<dyn Backend>> {
+            match user {
+                "alice" => Ok(self.alice.clone()),
+                _ => Err(BackendError::PermissionDenied),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shared_backend_returns_same_backend() {
+        let inner: Arc<dyn Backend> = Arc::new(MemoryBackend::new());
+        let factory = SharedBackend::new(inner.clone());
+        let context = AuthContext::new("bob", None, AuthMethod::Password);
+
+        let backend = factory.backend("bob", &context).await.unwrap();
+        backend
+            .write_file("a.txt", Bytes::from_static(b"data"))
+            .await
+            .unwrap();
+        assert!(inner.file_info("a.txt").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_factory_can_route_by_user() {
+        let factory = PerUser {
+            alice: Arc::new(MemoryBackend::new()),
+        };
+        let context = AuthContext::new("alice", None, AuthMethod::Password);
+        assert!(factory.backend("alice", &context).await.is_ok());
+
+        let context = AuthContext::new("mallory", None, AuthMethod::Password);
+        let result = factory.backend("mallory", &context).await;
+        assert!(matches!(result, Err(BackendError::PermissionDenied)));
+    }
+}
@@ -1,29 +1,129 @@
-use super::{normalize_path, Backend, BackendError, BackendResult, DirEntry, FileInfo};
+use super::{
+    resolve_path, AttrCapabilities, Backend, BackendError, BackendResult, DirEntry, FileInfo,
+    FileType, SetAttrs, SpaceInfo, MAX_SYMLINK_HOPS,
+};
 use async_trait::async_trait;
 use bytes::Bytes;
-use std::path::{Path, PathBuf};
+use std::io::SeekFrom;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
 use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tracing::debug;
 
 /// Local filesystem storage backend
+///
+/// Every path is confined to the root directory: `..` components are resolved
+/// lexically and may not climb above the root, and the resolved location is
+/// canonicalized so symlinks cannot point outside of it either. Escapes are
+/// reported as `BackendError::PermissionDenied`.
+///
+/// Symlinks created through the backend must point inside the root too.
+/// Absolute targets are stored relative to the root on disk and read back
+/// as paths from the root.
+///
+/// Modes are limited to the permission bits, and clients may only change
+/// owners when enabled with [`LocalBackend::with_ownership`].
 pub struct LocalBackend {
     root: PathBuf,
+    /// Root with symlinks resolved, used for confinement checks
+    canonical_root: PathBuf,
+    /// Whether clients may change uid and gid
+    ownership: bool,
 }
 
 impl LocalBackend {
     /// Create a new local backend rooted at the given path
     pub fn new(root: impl AsRef<Path>) -> Self {
+        let root = root.as_ref().to_path_buf();
+        let canonical_root = std::fs::canonicalize(&root).unwrap_or_else(|_| root.clone());
         Self {
-            root: root.as_ref().to_path_buf(),
+            root,
+            canonical_root,
+            ownership: false,
         }
     }
 
-    /// Get the full filesystem path for a normalized SFTP path
-    fn full_path(&self, path: &str) -> PathBuf {
-        if path.is_empty() {
-            self.root.clone()
+    /// Let clients change the owner and group of files
+    ///
+    /// Off by default, since the server's own user (often root) would apply
+    /// whatever ids the client sends.
+    pub fn with_ownership(mut self, enabled: bool) -> Self {
+        self.ownership = enabled;
+        self
+    }
+
+    /// Get the full filesystem path for an SFTP path, confined to the root
+    async fn full_path(&self, path: &str) -> BackendResult<PathBuf> {
+        let resolved = resolve_path(path).ok_or(BackendError::PermissionDenied)?;
+
+        let mut full_path = self.root.clone();
+        for component in Path::new(&resolved).components() {
+            match component {
+                Component::Normal(name) => full_path.push(name),
+                // Drive prefixes and the like on non-Unix platforms
+                _ => return Err(BackendError::PermissionDenied),
+            }
+        }
+
+        self.check_beneath_root(&full_path).await?;
+        Ok(full_path)
+    }
+
+    /// Get the filesystem path of `path` itself, for operations that must not
+    /// follow a final symlink
+    ///
+    /// Only the parent directory is confined; the last component is used as is.
+    async fn link_path(&self, path: &str) -> BackendResult<PathBuf> {
+        let resolved = resolve_path(path).ok_or(BackendError::PermissionDenied)?;
+        match resolved.rsplit_once('/') {
+            Some((parent, name)) => Ok(self.full_path(parent).await?.join(name)),
+            None if resolved.is_empty() => self.full_path(&resolved).await,
+            None => Ok(self.full_path("").await?.join(&resolved)),
+        }
+    }
+
+    /// Ensure `path` resolves to a location inside the root after following symlinks
+    ///
+    /// Walks up to the deepest existing ancestor and canonicalizes it. Dangling
+    /// symlinks are followed by hand, since creating a file through one would
+    /// land wherever it points.
+    async fn check_beneath_root(&self, path: &Path) -> BackendResult<()> {
+        let mut candidate = path.to_path_buf();
+        let mut hops = 0;
+
+        let resolved = loop {
+            match fs::canonicalize(&candidate).await {
+                Ok(resolved) => break resolved,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => return Err(Self::map_io_error(err)),
+            }
+
+            let parent = candidate
+                .parent()
+                .ok_or(BackendError::PermissionDenied)?
+                .to_path_buf();
+
+            candidate = match fs::read_link(&candidate).await {
+                Ok(target) => {
+                    hops += 1;
+                    if hops > MAX_SYMLINK_HOPS {
+                        return Err(BackendError::PermissionDenied);
+                    }
+                    let parent = fs::canonicalize(&parent)
+                        .await
+                        .map_err(Self::map_io_error)?;
+                    normalize_lexically(&parent.join(target))
+                }
+                Err(_) => parent,
+            };
+        };
+
+        if resolved.starts_with(&self.canonical_root) {
+            Ok(())
         } else {
-            self.root.join(path)
+            debug!(path = %path.display(), resolved = %resolved.display(), "Path escapes root");
+            Err(BackendError::PermissionDenied)
         }
     }
 
@@ -70,9 +170,18 @@ impl LocalBackend {
             }
         };
 
+        let file_type = if metadata.is_symlink() {
+            FileType::Symlink
+        } else if metadata.is_dir() {
+            FileType::Dir
+        } else {
+            FileType::File
+        };
+
         FileInfo {
             size: metadata.len(),
             is_dir: metadata.is_dir(),
+            file_type,
             permissions,
             mtime,
             atime,
@@ -82,11 +191,95 @@ impl LocalBackend {
     }
 }
 
+/// Apply `attrs` to `path`, blocking
+///
+/// Ownership and mode change before times, so a chown can't clobber a
+/// freshly set mtime on filesystems that update it.
+fn apply_attrs(path: &Path, attrs: SetAttrs) -> std::io::Result<()> {
+    if let Some(size) = attrs.size {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(size)?;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        if attrs.uid.is_some() || attrs.gid.is_some() {
+            std::os::unix::fs::chown(path, attrs.uid, attrs.gid)?;
+        }
+        if let Some(mode) = attrs.permissions {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o777))?;
+        }
+    }
+    if attrs.atime.is_some() || attrs.mtime.is_some() {
+        let at = |secs: u32| UNIX_EPOCH + Duration::from_secs(secs as u64);
+        let mut times = std::fs::FileTimes::new();
+        if let Some(atime) = attrs.atime {
+            times = times.set_accessed(at(atime));
+        }
+        if let Some(mtime) = attrs.mtime {
+            times = times.set_modified(at(mtime));
+        }
+        std::fs::File::open(path)?.set_times(times)?;
+    }
+    Ok(())
+}
+
+/// Copy `src` over `dst`, blocking
+///
+/// Tries a reflink first, so copy-on-write filesystems share the data instead
+/// of duplicating it. Otherwise `std::fs::copy` lets the kernel copy where it
+/// can.
+fn copy_file(src: &Path, dst: &Path) -> std::io::Result<()> {
+    let source = std::fs::File::open(src)?;
+    let metadata = source.metadata()?;
+    if metadata.is_dir() {
+        return Err(std::io::ErrorKind::IsADirectory.into());
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        // Opening the destination for writing would truncate the source
+        let same = |to: std::fs::Metadata| to.dev() == metadata.dev() && to.ino() == metadata.ino();
+        if std::fs::metadata(dst).is_ok_and(same) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "source and destination are the same file",
+            ));
+        }
+    }
+    #[cfg(target_os = "linux")]
+    {
+        let dest = std::fs::File::create(dst)?;
+        if rustix::fs::ioctl_ficlone(&dest, &source).is_ok() {
+            return Ok(());
+        }
+    }
+    std::fs::copy(src, dst).map(|_| ())
+}
+
+/// Resolve `.` and `..` components of an absolute path without touching the filesystem
+fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::CurDir => {}
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
 #[async_trait]
 impl Backend for LocalBackend {
     async fn list_dir(&self, path: &str) -> BackendResult<Vec<DirEntry>> {
-        let normalized = normalize_path(path);
-        let full_path = self.full_path(&normalized);
+        let full_path = self.full_path(path).await?;
 
         debug!(path = %full_path.display(), "Listing directory");
 
@@ -115,8 +308,7 @@ impl Backend for LocalBackend {
     }
 
     async fn file_info(&self, path: &str) -> BackendResult<FileInfo> {
-        let normalized = normalize_path(path);
-        let full_path = self.full_path(&normalized);
+        let full_path = self.full_path(path).await?;
 
         debug!(path = %full_path.display(), "Getting file info");
 
@@ -125,8 +317,7 @@ impl Backend for LocalBackend {
     }
 
     async fn make_dir(&self, path: &str) -> BackendResult<()> {
-        let normalized = normalize_path(path);
-        let full_path = self.full_path(&normalized);
+        let full_path = self.full_path(path).await?;
 
         debug!(path = %full_path.display(), "Creating directory");
 
@@ -134,8 +325,7 @@ impl Backend for LocalBackend {
     }
 
     async fn del_dir(&self, path: &str) -> BackendResult<()> {
-        let normalized = normalize_path(path);
-        let full_path = self.full_path(&normalized);
+        let full_path = self.full_path(path).await?;
 
         debug!(path = %full_path.display(), "Removing directory");
 
@@ -143,8 +333,8 @@ impl Backend for LocalBackend {
     }
 
     async fn delete(&self, path: &str) -> BackendResult<()> {
-        let normalized = normalize_path(path);
-        let full_path = self.full_path(&normalized);
+        // Removes a symlink itself, wherever it points
+        let full_path = self.link_path(path).await?;
 
         debug!(path = %full_path.display(), "Deleting file");
 
@@ -154,8 +344,8 @@ impl Backend for LocalBackend {
     }
 
     async fn rename(&self, src: &str, dst: &str) -> BackendResult<()> {
-        let src_path = self.full_path(&normalize_path(src));
-        let dst_path = self.full_path(&normalize_path(dst));
+        let src_path = self.link_path(src).await?;
+        let dst_path = self.link_path(dst).await?;
 
         debug!(from = %src_path.display(), to = %dst_path.display(), "Renaming");
 
@@ -164,9 +354,13 @@ impl Backend for LocalBackend {
             .map_err(Self::map_io_error)
     }
 
+    /// rename(2) swaps the target in one step
+    fn atomic_rename(&self) -> bool {
+        true
+    }
+
     async fn read_file(&self, path: &str) -> BackendResult<Bytes> {
-        let normalized = normalize_path(path);
-        let full_path = self.full_path(&normalized);
+        let full_path = self.full_path(path).await?;
 
         debug!(path = %full_path.display(), "Reading file");
 
@@ -174,9 +368,28 @@ impl Backend for LocalBackend {
         Ok(Bytes::from(content))
     }
 
+    async fn read_range(&self, path: &str, offset: u64, len: u32) -> BackendResult<Bytes> {
+        let full_path = self.full_path(path).await?;
+
+        debug!(path = %full_path.display(), offset, len, "Reading file range");
+
+        let mut file = fs::File::open(&full_path)
+            .await
+            .map_err(Self::map_io_error)?;
+        file.seek(SeekFrom::Start(offset))
+            .await
+            .map_err(Self::map_io_error)?;
+
+        let mut buf = Vec::with_capacity(len as usize);
+        file.take(len as u64)
+            .read_to_end(&mut buf)
+            .await
+            .map_err(Self::map_io_error)?;
+        Ok(Bytes::from(buf))
+    }
+
     async fn write_file(&self, path: &str, content: Bytes) -> BackendResult<()> {
-        let normalized = normalize_path(path);
-        let full_path = self.full_path(&normalized);
+        let full_path = self.full_path(path).await?;
 
         debug!(path = %full_path.display(), len = content.len(), "Writing file");
 
@@ -184,6 +397,135 @@ impl Backend for LocalBackend {
             .await
             .map_err(Self::map_io_error)
     }
+
+    fn attr_capabilities(&self) -> AttrCapabilities {
+        if cfg!(unix) {
+            AttrCapabilities {
+                ownership: self.ownership,
+                ..AttrCapabilities::ALL
+            }
+        } else {
+            AttrCapabilities {
+                size: true,
+                times: true,
+                ..AttrCapabilities::NONE
+            }
+        }
+    }
+
+    async fn set_attrs(&self, path: &str, attrs: SetAttrs) -> BackendResult<()> {
+        let full_path = self.full_path(path).await?;
+
+        debug!(path = %full_path.display(), ?attrs, "Setting attributes");
+
+        tokio::task::spawn_blocking(move || apply_attrs(&full_path, attrs))
+            .await
+            .map_err(|e| BackendError::Io(e.to_string()))?
+            .map_err(Self::map_io_error)
+    }
+
+    async fn symlink_info(&self, path: &str) -> BackendResult<FileInfo> {
+        let full_path = self.link_path(path).await?;
+
+        debug!(path = %full_path.display(), "Getting link info");
+
+        let metadata = fs::symlink_metadata(&full_path)
+            .await
+            .map_err(Self::map_io_error)?;
+        Ok(Self::metadata_to_info(&metadata))
+    }
+
+    async fn read_link(&self, path: &str) -> BackendResult<String> {
+        let full_path = self.link_path(path).await?;
+
+        debug!(path = %full_path.display(), "Reading link");
+
+        let target = match fs::read_link(&full_path).await {
+            Ok(target) => target,
+            Err(err) if err.kind() == std::io::ErrorKind::InvalidInput => {
+                return Err(BackendError::NotASymlink)
+            }
+            Err(err) => return Err(Self::map_io_error(err)),
+        };
+        if target.is_relative() {
+            return Ok(target.to_string_lossy().into_owned());
+        }
+
+        // Show absolute targets as paths from the root, and don't reveal
+        // where links placed outside of this server point
+        let within = target
+            .strip_prefix(&self.canonical_root)
+            .or_else(|_| target.strip_prefix(&self.root))
+            .map_err(|_| BackendError::PermissionDenied)?;
+        let parts: Vec<_> = within
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect();
+        Ok(format!("/{}", parts.join("/")))
+    }
+
+    async fn symlink(&self, path: &str, target: &str) -> BackendResult<()> {
+        let full_path = self.link_path(path).await?;
+
+        let disk_target = if target.starts_with('/') {
+            let resolved = resolve_path(target).ok_or(BackendError::PermissionDenied)?;
+            self.canonical_root.join(resolved)
+        } else {
+            let resolved = resolve_path(path).ok_or(BackendError::PermissionDenied)?;
+            let parent = resolved.rsplit_once('/').map_or("", |(parent, _)| parent);
+            resolve_path(&format!("{parent}/{target}")).ok_or(BackendError::PermissionDenied)?;
+            PathBuf::from(target)
+        };
+
+        debug!(path = %full_path.display(), target = %disk_target.display(), "Creating symlink");
+
+        #[cfg(unix)]
+        return fs::symlink(&disk_target, &full_path)
+            .await
+            .map_err(Self::map_io_error);
+        #[cfg(not(unix))]
+        Err(BackendError::Unsupported)
+    }
+
+    async fn hard_link(&self, src: &str, dst: &str) -> BackendResult<()> {
+        let src_path = self.link_path(src).await?;
+        let dst_path = self.link_path(dst).await?;
+
+        debug!(from = %src_path.display(), to = %dst_path.display(), "Creating hard link");
+
+        fs::hard_link(&src_path, &dst_path)
+            .await
+            .map_err(Self::map_io_error)
+    }
+
+    #[cfg(unix)]
+    async fn space_info(&self, path: &str) -> BackendResult<SpaceInfo> {
+        let full_path = self.full_path(path).await?;
+
+        let stat = tokio::task::spawn_blocking(move || rustix::fs::statvfs(&full_path))
+            .await
+            .map_err(|e| BackendError::Io(e.to_string()))?
+            .map_err(|e| Self::map_io_error(e.into()))?;
+
+        // Block counts are in units of the fragment size
+        Ok(SpaceInfo {
+            total: stat.f_blocks * stat.f_frsize,
+            free: stat.f_bfree * stat.f_frsize,
+            available: stat.f_bavail * stat.f_frsize,
+        })
+    }
+
+    async fn copy(&self, src: &str, dst: &str) -> BackendResult<()> {
+        let src_path = self.full_path(src).await?;
+        let dst_path = self.full_path(dst).await?;
+
+        debug!(from = %src_path.display(), to = %dst_path.display(), "Copying");
+
+        tokio::task::spawn_blocking(move || copy_file(&src_path, &dst_path))
+            .await
+            .map_err(|e| BackendError::Io(e.to_string()))?
+            .map_err(Self::map_io_error)
+    }
 }
 
 #[cfg(test)]
@@ -206,6 +548,59 @@ mod tests {
         assert_eq!(read, content);
     }
 
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_set_attrs() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let backend = LocalBackend::new(temp_dir.path());
+        backend
+            .write_file("test.txt", Bytes::from_static(b"hello world"))
+            .await
+            .unwrap();
+
+        // setuid and the like are dropped
+        let attrs = SetAttrs {
+            size: Some(5),
+            permissions: Some(0o4640),
+            mtime: Some(1_000_000),
+            ..Default::default()
+        };
+        backend.set_attrs("test.txt", attrs).await.unwrap();
+        let info = backend.file_info("test.txt").await.unwrap();
+        assert_eq!((info.size, info.mtime), (5, 1_000_000));
+        let mode = std::fs::metadata(temp_dir.path().join("test.txt"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o7777, 0o640);
+
+        // Owners only change when enabled
+        assert!(!backend.attr_capabilities().ownership);
+        assert!(backend.with_ownership(true).attr_capabilities().ownership);
+    }
+
+    #[tokio::test]
+    async fn test_read_range() {
+        let temp_dir = TempDir::new().unwrap();
+        let backend = LocalBackend::new(temp_dir.path());
+
+        backend
+            .write_file("test.txt", Bytes::from_static(b"hello world"))
+            .await
+            .unwrap();
+
+        let read = backend.read_range("test.txt", 6, 3).await.unwrap();
+        assert_eq!(read.as_ref(), b"wor");
+
+        let tail = backend.read_range("test.txt", 6, 100).await.unwrap();
+        assert_eq!(tail.as_ref(), b"world");
+
+        let past_end = backend.read_range("test.txt", 100, 10).await.unwrap();
+        assert!(past_end.is_empty());
+    }
+
     #[tokio::test]
     async fn test_list_dir() {
         let temp_dir = TempDir::new().unwrap();
@@ -278,6 +673,246 @@ mod tests {
         assert!(matches!(old_result, Err(BackendError::NotFound)));
     }
 
+    /// Create a served root inside a temp dir, with a secret file next to it
+    fn escape_fixture() -> (TempDir, LocalBackend) {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::create_dir(temp_dir.path().join("root")).unwrap();
+        std::fs::write(temp_dir.path().join("secret.txt"), b"secret").unwrap();
+        let backend = LocalBackend::new(temp_dir.path().join("root"));
+        (temp_dir, backend)
+    }
+
+    #[test]
+    fn test_resolve_path() {
+        assert_eq!(resolve_path("/a/./b/../c/").as_deref(), Some("a/c"));
+        assert_eq!(resolve_path("a/..").as_deref(), Some(""));
+        assert_eq!(resolve_path("/"), Some(String::new()));
+        assert_eq!(resolve_path(".."), None);
+        assert_eq!(resolve_path("a/../../b"), None);
+    }
+
+    #[tokio::test]
+    async fn test_parent_dir_escape_denied() {
+        let (_temp_dir, backend) = escape_fixture();
+
+        let result = backend.read_file("../secret.txt").await;
+        assert!(matches!(result, Err(BackendError::PermissionDenied)));
+
+        let result = backend.read_file("/../secret.txt").await;
+        assert!(matches!(result, Err(BackendError::PermissionDenied)));
+
+        let result = backend.list_dir("..").await;
+        assert!(matches!(result, Err(BackendError::PermissionDenied)));
+    }
+
+    #[tokio::test]
+    async fn test_nested_parent_dir_escape_denied() {
+        let (temp_dir, backend) = escape_fixture();
+        backend.make_dir("a").await.unwrap();
+
+        let result = backend.read_file("a/../../secret.txt").await;
+        assert!(matches!(result, Err(BackendError::PermissionDenied)));
+
+        let result = backend
+            .write_file("a/../../planted.txt", Bytes::from_static(b"x"))
+            .await;
+        assert!(matches!(result, Err(BackendError::PermissionDenied)));
+        assert!(!temp_dir.path().join("planted.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_parent_dir_within_root_allowed() {
+        let (_temp_dir, backend) = escape_fixture();
+        backend.make_dir("a").await.unwrap();
+        backend
+            .write_file("a/../file.txt", Bytes::from_static(b"data"))
+            .await
+            .unwrap();
+
+        let read = backend.read_file("file.txt").await.unwrap();
+        assert_eq!(read.as_ref(), b"data");
+    }
+
+    #[tokio::test]
+    async fn test_absolute_path_stays_in_root() {
+        let (_temp_dir, backend) = escape_fixture();
+        let result = backend.read_file("/etc/passwd").await;
+        assert!(matches!(result, Err(BackendError::NotFound)));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_symlink_escape_denied() {
+        let (temp_dir, backend) = escape_fixture();
+        std::os::unix::fs::symlink(
+            temp_dir.path().join("secret.txt"),
+            temp_dir.path().join("root/link.txt"),
+        )
+        .unwrap();
+
+        let result = backend.read_file("link.txt").await;
+        assert!(matches!(result, Err(BackendError::PermissionDenied)));
+
+        let result = backend.file_info("link.txt").await;
+        assert!(matches!(result, Err(BackendError::PermissionDenied)));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_symlinked_dir_escape_denied() {
+        let (temp_dir, backend) = escape_fixture();
+        std::fs::create_dir(temp_dir.path().join("outside")).unwrap();
+        std::os::unix::fs::symlink(
+            temp_dir.path().join("outside"),
+            temp_dir.path().join("root/dir"),
+        )
+        .unwrap();
+
+        let result = backend.list_dir("dir").await;
+        assert!(matches!(result, Err(BackendError::PermissionDenied)));
+
+        let result = backend
+            .write_file("dir/planted.txt", Bytes::from_static(b"x"))
+            .await;
+        assert!(matches!(result, Err(BackendError::PermissionDenied)));
+        assert!(!temp_dir.path().join("outside/planted.txt").exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_dangling_symlink_escape_denied() {
+        let (temp_dir, backend) = escape_fixture();
+        std::os::unix::fs::symlink(
+            temp_dir.path().join("planted.txt"),
+            temp_dir.path().join("root/dangling"),
+        )
+        .unwrap();
+
+        let result = backend
+            .write_file("dangling", Bytes::from_static(b"x"))
+            .await;
+        assert!(matches!(result, Err(BackendError::PermissionDenied)));
+        assert!(!temp_dir.path().join("planted.txt").exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_symlink_within_root_allowed() {
+        let (temp_dir, backend) = escape_fixture();
+        backend
+            .write_file("target.txt", Bytes::from_static(b"data"))
+            .await
+            .unwrap();
+        std::os::unix::fs::symlink("target.txt", temp_dir.path().join("root/link.txt")).unwrap();
+
+        let read = backend.read_file("link.txt").await.unwrap();
+        assert_eq!(read.as_ref(), b"data");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_create_and_read_symlinks() {
+        let (temp_dir, backend) = escape_fixture();
+        backend.make_dir("dir").await.unwrap();
+        backend
+            .write_file("dir/target.txt", Bytes::from_static(b"data"))
+            .await
+            .unwrap();
+
+        backend.symlink("rel", "dir/target.txt").await.unwrap();
+        backend.symlink("dir/abs", "/dir/target.txt").await.unwrap();
+        for link in ["rel", "dir/abs"] {
+            assert_eq!(backend.read_file(link).await.unwrap().as_ref(), b"data");
+            assert!(backend.symlink_info(link).await.unwrap().is_symlink());
+            assert_eq!(
+                backend.file_info(link).await.unwrap().file_type,
+                FileType::File
+            );
+        }
+        assert_eq!(backend.read_link("rel").await.unwrap(), "dir/target.txt");
+        assert_eq!(
+            backend.read_link("dir/abs").await.unwrap(),
+            "/dir/target.txt"
+        );
+        // Absolute targets are stored beneath the root on disk
+        let on_disk = std::fs::read_link(temp_dir.path().join("root/dir/abs")).unwrap();
+        assert!(on_disk.ends_with("root/dir/target.txt"));
+        assert!(matches!(
+            backend.read_link("dir/target.txt").await,
+            Err(BackendError::NotASymlink)
+        ));
+
+        // Removing a link leaves its target
+        backend.delete("rel").await.unwrap();
+        assert!(backend.file_info("dir/target.txt").await.is_ok());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_symlink_target_escape_denied() {
+        let (temp_dir, backend) = escape_fixture();
+
+        let result = backend.symlink("link", "../secret.txt").await;
+        assert!(matches!(result, Err(BackendError::PermissionDenied)));
+        assert!(!temp_dir.path().join("root/link").exists());
+
+        // A link placed outside the server can be inspected and removed, but
+        // doesn't reveal where it points
+        std::os::unix::fs::symlink(
+            temp_dir.path().join("secret.txt"),
+            temp_dir.path().join("root/planted"),
+        )
+        .unwrap();
+        assert!(backend.symlink_info("planted").await.unwrap().is_symlink());
+        assert!(matches!(
+            backend.read_link("planted").await,
+            Err(BackendError::PermissionDenied)
+        ));
+        backend.delete("planted").await.unwrap();
+        assert!(temp_dir.path().join("secret.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_hard_link() {
+        let temp_dir = TempDir::new().unwrap();
+        let backend = LocalBackend::new(temp_dir.path());
+        backend
+            .write_file("a.txt", Bytes::from_static(b"data"))
+            .await
+            .unwrap();
+
+        backend.hard_link("a.txt", "b.txt").await.unwrap();
+        backend.delete("a.txt").await.unwrap();
+        assert_eq!(backend.read_file("b.txt").await.unwrap().as_ref(), b"data");
+        assert!(matches!(
+            backend.hard_link("missing", "c.txt").await,
+            Err(BackendError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_copy() {
+        let temp_dir = TempDir::new().unwrap();
+        let backend = LocalBackend::new(temp_dir.path());
+        backend
+            .write_file("a.txt", Bytes::from_static(b"data"))
+            .await
+            .unwrap();
+        backend
+            .write_file("b.txt", Bytes::from_static(b"longer old data"))
+            .await
+            .unwrap();
+
+        backend.copy("a.txt", "b.txt").await.unwrap();
+        assert_eq!(backend.read_file("b.txt").await.unwrap().as_ref(), b"data");
+        backend.write_file("a.txt", Bytes::new()).await.unwrap();
+        assert_eq!(backend.read_file("b.txt").await.unwrap().as_ref(), b"data");
+
+        assert!(backend.copy("a.txt", "a.txt").await.is_err());
+        assert!(backend.copy("b.txt", "../escape.txt").await.is_err());
+        assert_eq!(backend.read_file("b.txt").await.unwrap().as_ref(), b"data");
+    }
+
     proptest! {
         #[test]
         fn prop_write_read_roundtrip(
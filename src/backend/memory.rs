@@ -1,8 +1,12 @@
-use super::{normalize_path, Backend, BackendError, BackendResult, DirEntry, FileInfo};
+use super::{
+    link_target_path, AttrCapabilities, Backend, BackendError, BackendResult, DirEntry, FileInfo,
+    SetAttrs, MAX_SYMLINK_HOPS,
+};
 use async_trait::async_trait;
 use bytes::Bytes;
 use parking_lot::RwLock;
 use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
 const KEEP_MARKER: &str = ".keep";
 
@@ -11,11 +15,105 @@ const KEEP_MARKER: &str = ".keep";
 struct FileData {
     content: Bytes,
     mtime: u32,
+    atime: u32,
+    permissions: u32,
+    /// Target, if this is a symlink
+    link: Option<String>,
+}
+
+impl FileData {
+    fn new(content: Bytes, mtime: u32) -> Self {
+        Self {
+            content,
+            mtime,
+            atime: mtime,
+            permissions: 0o644,
+            link: None,
+        }
+    }
+
+    fn symlink(target: &str, mtime: u32) -> Self {
+        Self {
+            link: Some(target.to_string()),
+            ..Self::new(Bytes::new(), mtime)
+        }
+    }
+
+    fn info(&self) -> FileInfo {
+        let info = match self.link {
+            Some(ref target) => FileInfo::symlink(target.len() as u64, self.mtime),
+            None => FileInfo {
+                permissions: self.permissions,
+                ..FileInfo::file_with_mtime(self.content.len() as u64, self.mtime)
+            },
+        };
+        FileInfo {
+            atime: self.atime,
+            ..info
+        }
+    }
+}
+
+/// A file, shared between the names hard linked to it
+type Node = Arc<RwLock<FileData>>;
+
+type Files = HashMap<String, Node>;
+
+fn node(data: FileData) -> Node {
+    Arc::new(RwLock::new(data))
+}
+
+/// Whether `key` names a file or a directory
+fn exists(files: &Files, key: &str) -> bool {
+    let prefix = format!("{key}/");
+    files.contains_key(key) || files.keys().any(|k| k.starts_with(&prefix))
+}
+
+/// Resolve symlinks in `path`, and in its last component if `follow_last`
+fn resolve(files: &Files, path: &str, follow_last: bool) -> BackendResult<String> {
+    let components = |path: &str| -> Vec<String> {
+        path.split('/')
+            .filter(|c| !c.is_empty())
+            .rev()
+            .map(str::to_string)
+            .collect()
+    };
+    // Components still to visit, next one last
+    let mut pending = components(path);
+    let mut resolved = String::new();
+    let mut hops = 0;
+
+    while let Some(name) = pending.pop() {
+        let candidate = if resolved.is_empty() {
+            name
+        } else {
+            format!("{resolved}/{name}")
+        };
+        let target = match files.get(&candidate) {
+            Some(node) if follow_last || !pending.is_empty() => node.read().link.clone(),
+            _ => None,
+        };
+        match target {
+            Some(target) => {
+                hops += 1;
+                if hops > MAX_SYMLINK_HOPS {
+                    return Err(BackendError::SymlinkLoop);
+                }
+                pending.extend(components(&link_target_path(&candidate, &target)));
+                resolved.clear();
+            }
+            None => resolved = candidate,
+        }
+    }
+    Ok(resolved)
 }
 
 /// In-memory storage backend for testing and development
+///
+/// Supports symlinks, followed in every path component, and hard links,
+/// which share contents and attributes between names.
 pub struct MemoryBackend {
-    files: RwLock<HashMap<String, FileData>>,
+    files: RwLock<Files>,
 }
 
 impl Default for MemoryBackend {
@@ -36,33 +134,32 @@ impl MemoryBackend {
         let mtime = super::current_timestamp();
         let files = files
             .into_iter()
-            .map(|(k, content)| {
-                (
-                    k,
-                    FileData {
-                        content: content.into(),
-                        mtime,
-                    },
-                )
-            })
+            .map(|(k, content)| (k, node(FileData::new(content.into(), mtime))))
             .collect();
         Self {
             files: RwLock::new(files),
         }
     }
+
+    /// The file `path` resolves to, following symlinks
+    fn get(&self, path: &str) -> BackendResult<Node> {
+        let files = self.files.read();
+        let key = resolve(&files, path, true)?;
+        files.get(&key).cloned().ok_or(BackendError::NotFound)
+    }
 }
 
 #[async_trait]
 impl Backend for MemoryBackend {
     async fn list_dir(&self, path: &str) -> BackendResult<Vec<DirEntry>> {
-        let normalized = normalize_path(path);
-        let prefix = if normalized.is_empty() {
+        let files = self.files.read();
+        let resolved = resolve(&files, path, true)?;
+        let prefix = if resolved.is_empty() {
             String::new()
         } else {
-            format!("{}/", normalized)
+            format!("{}/", resolved)
         };
 
-        let files = self.files.read();
         let mut seen = HashSet::new();
         let mut entries = vec![
             DirEntry {
@@ -75,7 +172,7 @@ impl Backend for MemoryBackend {
             },
         ];
 
-        for (key, data) in files.iter() {
+        for (key, node) in files.iter() {
             let relative = if prefix.is_empty() {
                 key.as_str()
             } else if let Some(stripped) = key.strip_prefix(&prefix) {
@@ -93,10 +190,11 @@ impl Backend for MemoryBackend {
 
             if seen.insert(name.to_string()) {
                 let is_dir = relative.contains('/');
+                let data = node.read();
                 let attrs = if is_dir {
                     FileInfo::directory_with_mtime(data.mtime)
                 } else {
-                    FileInfo::file_with_mtime(data.content.len() as u64, data.mtime)
+                    data.info()
                 };
 
                 entries.push(DirEntry {
@@ -110,89 +208,192 @@ impl Backend for MemoryBackend {
     }
 
     async fn file_info(&self, path: &str) -> BackendResult<FileInfo> {
-        let normalized = normalize_path(path);
-
-        if normalized.is_empty() {
-            return Ok(FileInfo::directory());
-        }
-
         let files = self.files.read();
+        let resolved = resolve(&files, path, true)?;
+        entry_info(&files, &resolved)
+    }
 
-        // Check if it's a file
-        if let Some(data) = files.get(normalized.as_ref()) {
-            return Ok(FileInfo::file_with_mtime(
-                data.content.len() as u64,
-                data.mtime,
-            ));
-        }
-
-        // Check if it's a directory
-        let prefix = format!("{}/", normalized);
-        if files.keys().any(|k| k.starts_with(&prefix)) {
-            return Ok(FileInfo::directory());
-        }
-
-        Err(BackendError::NotFound)
+    async fn symlink_info(&self, path: &str) -> BackendResult<FileInfo> {
+        let files = self.files.read();
+        let resolved = resolve(&files, path, false)?;
+        entry_info(&files, &resolved)
     }
 
     async fn make_dir(&self, path: &str) -> BackendResult<()> {
-        let key = format!("{}/{}", normalize_path(path), KEEP_MARKER);
-        self.files.write().insert(
+        let mut files = self.files.write();
+        let key = format!("{}/{}", resolve(&files, path, false)?, KEEP_MARKER);
+        files.insert(
             key,
-            FileData {
-                content: Bytes::new(),
-                mtime: super::current_timestamp(),
-            },
+            node(FileData::new(Bytes::new(), super::current_timestamp())),
         );
         Ok(())
     }
 
     async fn del_dir(&self, path: &str) -> BackendResult<()> {
-        let key = format!("{}/{}", normalize_path(path), KEEP_MARKER);
-        self.files.write().remove(&key);
+        let mut files = self.files.write();
+        let key = format!("{}/{}", resolve(&files, path, false)?, KEEP_MARKER);
+        files.remove(&key);
         Ok(())
     }
 
     async fn delete(&self, path: &str) -> BackendResult<()> {
-        self.files.write().remove(normalize_path(path).as_ref());
+        let mut files = self.files.write();
+        let key = resolve(&files, path, false)?;
+        files.remove(&key);
         Ok(())
     }
 
     async fn rename(&self, src: &str, dst: &str) -> BackendResult<()> {
-        let src_key = normalize_path(src);
-        let dst_key = normalize_path(dst);
-
         let mut files = self.files.write();
-        if let Some(data) = files.remove(src_key.as_ref()) {
-            files.insert(dst_key.into_owned(), data);
+        let src_key = resolve(&files, src, false)?;
+        let dst_key = resolve(&files, dst, false)?;
+
+        if let Some(node) = files.remove(&src_key) {
+            files.insert(dst_key, node);
         }
         Ok(())
     }
 
+    /// The whole rename happens under one lock
+    fn atomic_rename(&self) -> bool {
+        true
+    }
+
     async fn read_file(&self, path: &str) -> BackendResult<Bytes> {
-        let normalized = normalize_path(path);
-        self.files
-            .read()
-            .get(normalized.as_ref())
-            .map(|d| d.content.clone()) // Bytes clone is O(1)
-            .ok_or(BackendError::NotFound)
+        // Bytes clone is O(1)
+        Ok(self.get(path)?.read().content.clone())
+    }
+
+    async fn read_range(&self, path: &str, offset: u64, len: u32) -> BackendResult<Bytes> {
+        let node = self.get(path)?;
+        let data = node.read();
+        Ok(super::slice_range(&data.content, offset, len))
     }
 
     async fn write_file(&self, path: &str, content: Bytes) -> BackendResult<()> {
-        self.files.write().insert(
-            normalize_path(path).into_owned(),
-            FileData {
-                content,
-                mtime: super::current_timestamp(),
-            },
+        let mut files = self.files.write();
+        let key = resolve(&files, path, true)?;
+        let data = FileData::new(content, super::current_timestamp());
+        match files.get(&key) {
+            // Overwriting keeps the mode, as on a filesystem, and is seen
+            // through every hard link
+            Some(existing) => {
+                let mut existing = existing.write();
+                *existing = FileData {
+                    permissions: existing.permissions,
+                    ..data
+                };
+            }
+            None => {
+                files.insert(key, node(data));
+            }
+        }
+        Ok(())
+    }
+
+    fn attr_capabilities(&self) -> AttrCapabilities {
+        AttrCapabilities {
+            ownership: false,
+            ..AttrCapabilities::ALL
+        }
+    }
+
+    /// Only files have attributes; directories here are implied by their contents
+    async fn set_attrs(&self, path: &str, attrs: SetAttrs) -> BackendResult<()> {
+        let node = match self.get(path) {
+            Ok(node) => node,
+            Err(BackendError::NotFound) => {
+                self.file_info(path).await?;
+                return Err(BackendError::Unsupported);
+            }
+            Err(err) => return Err(err),
+        };
+        let mut data = node.write();
+        if let Some(size) = attrs.size {
+            let size = usize::try_from(size).map_err(|e| BackendError::Io(e.to_string()))?;
+            let mut content = data.content.to_vec();
+            // The size comes from the client; fail instead of aborting on allocation
+            content
+                .try_reserve(size.saturating_sub(content.len()))
+                .map_err(|e| BackendError::Io(e.to_string()))?;
+            content.resize(size, 0);
+            data.content = Bytes::from(content);
+        }
+        if let Some(mode) = attrs.permissions {
+            data.permissions = mode & 0o777;
+        }
+        if let Some(atime) = attrs.atime {
+            data.atime = atime;
+        }
+        if let Some(mtime) = attrs.mtime {
+            data.mtime = mtime;
+        }
+        Ok(())
+    }
+
+    async fn read_link(&self, path: &str) -> BackendResult<String> {
+        let files = self.files.read();
+        let key = resolve(&files, path, false)?;
+        match files.get(&key) {
+            Some(node) => node.read().link.clone().ok_or(BackendError::NotASymlink),
+            None if exists(&files, &key) => Err(BackendError::NotASymlink),
+            None => Err(BackendError::NotFound),
+        }
+    }
+
+    async fn symlink(&self, path: &str, target: &str) -> BackendResult<()> {
+        let mut files = self.files.write();
+        let key = resolve(&files, path, false)?;
+        if key.is_empty() || exists(&files, &key) {
+            return Err(BackendError::AlreadyExists);
+        }
+        files.insert(
+            key,
+            node(FileData::symlink(target, super::current_timestamp())),
         );
         Ok(())
     }
+
+    async fn hard_link(&self, src: &str, dst: &str) -> BackendResult<()> {
+        let mut files = self.files.write();
+        let src_key = resolve(&files, src, false)?;
+        let node = match files.get(&src_key) {
+            Some(node) => node.clone(),
+            None if exists(&files, &src_key) => return Err(BackendError::IsADirectory),
+            None => return Err(BackendError::NotFound),
+        };
+        let dst_key = resolve(&files, dst, false)?;
+        if dst_key.is_empty() || exists(&files, &dst_key) {
+            return Err(BackendError::AlreadyExists);
+        }
+        files.insert(dst_key, node);
+        Ok(())
+    }
+}
+
+/// Info for the file or directory at the resolved `key`
+fn entry_info(files: &Files, key: &str) -> BackendResult<FileInfo> {
+    if key.is_empty() {
+        return Ok(FileInfo::directory());
+    }
+
+    // Check if it's a file
+    if let Some(node) = files.get(key) {
+        return Ok(node.read().info());
+    }
+
+    // Check if it's a directory
+    if exists(files, key) {
+        return Ok(FileInfo::directory());
+    }
+
+    Err(BackendError::NotFound)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::backend::normalize_path;
     use proptest::prelude::*;
     use std::sync::Arc;
 
@@ -211,6 +412,24 @@ mod tests {
         assert_eq!(read, content);
     }
 
+    #[tokio::test]
+    async fn test_read_range() {
+        let backend = MemoryBackend::new();
+        backend
+            .write_file("test.txt", Bytes::from_static(b"hello world"))
+            .await
+            .unwrap();
+
+        let read = backend.read_range("test.txt", 6, 100).await.unwrap();
+        assert_eq!(read.as_ref(), b"world");
+
+        let past_end = backend.read_range("test.txt", 20, 10).await.unwrap();
+        assert!(past_end.is_empty());
+
+        let missing = backend.read_range("missing.txt", 0, 10).await;
+        assert!(matches!(missing, Err(BackendError::NotFound)));
+    }
+
     #[tokio::test]
     async fn test_list_root() {
         let backend = MemoryBackend::new();
@@ -259,6 +478,148 @@ mod tests {
         assert!(root_info.is_dir);
     }
 
+    #[tokio::test]
+    async fn test_set_attrs() {
+        let backend = MemoryBackend::new();
+        backend
+            .write_file("test.txt", Bytes::from_static(b"12345"))
+            .await
+            .unwrap();
+
+        let attrs = SetAttrs {
+            size: Some(8),
+            permissions: Some(0o2600),
+            atime: Some(100),
+            mtime: Some(200),
+            ..Default::default()
+        };
+        backend.set_attrs("test.txt", attrs).await.unwrap();
+        let info = backend.file_info("test.txt").await.unwrap();
+        assert_eq!((info.size, info.atime, info.mtime), (8, 100, 200));
+        assert_eq!(info.permissions & 0o7777, 0o600);
+        assert_eq!(
+            backend.read_file("test.txt").await.unwrap().as_ref(),
+            b"12345\0\0\0"
+        );
+
+        // Rewriting the file keeps its mode
+        backend
+            .write_file("test.txt", Bytes::from_static(b"x"))
+            .await
+            .unwrap();
+        let info = backend.file_info("test.txt").await.unwrap();
+        assert_eq!(info.permissions & 0o7777, 0o600);
+
+        // Sizes that can't be allocated fail without touching the file
+        let huge = SetAttrs {
+            size: Some(u64::MAX),
+            ..Default::default()
+        };
+        assert!(matches!(
+            backend.set_attrs("test.txt", huge).await,
+            Err(BackendError::Io(_))
+        ));
+        assert_eq!(backend.read_file("test.txt").await.unwrap().as_ref(), b"x");
+
+        assert!(matches!(
+            backend.set_attrs("missing", attrs).await,
+            Err(BackendError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_link_target_path() {
+        assert_eq!(link_target_path("dir/link", "a.txt"), "dir/a.txt");
+        assert_eq!(link_target_path("/dir/link", "../a.txt"), "a.txt");
+        assert_eq!(
+            link_target_path("dir/link", "/other/./a.txt"),
+            "other/a.txt"
+        );
+        // `..` stops at the root
+        assert_eq!(link_target_path("link", "../../a.txt"), "a.txt");
+    }
+
+    #[tokio::test]
+    async fn test_symlinks() {
+        let backend = MemoryBackend::new();
+        backend
+            .write_file("dir/a.txt", Bytes::from_static(b"data"))
+            .await
+            .unwrap();
+        backend.symlink("file", "dir/a.txt").await.unwrap();
+        backend.symlink("alias", "/dir").await.unwrap();
+        backend.symlink("dangling", "missing").await.unwrap();
+
+        assert_eq!(backend.read_file("file").await.unwrap().as_ref(), b"data");
+        assert_eq!(
+            backend.read_file("alias/a.txt").await.unwrap().as_ref(),
+            b"data"
+        );
+        assert!(backend.file_info("alias").await.unwrap().is_dir);
+        assert!(backend.symlink_info("alias").await.unwrap().is_symlink());
+        let names: Vec<_> = backend
+            .list_dir("alias")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        assert!(names.contains(&"a.txt".to_string()));
+
+        assert_eq!(backend.read_link("alias").await.unwrap(), "/dir");
+        assert!(matches!(
+            backend.read_link("dir").await,
+            Err(BackendError::NotASymlink)
+        ));
+        assert!(matches!(
+            backend.file_info("dangling").await,
+            Err(BackendError::NotFound)
+        ));
+        assert!(matches!(
+            backend.symlink("file", "elsewhere").await,
+            Err(BackendError::AlreadyExists)
+        ));
+
+        // Writing through a link changes the target
+        backend
+            .write_file("file", Bytes::from_static(b"new"))
+            .await
+            .unwrap();
+        assert_eq!(
+            backend.read_file("dir/a.txt").await.unwrap().as_ref(),
+            b"new"
+        );
+
+        backend.symlink("loop", "loop").await.unwrap();
+        assert!(matches!(
+            backend.file_info("loop").await,
+            Err(BackendError::SymlinkLoop)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_hard_links_share_contents() {
+        let backend = MemoryBackend::new();
+        backend
+            .write_file("a.txt", Bytes::from_static(b"old"))
+            .await
+            .unwrap();
+        backend.hard_link("a.txt", "b.txt").await.unwrap();
+
+        backend
+            .write_file("a.txt", Bytes::from_static(b"new"))
+            .await
+            .unwrap();
+        assert_eq!(backend.read_file("b.txt").await.unwrap().as_ref(), b"new");
+
+        backend.delete("a.txt").await.unwrap();
+        assert_eq!(backend.read_file("b.txt").await.unwrap().as_ref(), b"new");
+        assert!(matches!(
+            backend.hard_link("missing", "c.txt").await,
+            Err(BackendError::NotFound)
+        ));
+    }
+
     // Concurrent access test
     #[tokio::test]
     async fn test_concurrent_writes() {
@@ -333,6 +694,25 @@ mod tests {
             })?
         }
 
+        // Ranged reads match slices of the full content
+        #[test]
+        fn prop_read_range_matches_slice(
+            content in prop::collection::vec(any::<u8>(), 0..1024),
+            offset in 0u64..1100,
+            len in 0u32..1100
+        ) {
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            rt.block_on(async {
+                let backend = MemoryBackend::new();
+                backend.write_file("file", Bytes::from(content.clone())).await.unwrap();
+                let read = backend.read_range("file", offset, len).await.unwrap();
+                let start = (offset as usize).min(content.len());
+                let end = (start + len as usize).min(content.len());
+                prop_assert_eq!(read.as_ref(), &content[start..end]);
+                Ok(())
+            })?
+        }
+
         // Delete then not found
         #[test]
         fn prop_delete_then_not_found(
@@ -0,0 +1,135 @@
+use super::{
+    AttrCapabilities, Backend, BackendResult, DirEntry, FileInfo, FileWriter, SetAttrs, SpaceInfo,
+};
+use crate::telemetry;
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Time `call` as backend operation `op`
+async fn timed<T>(
+    op: &'static str,
+    call: impl Future<Output = BackendResult<T>>,
+) -> BackendResult<T> {
+    let started = Instant::now();
+    let result = call.await;
+    telemetry::backend_operation(op, started, result.is_ok());
+    result
+}
+
+/// Backend wrapper recording the latency of every call
+pub struct MeteredBackend<B: Backend + ?Sized> {
+    inner: Arc<B>,
+}
+
+impl<B: Backend + ?Sized> MeteredBackend<B> {
+    pub fn new(inner: Arc<B>) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl<B: Backend + ?Sized> Backend for MeteredBackend<B> {
+    async fn list_dir(&self, path: &str) -> BackendResult<Vec<DirEntry>> {
+        timed("list_dir", self.inner.list_dir(path)).await
+    }
+
+    async fn file_info(&self, path: &str) -> BackendResult<FileInfo> {
+        timed("file_info", self.inner.file_info(path)).await
+    }
+
+    async fn make_dir(&self, path: &str) -> BackendResult<()> {
+        timed("make_dir", self.inner.make_dir(path)).await
+    }
+
+    async fn del_dir(&self, path: &str) -> BackendResult<()> {
+        timed("del_dir", self.inner.del_dir(path)).await
+    }
+
+    async fn delete(&self, path: &str) -> BackendResult<()> {
+        timed("delete", self.inner.delete(path)).await
+    }
+
+    async fn rename(&self, src: &str, dst: &str) -> BackendResult<()> {
+        timed("rename", self.inner.rename(src, dst)).await
+    }
+
+    fn atomic_rename(&self) -> bool {
+        self.inner.atomic_rename()
+    }
+
+    async fn read_file(&self, path: &str) -> BackendResult<Bytes> {
+        timed("read_file", self.inner.read_file(path)).await
+    }
+
+    async fn read_range(&self, path: &str, offset: u64, len: u32) -> BackendResult<Bytes> {
+        timed("read_range", self.inner.read_range(path, offset, len)).await
+    }
+
+    async fn write_file(&self, path: &str, content: Bytes) -> BackendResult<()> {
+        timed("write_file", self.inner.write_file(path, content)).await
+    }
+
+    async fn open_writer(&self, path: &str) -> BackendResult<Option<Box<dyn FileWriter>>> {
+        let writer = timed("open_writer", self.inner.open_writer(path)).await?;
+        Ok(writer.map(|inner| Box::new(MeteredWriter { inner }) as Box<dyn FileWriter>))
+    }
+
+    fn attr_capabilities(&self) -> AttrCapabilities {
+        self.inner.attr_capabilities()
+    }
+
+    async fn set_attrs(&self, path: &str, attrs: SetAttrs) -> BackendResult<()> {
+        timed("set_attrs", self.inner.set_attrs(path, attrs)).await
+    }
+
+    async fn symlink_info(&self, path: &str) -> BackendResult<FileInfo> {
+        timed("symlink_info", self.inner.symlink_info(path)).await
+    }
+
+    async fn read_link(&self, path: &str) -> BackendResult<String> {
+        timed("read_link", self.inner.read_link(path)).await
+    }
+
+    async fn symlink(&self, path: &str, target: &str) -> BackendResult<()> {
+        timed("symlink", self.inner.symlink(path, target)).await
+    }
+
+    async fn hard_link(&self, src: &str, dst: &str) -> BackendResult<()> {
+        timed("hard_link", self.inner.hard_link(src, dst)).await
+    }
+
+    async fn resolve_links(&self, path: &str, follow_last: bool) -> BackendResult<String> {
+        timed("resolve_links", self.inner.resolve_links(path, follow_last)).await
+    }
+
+    async fn space_info(&self, path: &str) -> BackendResult<SpaceInfo> {
+        timed("space_info", self.inner.space_info(path)).await
+    }
+
+    async fn copy(&self, src: &str, dst: &str) -> BackendResult<()> {
+        timed("copy", self.inner.copy(src, dst)).await
+    }
+}
+
+/// Streaming writer whose parts and final commit are timed too
+struct MeteredWriter {
+    inner: Box<dyn FileWriter>,
+}
+
+#[async_trait]
+impl FileWriter for MeteredWriter {
+    async fn write(&mut self, data: Bytes) -> BackendResult<()> {
+        timed("writer_write", self.inner.write(data)).await
+    }
+
+    async fn commit(self: Box<Self>) -> BackendResult<()> {
+        timed("writer_commit", self.inner.commit()).await
+    }
+
+    async fn abort(self: Box<Self>) -> BackendResult<()> {
+        timed("writer_abort", self.inner.abort()).await
+    }
+}
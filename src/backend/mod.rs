@@ -3,15 +3,23 @@ use bytes::Bytes;
 use std::borrow::Cow;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+pub mod factory;
 pub mod local;
 pub mod memory;
+#[cfg(feature = "metrics")]
+pub mod metered;
 #[cfg(feature = "s3")]
 pub mod s3;
+pub mod scoped;
 
+pub use factory::{BackendFactory, SharedBackend};
 pub use local::LocalBackend;
 pub use memory::MemoryBackend;
+#[cfg(feature = "metrics")]
+pub use metered::MeteredBackend;
 #[cfg(feature = "s3")]
 pub use s3::{S3Backend, S3Config};
+pub use scoped::ScopedBackend;
 
 /// Result type for backend operations
 pub type BackendResult<T> = Result<T, BackendError>;
@@ -31,6 +39,14 @@ pub enum BackendError {
     IsADirectory,
     #[error("Directory not empty")]
     DirectoryNotEmpty,
+    #[error("Operation not supported by this backend")]
+    Unsupported,
+    #[error("Not a symbolic link")]
+    NotASymlink,
+    #[error("Too many levels of symbolic links")]
+    SymlinkLoop,
+    #[error("File too large")]
+    FileTooLarge,
     #[error("I/O error: {0}")]
     Io(String),
     #[error("Backend error: {0}")]
@@ -44,11 +60,36 @@ pub struct DirEntry {
     pub attrs: FileInfo,
 }
 
+/// Maximum number of symlinks followed while resolving one path
+pub const MAX_SYMLINK_HOPS: usize = 40;
+
+/// Kind of object a [`FileInfo`] describes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FileType {
+    #[default]
+    File,
+    Dir,
+    Symlink,
+}
+
+impl FileType {
+    /// `S_IFMT` bits for this type, as sent in SFTP permissions
+    pub fn mode_bits(self) -> u32 {
+        match self {
+            FileType::File => 0o100000,
+            FileType::Dir => 0o040000,
+            FileType::Symlink => 0o120000,
+        }
+    }
+}
+
 /// File metadata information
 #[derive(Debug, Clone)]
 pub struct FileInfo {
     pub size: u64,
     pub is_dir: bool,
+    /// File, directory or symlink; agrees with `is_dir`
+    pub file_type: FileType,
     pub permissions: u32,
     pub mtime: u32,
     pub atime: u32,
@@ -62,6 +103,7 @@ impl FileInfo {
         Self {
             size: 4096,
             is_dir: true,
+            file_type: FileType::Dir,
             permissions: 0o755,
             mtime: current_timestamp(),
             atime: current_timestamp(),
@@ -75,6 +117,7 @@ impl FileInfo {
         Self {
             size: 4096,
             is_dir: true,
+            file_type: FileType::Dir,
             permissions: 0o755,
             mtime,
             atime: mtime,
@@ -88,6 +131,7 @@ impl FileInfo {
         Self {
             size,
             is_dir: false,
+            file_type: FileType::File,
             permissions: 0o644,
             mtime: current_timestamp(),
             atime: current_timestamp(),
@@ -101,6 +145,7 @@ impl FileInfo {
         Self {
             size,
             is_dir: false,
+            file_type: FileType::File,
             permissions: 0o644,
             mtime,
             atime: mtime,
@@ -108,6 +153,133 @@ impl FileInfo {
             gid: 1000,
         }
     }
+
+    /// Create FileInfo for a symlink whose target is `target_len` bytes long
+    pub fn symlink(target_len: u64, mtime: u32) -> Self {
+        Self {
+            is_dir: false,
+            file_type: FileType::Symlink,
+            permissions: 0o777,
+            ..Self::file_with_mtime(target_len, mtime)
+        }
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.file_type == FileType::Symlink
+    }
+
+    /// Permissions with the file type bits set, as SFTP clients expect
+    ///
+    /// Type bits already present (e.g. a FIFO on a local filesystem) are kept.
+    pub fn mode(&self) -> u32 {
+        if self.permissions & 0o170000 != 0 {
+            self.permissions
+        } else {
+            self.permissions | self.file_type.mode_bits()
+        }
+    }
+}
+
+/// Attribute changes requested by a client; `None` fields are left alone
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SetAttrs {
+    /// Truncate or extend the file to this size
+    pub size: Option<u64>,
+    /// Permission bits, e.g. `0o644`
+    pub permissions: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub atime: Option<u32>,
+    pub mtime: Option<u32>,
+}
+
+impl SetAttrs {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Combine with later changes, which win where both set a field
+    pub fn merge(self, later: SetAttrs) -> Self {
+        Self {
+            size: later.size.or(self.size),
+            permissions: later.permissions.or(self.permissions),
+            uid: later.uid.or(self.uid),
+            gid: later.gid.or(self.gid),
+            atime: later.atime.or(self.atime),
+            mtime: later.mtime.or(self.mtime),
+        }
+    }
+}
+
+/// Which attributes a backend can change through [`Backend::set_attrs`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AttrCapabilities {
+    pub size: bool,
+    pub permissions: bool,
+    /// uid and gid
+    pub ownership: bool,
+    /// atime and mtime
+    pub times: bool,
+}
+
+impl AttrCapabilities {
+    /// Nothing can be changed (the default)
+    pub const NONE: Self = Self {
+        size: false,
+        permissions: false,
+        ownership: false,
+        times: false,
+    };
+
+    pub const ALL: Self = Self {
+        size: true,
+        permissions: true,
+        ownership: true,
+        times: true,
+    };
+
+    /// Whether every change in `attrs` can be made
+    pub fn supports(&self, attrs: &SetAttrs) -> bool {
+        self.filter(*attrs) == *attrs
+    }
+
+    /// `attrs` without the changes that can't be made
+    pub fn filter(&self, attrs: SetAttrs) -> SetAttrs {
+        SetAttrs {
+            size: attrs.size.filter(|_| self.size),
+            permissions: attrs.permissions.filter(|_| self.permissions),
+            uid: attrs.uid.filter(|_| self.ownership),
+            gid: attrs.gid.filter(|_| self.ownership),
+            atime: attrs.atime.filter(|_| self.times),
+            mtime: attrs.mtime.filter(|_| self.times),
+        }
+    }
+}
+
+/// Capacity of the storage holding a path, in bytes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SpaceInfo {
+    pub total: u64,
+    pub free: u64,
+    /// Free space usable by this server, which may be less than `free`
+    pub available: u64,
+}
+
+/// Incremental writer for streaming uploads
+///
+/// Chunks are appended in order with `write`. Nothing becomes visible at the
+/// destination until `commit`; dropping a writer without committing discards
+/// the upload.
+#[async_trait]
+pub trait FileWriter: Send + Sync {
+    /// Append a chunk to the end of the file
+    async fn write(&mut self, data: Bytes) -> BackendResult<()>;
+
+    /// Finish the upload and make the file visible
+    async fn commit(self: Box<Self>) -> BackendResult<()>;
+
+    /// Discard the upload
+    async fn abort(self: Box<Self>) -> BackendResult<()>;
 }
 
 /// Backend trait for storage implementations
@@ -139,16 +311,204 @@ pub trait Backend: Send + Sync + 'static {
     /// Rename/move a file or directory
     async fn rename(&self, src: &str, dst: &str) -> BackendResult<()>;
 
+    /// Whether [`Backend::rename`] replaces an existing target atomically
+    ///
+    /// `posix-rename@openssh.com` is only advertised when it does. Defaults
+    /// to false.
+    fn atomic_rename(&self) -> bool {
+        false
+    }
+
     /// Read entire file contents
     ///
     /// Returns file content as Bytes (reference-counted, cheap to clone).
-    /// For the initial implementation, files are loaded entirely into memory.
     async fn read_file(&self, path: &str) -> BackendResult<Bytes>;
 
+    /// Read up to `len` bytes starting at `offset`
+    ///
+    /// Returns an empty buffer when `offset` is at or past the end of the file.
+    /// The default implementation reads the whole file and slices it; backends
+    /// that support ranged reads should override this.
+    async fn read_range(&self, path: &str, offset: u64, len: u32) -> BackendResult<Bytes> {
+        let content = self.read_file(path).await?;
+        Ok(slice_range(&content, offset, len))
+    }
+
     /// Write file contents
     ///
     /// Creates or overwrites the file at `path` with `content`.
     async fn write_file(&self, path: &str, content: Bytes) -> BackendResult<()>;
+
+    /// Open a streaming writer for `path`
+    ///
+    /// Returns `None` (the default) when the backend only accepts whole
+    /// files through `write_file`, in which case uploads are buffered until
+    /// the handle is closed.
+    async fn open_writer(&self, _path: &str) -> BackendResult<Option<Box<dyn FileWriter>>> {
+        Ok(None)
+    }
+
+    /// Attributes [`Backend::set_attrs`] can change
+    ///
+    /// Requests touching anything else are refused before `set_attrs` is
+    /// called. Defaults to none.
+    fn attr_capabilities(&self) -> AttrCapabilities {
+        AttrCapabilities::NONE
+    }
+
+    /// Change attributes of the file or directory at `path`
+    ///
+    /// Only called with changes allowed by [`Backend::attr_capabilities`].
+    /// The default returns [`BackendError::Unsupported`].
+    async fn set_attrs(&self, _path: &str, _attrs: SetAttrs) -> BackendResult<()> {
+        Err(BackendError::Unsupported)
+    }
+
+    /// Get information about `path` itself, without following a final symlink
+    ///
+    /// [`Backend::file_info`] follows symlinks; this is its `lstat`
+    /// counterpart. Backends without links can keep the default, which
+    /// calls `file_info`.
+    async fn symlink_info(&self, path: &str) -> BackendResult<FileInfo> {
+        self.file_info(path).await
+    }
+
+    /// Target of the symlink at `path`, as it was given to [`Backend::symlink`]
+    ///
+    /// Absolute targets are paths in this backend, relative ones are
+    /// relative to the link's directory. The default returns
+    /// [`BackendError::Unsupported`].
+    async fn read_link(&self, _path: &str) -> BackendResult<String> {
+        Err(BackendError::Unsupported)
+    }
+
+    /// Create a symlink at `path` pointing to `target`
+    ///
+    /// The target does not need to exist. The default returns
+    /// [`BackendError::Unsupported`].
+    async fn symlink(&self, _path: &str, _target: &str) -> BackendResult<()> {
+        Err(BackendError::Unsupported)
+    }
+
+    /// Create `dst` as another name for the existing file `src`
+    ///
+    /// The default returns [`BackendError::Unsupported`].
+    async fn hard_link(&self, _src: &str, _dst: &str) -> BackendResult<()> {
+        Err(BackendError::Unsupported)
+    }
+
+    /// `path` with its symlinks resolved the way this backend follows them
+    ///
+    /// The last component is followed only if `follow_last`. Once a
+    /// component doesn't exist the rest is kept as given. Used to check
+    /// permissions against where a request really lands. The default walks
+    /// the path with [`Backend::symlink_info`] and [`Backend::read_link`].
+    async fn resolve_links(&self, path: &str, follow_last: bool) -> BackendResult<String> {
+        let components = |path: &str| -> Vec<String> {
+            path.split('/')
+                .filter(|c| !c.is_empty())
+                .rev()
+                .map(str::to_string)
+                .collect()
+        };
+        // Components still to visit, next one last
+        let mut pending = components(path);
+        let mut resolved = String::new();
+        let mut hops = 0;
+
+        while let Some(name) = pending.pop() {
+            let candidate = if resolved.is_empty() {
+                name
+            } else {
+                format!("{resolved}/{name}")
+            };
+            if follow_last || !pending.is_empty() {
+                match self.symlink_info(&candidate).await {
+                    Ok(info) if info.is_symlink() => {
+                        hops += 1;
+                        if hops > MAX_SYMLINK_HOPS {
+                            return Err(BackendError::SymlinkLoop);
+                        }
+                        let target = self.read_link(&candidate).await?;
+                        pending.extend(components(&link_target_path(&candidate, &target)));
+                        resolved.clear();
+                        continue;
+                    }
+                    Ok(_) => {}
+                    // Nothing below a missing component exists either
+                    Err(BackendError::NotFound) => {
+                        pending.push(candidate);
+                        pending.reverse();
+                        return Ok(pending.join("/"));
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            resolved = candidate;
+        }
+        Ok(resolved)
+    }
+
+    /// Size and free space of the storage holding `path`
+    ///
+    /// The default returns [`BackendError::Unsupported`].
+    async fn space_info(&self, _path: &str) -> BackendResult<SpaceInfo> {
+        Err(BackendError::Unsupported)
+    }
+
+    /// Copy the file at `src` to `dst`, replacing `dst` if it exists
+    ///
+    /// The default reads the whole file and writes it back. Override it when
+    /// the storage can copy without moving the data through the server.
+    async fn copy(&self, src: &str, dst: &str) -> BackendResult<()> {
+        let content = self.read_file(src).await?;
+        self.write_file(dst, content).await
+    }
+}
+
+/// Follow symlinks at the end of `path` until it names something else
+///
+/// Returns the final path and its info. Built on
+/// [`Backend::symlink_info`] and [`Backend::read_link`], for backends that
+/// store links themselves rather than relying on a filesystem.
+pub async fn follow_symlinks<B: Backend + ?Sized>(
+    backend: &B,
+    path: &str,
+) -> BackendResult<(String, FileInfo)> {
+    let mut path = normalize_path(path).into_owned();
+    for _ in 0..=MAX_SYMLINK_HOPS {
+        let info = backend.symlink_info(&path).await?;
+        if !info.is_symlink() {
+            return Ok((path, info));
+        }
+        let target = backend.read_link(&path).await?;
+        path = link_target_path(&path, &target);
+    }
+    Err(BackendError::SymlinkLoop)
+}
+
+/// Path a symlink at `link` pointing to `target` refers to
+///
+/// Relative targets are resolved against the link's directory. `..` stops
+/// at the root, as it does in a chroot.
+pub fn link_target_path(link: &str, target: &str) -> String {
+    let link = normalize_path(link);
+    let base = if target.starts_with('/') {
+        ""
+    } else {
+        link.rsplit_once('/').map_or("", |(parent, _)| parent)
+    };
+    let mut parts: Vec<&str> = Vec::new();
+    for component in base.split('/').chain(target.split('/')) {
+        match component {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            name => parts.push(name),
+        }
+    }
+    parts.join("/")
 }
 
 /// Normalize a path: trim leading/trailing slashes, handle empty as root.
@@ -165,6 +525,31 @@ pub fn normalize_path(path: &str) -> Cow<'_, str> {
     }
 }
 
+/// Resolve `.` and `..` components of a path lexically
+///
+/// Returns the normalized path without leading or trailing slashes, or
+/// `None` if a `..` component would climb above the root.
+pub fn resolve_path(path: &str) -> Option<String> {
+    let mut parts: Vec<&str> = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            name => parts.push(name),
+        }
+    }
+    Some(parts.join("/"))
+}
+
+/// Slice `len` bytes at `offset` out of `content`, clamped to its bounds
+pub(crate) fn slice_range(content: &Bytes, offset: u64, len: u32) -> Bytes {
+    let start = std::cmp::min(offset, content.len() as u64) as usize;
+    let end = std::cmp::min(start.saturating_add(len as usize), content.len());
+    content.slice(start..end)
+}
+
 /// Get current Unix timestamp
 pub fn current_timestamp() -> u32 {
     SystemTime::now()
@@ -1,16 +1,45 @@
 use super::{
-    current_timestamp, normalize_path, Backend, BackendError, BackendResult, DirEntry, FileInfo,
+    current_timestamp, follow_symlinks, normalize_path, AttrCapabilities, Backend, BackendError,
+    BackendResult, DirEntry, FileInfo, FileType, FileWriter, SetAttrs,
 };
 use async_trait::async_trait;
+use aws_sdk_s3::error::ProvideErrorMetadata;
 use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart, MetadataDirective};
 use aws_sdk_s3::Client;
-use bytes::Bytes;
-use std::collections::HashSet;
-use tracing::debug;
+use bytes::{Bytes, BytesMut};
+use std::collections::{HashMap, HashSet};
+use tracing::{debug, warn};
 
 /// Marker file for empty directories (matching Elixir implementation)
 const KEEP_MARKER: &str = ".keep";
 
+/// Object metadata key holding a modification time set by a client
+const META_MTIME: &str = "mtime";
+
+/// Object metadata key holding octal permission bits set by a client
+///
+/// Symlinks are objects whose body is the target and whose mode carries the
+/// `S_IFLNK` type bits, the same convention s3fs uses.
+const META_MODE: &str = "mode";
+
+/// Mode stored on symlink objects
+const SYMLINK_MODE: u32 = 0o120777;
+
+/// Smallest part size S3 accepts for all but the last part of a multipart upload
+pub const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// Largest object a single CopyObject request can copy
+const MAX_COPY_OBJECT_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+
+/// Part size for copying larger objects with UploadPartCopy
+///
+/// Large enough that the 5 TiB object limit stays under 10,000 parts.
+const COPY_PART_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Default multipart upload part size
+const DEFAULT_PART_SIZE: usize = 8 * 1024 * 1024;
+
 /// S3 storage backend configuration
 #[derive(Debug, Clone)]
 pub struct S3Config {
@@ -18,6 +47,8 @@ pub struct S3Config {
     pub bucket: String,
     /// Key prefix for all objects (optional, for multi-tenant setups)
     pub prefix: String,
+    /// Part size for multipart uploads
+    pub part_size: usize,
 }
 
 impl S3Config {
@@ -25,6 +56,7 @@ impl S3Config {
         Self {
             bucket: bucket.into(),
             prefix: String::new(),
+            part_size: DEFAULT_PART_SIZE,
         }
     }
 
@@ -32,6 +64,12 @@ impl S3Config {
         self.prefix = prefix.into();
         self
     }
+
+    /// Set the multipart upload part size (raised to S3's 5 MiB minimum)
+    pub fn with_part_size(mut self, part_size: usize) -> Self {
+        self.part_size = part_size.max(MIN_PART_SIZE);
+        self
+    }
 }
 
 /// S3 storage backend
@@ -99,6 +137,141 @@ impl S3Backend {
     fn parse_datetime(dt: &aws_sdk_s3::primitives::DateTime) -> u32 {
         dt.secs() as u32
     }
+
+    /// Copy an object over `dst_key` with multipart UploadPartCopy requests
+    ///
+    /// Needed past [`MAX_COPY_OBJECT_SIZE`]. The upload is aborted if any
+    /// part fails.
+    async fn multipart_copy(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        size: u64,
+        metadata: HashMap<String, String>,
+        content_type: Option<String>,
+    ) -> BackendResult<()> {
+        let upload_id = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.config.bucket)
+            .key(dst_key)
+            .set_metadata(Some(metadata))
+            .set_content_type(content_type)
+            .send()
+            .await
+            .map_err(Self::map_s3_error)?
+            .upload_id()
+            .ok_or_else(|| BackendError::Other("missing multipart upload id".into()))?
+            .to_string();
+
+        let copy_source = format!("{}/{}", self.config.bucket, src_key);
+        let mut parts = Vec::new();
+        let mut result = Ok(());
+        for (index, start) in (0..size).step_by(COPY_PART_SIZE as usize).enumerate() {
+            let end = std::cmp::min(start + COPY_PART_SIZE, size) - 1;
+            let part_number = index as i32 + 1;
+            debug!(key = %dst_key, part_number, start, end, "Copying part");
+            match self
+                .client
+                .upload_part_copy()
+                .bucket(&self.config.bucket)
+                .key(dst_key)
+                .upload_id(&upload_id)
+                .part_number(part_number)
+                .copy_source(&copy_source)
+                .copy_source_range(format!("bytes={start}-{end}"))
+                .send()
+                .await
+            {
+                Ok(output) => parts.push(
+                    CompletedPart::builder()
+                        .set_e_tag(
+                            output
+                                .copy_part_result()
+                                .and_then(|part| part.e_tag())
+                                .map(str::to_string),
+                        )
+                        .part_number(part_number)
+                        .build(),
+                ),
+                Err(err) => {
+                    result = Err(Self::map_s3_error(err));
+                    break;
+                }
+            }
+        }
+
+        if result.is_ok() {
+            debug!(key = %dst_key, parts = parts.len(), "Completing multipart copy");
+            result = self
+                .client
+                .complete_multipart_upload()
+                .bucket(&self.config.bucket)
+                .key(dst_key)
+                .upload_id(&upload_id)
+                .multipart_upload(
+                    CompletedMultipartUpload::builder()
+                        .set_parts(Some(parts))
+                        .build(),
+                )
+                .send()
+                .await
+                .map(|_| ())
+                .map_err(Self::map_s3_error);
+        }
+
+        if result.is_err() {
+            debug!(key = %dst_key, "Aborting multipart copy");
+            if let Err(err) = self
+                .client
+                .abort_multipart_upload()
+                .bucket(&self.config.bucket)
+                .key(dst_key)
+                .upload_id(&upload_id)
+                .send()
+                .await
+            {
+                warn!(error = %err, "Failed to abort multipart copy");
+            }
+        }
+        result
+    }
+
+    /// Read a range from the file a symlink points to
+    async fn read_link_range(&self, path: &str, offset: u64, len: u32) -> BackendResult<Bytes> {
+        let (target, _) = follow_symlinks(self, path).await?;
+        self.read_range(&target, offset, len).await
+    }
+
+    /// Mode stored in object metadata, if any
+    fn stored_mode(metadata: Option<&HashMap<String, String>>) -> Option<u32> {
+        metadata?
+            .get(META_MODE)
+            .and_then(|v| u32::from_str_radix(v, 8).ok())
+    }
+
+    /// Whether object metadata marks the object as a symlink
+    fn is_symlink(metadata: Option<&HashMap<String, String>>) -> bool {
+        Self::stored_mode(metadata).is_some_and(|mode| mode & 0o170000 == 0o120000)
+    }
+
+    /// Apply mtime and mode stored in object metadata over `info`
+    fn apply_metadata(mut info: FileInfo, metadata: Option<&HashMap<String, String>>) -> FileInfo {
+        let Some(metadata) = metadata else {
+            return info;
+        };
+        if let Some(mtime) = metadata.get(META_MTIME).and_then(|v| v.parse().ok()) {
+            info.mtime = mtime;
+            info.atime = mtime;
+        }
+        if let Some(mode) = Self::stored_mode(Some(metadata)) {
+            if Self::is_symlink(Some(metadata)) {
+                info.file_type = FileType::Symlink;
+            }
+            info.permissions = mode;
+        }
+        info
+    }
 }
 
 #[async_trait]
@@ -184,6 +357,10 @@ impl Backend for S3Backend {
     }
 
     async fn file_info(&self, path: &str) -> BackendResult<FileInfo> {
+        follow_symlinks(self, path).await.map(|(_, info)| info)
+    }
+
+    async fn symlink_info(&self, path: &str) -> BackendResult<FileInfo> {
         let normalized = normalize_path(path);
 
         // Root is always a directory
@@ -209,7 +386,8 @@ impl Backend for S3Backend {
                     .as_ref()
                     .map(Self::parse_datetime)
                     .unwrap_or_else(current_timestamp);
-                return Ok(FileInfo::file_with_mtime(size, mtime));
+                let info = FileInfo::file_with_mtime(size, mtime);
+                return Ok(Self::apply_metadata(info, result.metadata()));
             }
             Err(_) => {
                 // Not a file, check if it's a directory
@@ -317,6 +495,11 @@ impl Backend for S3Backend {
             .await
             .map_err(Self::map_s3_error)?;
 
+        if Self::is_symlink(result.metadata()) {
+            let (target, _) = follow_symlinks(self, path).await?;
+            return self.read_file(&target).await;
+        }
+
         let bytes = result
             .body
             .collect()
@@ -327,6 +510,49 @@ impl Backend for S3Backend {
         Ok(bytes) // No .to_vec() needed - already Bytes!
     }
 
+    async fn read_range(&self, path: &str, offset: u64, len: u32) -> BackendResult<Bytes> {
+        if len == 0 {
+            return Ok(Bytes::new());
+        }
+
+        let key = self.build_key(path);
+        let range = format!("bytes={}-{}", offset, offset.saturating_add(len as u64 - 1));
+
+        debug!(key = %key, range = %range, "Ranged S3 read");
+
+        let result = match self
+            .client
+            .get_object()
+            .bucket(&self.config.bucket)
+            .key(&key)
+            .range(range)
+            .send()
+            .await
+        {
+            Ok(result) if !Self::is_symlink(result.metadata()) => result,
+            // The range was taken from the link, not the file it points to
+            Ok(_) => return self.read_link_range(path, offset, len).await,
+            // Range starts past the end of the object. A link's body is only
+            // its target, so the file it points to may still have data there.
+            Err(err) if err.code() == Some("InvalidRange") => {
+                if self.symlink_info(path).await?.is_symlink() {
+                    return self.read_link_range(path, offset, len).await;
+                }
+                return Ok(Bytes::new());
+            }
+            Err(err) => return Err(Self::map_s3_error(err)),
+        };
+
+        let bytes = result
+            .body
+            .collect()
+            .await
+            .map_err(|e| BackendError::Io(e.to_string()))?
+            .into_bytes();
+
+        Ok(bytes)
+    }
+
     async fn write_file(&self, path: &str, content: Bytes) -> BackendResult<()> {
         let key = self.build_key(path);
 
@@ -341,4 +567,547 @@ impl Backend for S3Backend {
 
         Ok(())
     }
+
+    async fn open_writer(&self, path: &str) -> BackendResult<Option<Box<dyn FileWriter>>> {
+        Ok(Some(Box::new(S3Writer {
+            client: self.client.clone(),
+            bucket: self.config.bucket.clone(),
+            key: self.build_key(path),
+            part_size: self.config.part_size,
+            buffer: BytesMut::new(),
+            upload_id: None,
+            parts: Vec::new(),
+            finished: false,
+        })))
+    }
+
+    /// Mode and mtime, kept in object metadata
+    ///
+    /// Directory listings come from ListObjectsV2, which has no metadata, so
+    /// they keep showing the upload time; `stat` shows the stored values.
+    /// atime is accepted but not kept.
+    fn attr_capabilities(&self) -> AttrCapabilities {
+        AttrCapabilities {
+            permissions: true,
+            times: true,
+            ..AttrCapabilities::NONE
+        }
+    }
+
+    async fn set_attrs(&self, path: &str, attrs: SetAttrs) -> BackendResult<()> {
+        let key = self.build_key(path);
+
+        let head = match self
+            .client
+            .head_object()
+            .bucket(&self.config.bucket)
+            .key(&key)
+            .send()
+            .await
+        {
+            Ok(head) => head,
+            // Directories are only prefixes and have nowhere to keep metadata
+            Err(_) => {
+                self.file_info(path).await?;
+                return Err(BackendError::Unsupported);
+            }
+        };
+
+        if Self::is_symlink(head.metadata()) {
+            let (target, _) = follow_symlinks(self, path).await?;
+            return self.set_attrs(&target, attrs).await;
+        }
+
+        let mut metadata = head.metadata().cloned().unwrap_or_default();
+        if let Some(mtime) = attrs.mtime {
+            metadata.insert(META_MTIME.to_string(), mtime.to_string());
+        }
+        if let Some(mode) = attrs.permissions {
+            metadata.insert(META_MODE.to_string(), format!("{:o}", mode & 0o777));
+        }
+
+        let content_type = head.content_type().map(str::to_string);
+        let size = head.content_length.unwrap_or(0) as u64;
+
+        debug!(key = %key, ?attrs, "Updating object metadata");
+
+        // Metadata can only be replaced by copying the object onto itself
+        if size > MAX_COPY_OBJECT_SIZE {
+            return self
+                .multipart_copy(&key, &key, size, metadata, content_type)
+                .await;
+        }
+
+        self.client
+            .copy_object()
+            .bucket(&self.config.bucket)
+            .copy_source(format!("{}/{}", self.config.bucket, key))
+            .key(&key)
+            .metadata_directive(MetadataDirective::Replace)
+            .set_metadata(Some(metadata))
+            .set_content_type(content_type)
+            .send()
+            .await
+            .map_err(Self::map_s3_error)?;
+
+        Ok(())
+    }
+
+    async fn read_link(&self, path: &str) -> BackendResult<String> {
+        let key = self.build_key(path);
+
+        let result = match self
+            .client
+            .get_object()
+            .bucket(&self.config.bucket)
+            .key(&key)
+            .send()
+            .await
+        {
+            Ok(result) => result,
+            // A directory, or nothing at all
+            Err(_) => {
+                self.symlink_info(path).await?;
+                return Err(BackendError::NotASymlink);
+            }
+        };
+        if !Self::is_symlink(result.metadata()) {
+            return Err(BackendError::NotASymlink);
+        }
+
+        let target = result
+            .body
+            .collect()
+            .await
+            .map_err(|e| BackendError::Io(e.to_string()))?
+            .into_bytes();
+        String::from_utf8(target.to_vec()).map_err(|e| BackendError::Other(e.to_string()))
+    }
+
+    /// Store a link object holding the target
+    ///
+    /// Listings come from ListObjectsV2 and show links as small files; `stat`
+    /// and `lstat` tell them apart. Only the last component of a path is
+    /// followed, so links to directories can't be traversed.
+    async fn symlink(&self, path: &str, target: &str) -> BackendResult<()> {
+        match self.symlink_info(path).await {
+            Ok(_) => return Err(BackendError::AlreadyExists),
+            Err(BackendError::NotFound) => {}
+            Err(err) => return Err(err),
+        }
+
+        let key = self.build_key(path);
+        debug!(key = %key, target, "Creating symlink object");
+
+        self.client
+            .put_object()
+            .bucket(&self.config.bucket)
+            .key(&key)
+            .metadata(META_MODE, format!("{SYMLINK_MODE:o}"))
+            .body(ByteStream::from(Bytes::copy_from_slice(target.as_bytes())))
+            .send()
+            .await
+            .map_err(Self::map_s3_error)?;
+
+        Ok(())
+    }
+
+    /// Only a final symlink is followed, matching how paths are read
+    async fn resolve_links(&self, path: &str, follow_last: bool) -> BackendResult<String> {
+        if follow_last {
+            match follow_symlinks(self, path).await {
+                Ok((resolved, _)) => return Ok(resolved),
+                Err(BackendError::NotFound) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(normalize_path(path).into_owned())
+    }
+
+    /// Copied inside S3 with CopyObject, or UploadPartCopy for objects too
+    /// large for a single copy
+    ///
+    /// Symlinks are followed. The copy keeps the mode but not the mtime
+    /// stored in metadata, so it shows when it was made.
+    async fn copy(&self, src: &str, dst: &str) -> BackendResult<()> {
+        let (source, info) = follow_symlinks(self, src).await?;
+        if info.is_dir {
+            return Err(BackendError::IsADirectory);
+        }
+        let src_key = self.build_key(&source);
+        let dst_key = self.build_key(dst);
+
+        let head = self
+            .client
+            .head_object()
+            .bucket(&self.config.bucket)
+            .key(&src_key)
+            .send()
+            .await
+            .map_err(Self::map_s3_error)?;
+        let mut metadata = head.metadata().cloned().unwrap_or_default();
+        metadata.remove(META_MTIME);
+        let content_type = head.content_type().map(str::to_string);
+
+        debug!(from = %src_key, to = %dst_key, size = info.size, "Copying object");
+
+        if info.size > MAX_COPY_OBJECT_SIZE {
+            return self
+                .multipart_copy(&src_key, &dst_key, info.size, metadata, content_type)
+                .await;
+        }
+
+        self.client
+            .copy_object()
+            .bucket(&self.config.bucket)
+            .copy_source(format!("{}/{}", self.config.bucket, src_key))
+            .key(&dst_key)
+            .metadata_directive(MetadataDirective::Replace)
+            .set_metadata(Some(metadata))
+            .set_content_type(content_type)
+            .send()
+            .await
+            .map_err(Self::map_s3_error)?;
+
+        Ok(())
+    }
+}
+
+/// Streaming writer backed by an S3 multipart upload
+///
+/// The multipart upload is only created once a full part has been buffered,
+/// so files smaller than one part are written with a single PutObject.
+/// Dropping the writer before commit aborts the multipart upload.
+struct S3Writer {
+    client: Client,
+    bucket: String,
+    key: String,
+    part_size: usize,
+    buffer: BytesMut,
+    upload_id: Option<String>,
+    parts: Vec<CompletedPart>,
+    finished: bool,
+}
+
+impl S3Writer {
+    /// Upload `data` as the next part, creating the multipart upload if needed
+    async fn upload_part(&mut self, data: Bytes) -> BackendResult<()> {
+        let upload_id = match self.upload_id {
+            Some(ref id) => id.clone(),
+            None => {
+                debug!(key = %self.key, "Creating multipart upload");
+                let result = self
+                    .client
+                    .create_multipart_upload()
+                    .bucket(&self.bucket)
+                    .key(&self.key)
+                    .send()
+                    .await
+                    .map_err(S3Backend::map_s3_error)?;
+                let id = result
+                    .upload_id()
+                    .ok_or_else(|| BackendError::Other("missing multipart upload id".into()))?
+                    .to_string();
+                self.upload_id = Some(id.clone());
+                id
+            }
+        };
+
+        let part_number = self.parts.len() as i32 + 1;
+        debug!(key = %self.key, part_number, len = data.len(), "Uploading part");
+
+        let result = self
+            .client
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .body(ByteStream::from(data))
+            .send()
+            .await
+            .map_err(S3Backend::map_s3_error)?;
+
+        self.parts.push(
+            CompletedPart::builder()
+                .set_e_tag(result.e_tag().map(str::to_string))
+                .part_number(part_number)
+                .build(),
+        );
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl FileWriter for S3Writer {
+    async fn write(&mut self, data: Bytes) -> BackendResult<()> {
+        self.buffer.extend_from_slice(&data);
+        while self.buffer.len() >= self.part_size {
+            let part = self.buffer.split_to(self.part_size).freeze();
+            self.upload_part(part).await?;
+        }
+        Ok(())
+    }
+
+    async fn commit(mut self: Box<Self>) -> BackendResult<()> {
+        let remaining = self.buffer.split().freeze();
+
+        let Some(upload_id) = self.upload_id.clone() else {
+            // Never reached a full part, a single PutObject is enough
+            self.client
+                .put_object()
+                .bucket(&self.bucket)
+                .key(&self.key)
+                .body(ByteStream::from(remaining))
+                .send()
+                .await
+                .map_err(S3Backend::map_s3_error)?;
+            self.finished = true;
+            return Ok(());
+        };
+
+        if !remaining.is_empty() {
+            self.upload_part(remaining).await?;
+        }
+
+        debug!(key = %self.key, parts = self.parts.len(), "Completing multipart upload");
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(std::mem::take(&mut self.parts)))
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(S3Backend::map_s3_error)?;
+
+        self.finished = true;
+        Ok(())
+    }
+
+    async fn abort(mut self: Box<Self>) -> BackendResult<()> {
+        self.finished = true;
+        if let Some(upload_id) = self.upload_id.take() {
+            debug!(key = %self.key, "Aborting multipart upload");
+            self.client
+                .abort_multipart_upload()
+                .bucket(&self.bucket)
+                .key(&self.key)
+                .upload_id(upload_id)
+                .send()
+                .await
+                .map_err(S3Backend::map_s3_error)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for S3Writer {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        let Some(upload_id) = self.upload_id.take() else {
+            return;
+        };
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            warn!(key = %self.key, upload_id = %upload_id, "Leaking multipart upload, no runtime to abort it");
+            return;
+        };
+
+        debug!(key = %self.key, "Aborting abandoned multipart upload");
+        let request = self
+            .client
+            .abort_multipart_upload()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .upload_id(upload_id);
+        runtime.spawn(async move {
+            if let Err(err) = request.send().await {
+                warn!(error = %err, "Failed to abort multipart upload");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::config::http::{HttpRequest, HttpResponse};
+    use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region, RuntimeComponents};
+    use aws_smithy_runtime_api::client::http::{
+        HttpClient, HttpConnector, HttpConnectorFuture, HttpConnectorSettings, SharedHttpConnector,
+    };
+    use aws_smithy_types::body::SdkBody;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    const BUCKET: &str = "bucket";
+
+    /// Object bodies and stored modes by key
+    type Objects = HashMap<String, (Bytes, Option<u32>)>;
+
+    /// In-memory stand-in for the HEAD and GET object calls
+    #[derive(Debug, Clone, Default)]
+    struct FakeS3 {
+        objects: Arc<Mutex<Objects>>,
+    }
+
+    impl FakeS3 {
+        fn put(&self, key: &str, body: impl Into<Bytes>, mode: Option<u32>) {
+            self.objects
+                .lock()
+                .insert(key.to_string(), (body.into(), mode));
+        }
+
+        fn backend(&self) -> S3Backend {
+            let config = aws_sdk_s3::Config::builder()
+                .behavior_version(BehaviorVersion::latest())
+                .region(Region::new("us-east-1"))
+                .credentials_provider(Credentials::new("test", "test", None, None, "test"))
+                .endpoint_url("http://s3.test")
+                .force_path_style(true)
+                .http_client(self.clone())
+                .build();
+            S3Backend::new(Client::from_conf(config), S3Config::new(BUCKET))
+        }
+
+        fn respond(&self, request: &HttpRequest) -> HttpResponse {
+            let uri = request.uri();
+            let path = uri
+                .split_once("://")
+                .and_then(|(_, rest)| rest.find('/').map(|i| &rest[i..]))
+                .unwrap_or("/");
+            let path = path.split('?').next().unwrap_or_default();
+            let key = path
+                .strip_prefix(&format!("/{BUCKET}/"))
+                .unwrap_or_default();
+
+            let objects = self.objects.lock();
+            let Some((body, mode)) = objects.get(key) else {
+                return Self::error(404, "NoSuchKey");
+            };
+            let len = body.len() as u64;
+            let (status, body) = match request.headers().get("range") {
+                Some(range) if request.method() == "GET" => {
+                    let (start, end) = range
+                        .strip_prefix("bytes=")
+                        .and_then(|r| r.split_once('-'))
+                        .map(|(s, e)| (s.parse::<u64>().unwrap(), e.parse::<u64>().unwrap()))
+                        .unwrap();
+                    if start >= len {
+                        return Self::error(416, "InvalidRange");
+                    }
+                    let end = end.min(len - 1);
+                    (206, body.slice(start as usize..=end as usize))
+                }
+                _ => (200, body.clone()),
+            };
+
+            let mut response = HttpResponse::new(
+                status.try_into().unwrap(),
+                if request.method() == "HEAD" {
+                    SdkBody::empty()
+                } else {
+                    SdkBody::from(body.clone())
+                },
+            );
+            let headers = response.headers_mut();
+            headers.insert("content-length", body.len().to_string());
+            if status == 206 {
+                headers.insert(
+                    "content-range",
+                    format!("bytes {}-{}/{len}", len - body.len() as u64, len - 1),
+                );
+            }
+            if let Some(mode) = mode {
+                headers.insert("x-amz-meta-mode", format!("{mode:o}"));
+            }
+            response
+        }
+
+        fn error(status: u16, code: &str) -> HttpResponse {
+            let body = format!("<Error><Code>{code}</Code><Message>{code}</Message></Error>");
+            HttpResponse::new(status.try_into().unwrap(), SdkBody::from(body))
+        }
+    }
+
+    impl HttpConnector for FakeS3 {
+        fn call(&self, request: HttpRequest) -> HttpConnectorFuture {
+            HttpConnectorFuture::ready(Ok(self.respond(&request)))
+        }
+    }
+
+    impl HttpClient for FakeS3 {
+        fn http_connector(
+            &self,
+            _settings: &HttpConnectorSettings,
+            _components: &RuntimeComponents,
+        ) -> SharedHttpConnector {
+            SharedHttpConnector::new(self.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_range_follows_link_past_its_body() {
+        let s3 = FakeS3::default();
+        let content: Vec<u8> = (0..100).collect();
+        s3.put("data.bin", content.clone(), None);
+        s3.put("link", "data.bin", Some(SYMLINK_MODE));
+        let backend = s3.backend();
+
+        // The link body is 8 bytes, the file it points to is 100
+        let data = backend.read_range("link", 50, 10).await.unwrap();
+        assert_eq!(&data[..], &content[50..60]);
+        let data = backend.read_range("link", 2, 4).await.unwrap();
+        assert_eq!(&data[..], &content[2..6]);
+
+        assert!(backend
+            .read_range("link", 100, 10)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(backend
+            .read_range("data.bin", 200, 10)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_read_range_at_end_of_offsets() {
+        let s3 = FakeS3::default();
+        s3.put("data.bin", vec![1u8; 10], None);
+        let backend = s3.backend();
+
+        let data = backend.read_range("data.bin", u64::MAX, 10).await.unwrap();
+        assert!(data.is_empty());
+        let data = backend.read_range("data.bin", 4, 0).await.unwrap();
+        assert!(data.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_posix_rename_not_offered() {
+        use crate::sftp_handler::SftpHandler;
+        use russh_sftp::protocol::StatusCode;
+        use russh_sftp::server::Handler;
+
+        let backend = FakeS3::default().backend();
+        assert!(!backend.atomic_rename());
+
+        let mut handler = SftpHandler::new(Arc::new(backend));
+        let version = handler.init(3, HashMap::new()).await.unwrap();
+        assert!(!version.extensions.contains_key("posix-rename@openssh.com"));
+        assert_eq!(
+            handler
+                .extended(1, "posix-rename@openssh.com".into(), Vec::new())
+                .await
+                .unwrap_err(),
+            StatusCode::OpUnsupported
+        );
+    }
 }
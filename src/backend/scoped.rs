@@ -0,0 +1,315 @@
+use super::{
+    resolve_path, AttrCapabilities, Backend, BackendError, BackendResult, DirEntry, FileInfo,
+    FileWriter, SetAttrs, SpaceInfo,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::sync::Arc;
+use tracing::debug;
+
+/// Backend wrapper that re-roots every path under a fixed prefix
+///
+/// Used to confine a user to their home directory: `/` maps to `root` in the
+/// inner backend, and `..` components may not climb above it.
+pub struct ScopedBackend<B: Backend + ?Sized> {
+    inner: Arc<B>,
+    root: String,
+}
+
+impl<B: Backend + ?Sized> ScopedBackend<B> {
+    /// Wrap `inner` so all paths resolve beneath `root`
+    ///
+    /// An empty root, or `/`, is the whole inner backend. Roots with `.` or
+    /// `..` components are refused with [`BackendError::PermissionDenied`]
+    /// instead of being resolved, so a home built from a username like `..`
+    /// can't widen the scope.
+    pub fn new(inner: Arc<B>, root: impl AsRef<str>) -> BackendResult<Self> {
+        let root = root.as_ref();
+        if root.split('/').any(|part| part == "." || part == "..") {
+            return Err(BackendError::PermissionDenied);
+        }
+        let root = resolve_path(root).ok_or(BackendError::PermissionDenied)?;
+        Ok(Self { inner, root })
+    }
+
+    /// Root prefix in the inner backend
+    pub fn root(&self) -> &str {
+        &self.root
+    }
+
+    /// Create the root directory (and its parents) if it does not exist yet
+    pub async fn create_root(&self) -> BackendResult<()> {
+        let mut current = String::new();
+        for part in self.root.split('/').filter(|p| !p.is_empty()) {
+            if !current.is_empty() {
+                current.push('/');
+            }
+            current.push_str(part);
+
+            match self.inner.file_info(&current).await {
+                Ok(info) if info.is_dir => continue,
+                Ok(_) => return Err(BackendError::NotADirectory),
+                Err(BackendError::NotFound) => {
+                    debug!(path = %current, "Creating home directory");
+                    match self.inner.make_dir(&current).await {
+                        Ok(()) | Err(BackendError::AlreadyExists) => {}
+                        Err(err) => return Err(err),
+                    }
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Map a path in the scoped view to a path in the inner backend
+    fn scoped(&self, path: &str) -> BackendResult<String> {
+        let resolved = resolve_path(path).ok_or(BackendError::PermissionDenied)?;
+        Ok(if self.root.is_empty() {
+            resolved
+        } else if resolved.is_empty() {
+            self.root.clone()
+        } else {
+            format!("{}/{}", self.root, resolved)
+        })
+    }
+
+    /// Map the target of a symlink at `path` to one for the inner backend
+    ///
+    /// Targets are resolved against the link's directory and stored as
+    /// absolute paths, so following them in the inner backend can't climb
+    /// out of the root with `..`.
+    fn scoped_target(&self, path: &str, target: &str) -> BackendResult<String> {
+        if self.root.is_empty() {
+            return Ok(target.to_string());
+        }
+        let resolved = if target.starts_with('/') {
+            resolve_path(target)
+        } else {
+            let link = resolve_path(path).ok_or(BackendError::PermissionDenied)?;
+            let parent = link.rsplit_once('/').map_or("", |(parent, _)| parent);
+            resolve_path(&format!("{parent}/{target}"))
+        };
+        let resolved = resolved.ok_or(BackendError::PermissionDenied)?;
+        Ok(format!("/{}", self.scoped(&resolved)?))
+    }
+
+    /// Map a symlink target read from the inner backend to the scoped view
+    fn unscoped_target(&self, target: String) -> BackendResult<String> {
+        if self.root.is_empty() || !target.starts_with('/') {
+            return Ok(target);
+        }
+        let resolved = resolve_path(&target).ok_or(BackendError::PermissionDenied)?;
+        match resolved.strip_prefix(&self.root) {
+            Some("") => Ok("/".to_string()),
+            Some(rest) if rest.starts_with('/') => Ok(rest.to_string()),
+            // Points outside of this scope
+            _ => Err(BackendError::PermissionDenied),
+        }
+    }
+}
+
+#[async_trait]
+impl<B: Backend + ?Sized> Backend for ScopedBackend<B> {
+    async fn list_dir(&self, path: &str) -> BackendResult<Vec<DirEntry>> {
+        self.inner.list_dir(&self.scoped(path)?).await
+    }
+
+    async fn file_info(&self, path: &str) -> BackendResult<FileInfo> {
+        self.inner.file_info(&self.scoped(path)?).await
+    }
+
+    async fn make_dir(&self, path: &str) -> BackendResult<()> {
+        self.inner.make_dir(&self.scoped(path)?).await
+    }
+
+    async fn del_dir(&self, path: &str) -> BackendResult<()> {
+        self.inner.del_dir(&self.scoped(path)?).await
+    }
+
+    async fn delete(&self, path: &str) -> BackendResult<()> {
+        self.inner.delete(&self.scoped(path)?).await
+    }
+
+    async fn rename(&self, src: &str, dst: &str) -> BackendResult<()> {
+        self.inner
+            .rename(&self.scoped(src)?, &self.scoped(dst)?)
+            .await
+    }
+
+    fn atomic_rename(&self) -> bool {
+        self.inner.atomic_rename()
+    }
+
+    async fn read_file(&self, path: &str) -> BackendResult<Bytes> {
+        self.inner.read_file(&self.scoped(path)?).await
+    }
+
+    async fn read_range(&self, path: &str, offset: u64, len: u32) -> BackendResult<Bytes> {
+        self.inner
+            .read_range(&self.scoped(path)?, offset, len)
+            .await
+    }
+
+    async fn write_file(&self, path: &str, content: Bytes) -> BackendResult<()> {
+        self.inner.write_file(&self.scoped(path)?, content).await
+    }
+
+    async fn open_writer(&self, path: &str) -> BackendResult<Option<Box<dyn FileWriter>>> {
+        self.inner.open_writer(&self.scoped(path)?).await
+    }
+
+    fn attr_capabilities(&self) -> AttrCapabilities {
+        self.inner.attr_capabilities()
+    }
+
+    async fn set_attrs(&self, path: &str, attrs: SetAttrs) -> BackendResult<()> {
+        self.inner.set_attrs(&self.scoped(path)?, attrs).await
+    }
+
+    async fn symlink_info(&self, path: &str) -> BackendResult<FileInfo> {
+        self.inner.symlink_info(&self.scoped(path)?).await
+    }
+
+    async fn read_link(&self, path: &str) -> BackendResult<String> {
+        let target = self.inner.read_link(&self.scoped(path)?).await?;
+        self.unscoped_target(target)
+    }
+
+    async fn symlink(&self, path: &str, target: &str) -> BackendResult<()> {
+        let target = self.scoped_target(path, target)?;
+        self.inner.symlink(&self.scoped(path)?, &target).await
+    }
+
+    async fn hard_link(&self, src: &str, dst: &str) -> BackendResult<()> {
+        self.inner
+            .hard_link(&self.scoped(src)?, &self.scoped(dst)?)
+            .await
+    }
+
+    async fn resolve_links(&self, path: &str, follow_last: bool) -> BackendResult<String> {
+        let resolved = self
+            .inner
+            .resolve_links(&self.scoped(path)?, follow_last)
+            .await?;
+        let unscoped = self.unscoped_target(format!("/{resolved}"))?;
+        Ok(unscoped.trim_start_matches('/').to_string())
+    }
+
+    async fn space_info(&self, path: &str) -> BackendResult<SpaceInfo> {
+        self.inner.space_info(&self.scoped(path)?).await
+    }
+
+    async fn copy(&self, src: &str, dst: &str) -> BackendResult<()> {
+        self.inner
+            .copy(&self.scoped(src)?, &self.scoped(dst)?)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+
+    #[tokio::test]
+    async fn test_paths_are_rerooted() {
+        let inner = Arc::new(MemoryBackend::new());
+        let scoped = ScopedBackend::new(inner.clone(), "tenants/alice/").unwrap();
+
+        scoped
+            .write_file("/docs/a.txt", Bytes::from_static(b"data"))
+            .await
+            .unwrap();
+
+        let read = inner.read_file("tenants/alice/docs/a.txt").await.unwrap();
+        assert_eq!(read.as_ref(), b"data");
+
+        let entries = scoped.list_dir("/").await.unwrap();
+        assert!(entries.iter().any(|e| e.name == "docs"));
+    }
+
+    #[tokio::test]
+    async fn test_cannot_escape_root() {
+        let inner = Arc::new(MemoryBackend::new());
+        inner
+            .write_file("tenants/bob/secret.txt", Bytes::from_static(b"secret"))
+            .await
+            .unwrap();
+        let scoped = ScopedBackend::new(inner, "tenants/alice").unwrap();
+
+        let result = scoped.read_file("../bob/secret.txt").await;
+        assert!(matches!(result, Err(BackendError::PermissionDenied)));
+
+        let result = scoped.rename("/x", "/../../y").await;
+        assert!(matches!(result, Err(BackendError::PermissionDenied)));
+    }
+
+    #[test]
+    fn test_root_may_not_climb() {
+        let inner = Arc::new(MemoryBackend::new());
+        for root in ["tenants/..", "tenants/../..", "tenants/.", "../x"] {
+            assert!(
+                matches!(
+                    ScopedBackend::new(inner.clone(), root),
+                    Err(BackendError::PermissionDenied)
+                ),
+                "{root}"
+            );
+        }
+        assert_eq!(ScopedBackend::new(inner.clone(), "/").unwrap().root(), "");
+        assert_eq!(
+            ScopedBackend::new(inner, "/tenants//alice/")
+                .unwrap()
+                .root(),
+            "tenants/alice"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_symlink_targets_stay_in_root() {
+        let inner = Arc::new(MemoryBackend::new());
+        inner
+            .write_file("tenants/bob/secret.txt", Bytes::from_static(b"secret"))
+            .await
+            .unwrap();
+        let scoped = ScopedBackend::new(inner.clone(), "tenants/alice").unwrap();
+        scoped
+            .write_file("/docs/a.txt", Bytes::from_static(b"data"))
+            .await
+            .unwrap();
+
+        scoped.symlink("/docs/link", "a.txt").await.unwrap();
+        scoped.symlink("/top", "/docs/a.txt").await.unwrap();
+        assert_eq!(
+            inner.read_link("tenants/alice/docs/link").await.unwrap(),
+            "/tenants/alice/docs/a.txt"
+        );
+        assert_eq!(scoped.read_link("/top").await.unwrap(), "/docs/a.txt");
+        assert_eq!(
+            scoped.read_file("/docs/link").await.unwrap().as_ref(),
+            b"data"
+        );
+
+        let result = scoped.symlink("/docs/up", "../../../bob/secret.txt").await;
+        assert!(matches!(result, Err(BackendError::PermissionDenied)));
+
+        // Links pointing outside the scope aren't revealed
+        inner
+            .symlink("tenants/alice/out", "/tenants/bob/secret.txt")
+            .await
+            .unwrap();
+        let result = scoped.read_link("/out").await;
+        assert!(matches!(result, Err(BackendError::PermissionDenied)));
+    }
+
+    #[tokio::test]
+    async fn test_create_root() {
+        let inner = Arc::new(MemoryBackend::new());
+        let scoped = ScopedBackend::new(inner.clone(), "tenants/carol").unwrap();
+        scoped.create_root().await.unwrap();
+
+        let info = inner.file_info("tenants/carol").await.unwrap();
+        assert!(info.is_dir);
+    }
+}
@@ -0,0 +1,1129 @@
+//! Configuration file describing a whole server
+//!
+//! Everything the command-line flags can set, plus several users with their
+//! own credentials, backends, home directories and permissions. Files ending
+//! in `.yaml` or `.yml` are read as YAML, anything else as TOML.
+//!
+//! ```toml
+//! [server]
+//! bind = ["0.0.0.0:2222", "[::]:2222"]
+//! host_keys = ["/etc/sftp/ssh_host_ed25519_key"]
+//!
+//! [limits]
+//! max_connections = 100
+//!
+//! [backends.archive]
+//! type = "s3"
+//! bucket = "archive"
+//!
+//! [backends.scratch]
+//! type = "local"
+//! root = "/srv/scratch"
+//!
+//! [[users]]
+//! name = "alice"
+//! password_hash = "$argon2id$v=19$m=19456,t=2,p=1$..."
+//! backend = "archive"
+//! home = "tenants/alice"
+//!
+//! [[users]]
+//! name = "ci"
+//! authorized_keys = ["ssh-ed25519 AAAA... ci@build"]
+//! backend = "scratch"
+//! read_only = true
+//! ```
+
+use crate::audit::{JsonLinesSink, TracingSink};
+use crate::auth::password::{check_user_password, is_supported_hash};
+use crate::auth::{AuthContext, Authenticator, AuthorizedKey, AuthorizedKeys};
+use crate::backend::{Backend, BackendError, BackendFactory, BackendResult};
+use crate::events::{CommandHook, EventDelivery, WebhookHook};
+use crate::handle::UnflushedWrites;
+use crate::limits::BanPolicy;
+use crate::permissions::{Operation, PermissionPolicy, PolicyError, PolicyFile, Rule};
+use crate::server::{Server, ServerConfig};
+use crate::{LocalBackend, MemoryBackend};
+use async_trait::async_trait;
+use russh::keys::PublicKey;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Errors loading or applying a configuration file
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("could not read {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("invalid TOML: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("invalid YAML: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("could not load host key {0}: {1}")]
+    HostKey(PathBuf, russh::keys::Error),
+    #[error("invalid permissions: {0}")]
+    Permissions(#[from] PolicyError),
+    #[error("{0}")]
+    Invalid(String),
+}
+
+fn invalid(message: impl Into<String>) -> ConfigError {
+    ConfigError::Invalid(message.into())
+}
+
+/// A parsed and validated configuration file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    server: ServerSection,
+    #[serde(default)]
+    limits: LimitsSection,
+    #[serde(default)]
+    auth: AuthSection,
+    #[serde(default)]
+    audit: AuditSection,
+    #[serde(default)]
+    events: EventsSection,
+    #[serde(default)]
+    backends: BTreeMap<String, BackendSection>,
+    #[serde(default)]
+    users: Vec<UserSection>,
+    #[serde(default)]
+    permissions: PolicyFile,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ServerSection {
+    #[serde(default = "default_port")]
+    port: u16,
+    #[serde(default)]
+    bind: Vec<SocketAddr>,
+    /// Host key files; a key is generated when empty
+    #[serde(default)]
+    host_keys: Vec<PathBuf>,
+    #[serde(default = "default_shutdown_timeout")]
+    shutdown_timeout: u64,
+    spill_threshold: Option<usize>,
+    max_file_size: Option<u64>,
+    #[serde(default)]
+    commit_unclosed_uploads: bool,
+    #[serde(default)]
+    lenient_setstat: bool,
+    /// Backend for listed users without one, and the only way users not
+    /// listed in `users` are let in
+    default_backend: Option<String>,
+    /// Home for users not listed in `users`; `%u` is the login name
+    default_home: Option<String>,
+    metrics_addr: Option<SocketAddr>,
+}
+
+impl Default for ServerSection {
+    fn default() -> Self {
+        Self {
+            port: default_port(),
+            bind: Vec::new(),
+            host_keys: Vec::new(),
+            shutdown_timeout: default_shutdown_timeout(),
+            spill_threshold: None,
+            max_file_size: None,
+            commit_unclosed_uploads: false,
+            lenient_setstat: false,
+            default_backend: None,
+            default_home: None,
+            metrics_addr: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LimitsSection {
+    max_connections: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    #[serde(default = "default_max_auth_attempts")]
+    max_auth_attempts: usize,
+    /// Seconds without traffic before a connection is closed; 0 disables
+    #[serde(default = "default_idle_timeout")]
+    idle_timeout: u64,
+    max_session_duration: Option<u64>,
+    keepalive_interval: Option<u64>,
+    #[serde(default = "default_keepalive_max")]
+    keepalive_max: usize,
+    ban: Option<BanSection>,
+}
+
+impl Default for LimitsSection {
+    fn default() -> Self {
+        Self {
+            max_connections: None,
+            max_connections_per_ip: None,
+            max_auth_attempts: default_max_auth_attempts(),
+            idle_timeout: default_idle_timeout(),
+            max_session_duration: None,
+            keepalive_interval: None,
+            keepalive_max: default_keepalive_max(),
+            ban: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BanSection {
+    after: usize,
+    #[serde(default = "default_ban_time")]
+    find_time: u64,
+    #[serde(default = "default_ban_time")]
+    ban_time: u64,
+}
+
+/// Credentials shared by every user, in addition to those in `users`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AuthSection {
+    password_file: Option<PathBuf>,
+    authorized_keys_file: Option<String>,
+    trusted_user_ca_keys: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AuditSection {
+    log: Option<PathBuf>,
+    #[serde(default)]
+    tracing: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EventsSection {
+    webhook: Option<String>,
+    command: Option<String>,
+    #[serde(default = "default_queue_size")]
+    queue_size: usize,
+    #[serde(default = "default_max_attempts")]
+    max_attempts: usize,
+    #[serde(default = "default_retry_delay")]
+    retry_delay: u64,
+}
+
+impl Default for EventsSection {
+    fn default() -> Self {
+        Self {
+            webhook: None,
+            command: None,
+            queue_size: default_queue_size(),
+            max_attempts: default_max_attempts(),
+            retry_delay: default_retry_delay(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum BackendSection {
+    Local {
+        root: PathBuf,
+        /// Let clients change file owners
+        #[serde(default)]
+        allow_chown: bool,
+    },
+    S3 {
+        bucket: String,
+        #[serde(default)]
+        prefix: String,
+        endpoint: Option<String>,
+        region: Option<String>,
+    },
+    Memory,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UserSection {
+    name: String,
+    /// bcrypt, argon2id or sha512-crypt hash
+    password_hash: Option<String>,
+    /// authorized_keys lines, options included
+    #[serde(default)]
+    authorized_keys: Vec<String>,
+    backend: Option<String>,
+    /// Directory within the backend the user sees as `/`
+    home: Option<String>,
+    #[serde(default)]
+    read_only: bool,
+}
+
+fn default_port() -> u16 {
+    2222
+}
+
+fn default_shutdown_timeout() -> u64 {
+    30
+}
+
+fn default_max_auth_attempts() -> usize {
+    6
+}
+
+fn default_idle_timeout() -> u64 {
+    600
+}
+
+fn default_keepalive_max() -> usize {
+    3
+}
+
+fn default_ban_time() -> u64 {
+    600
+}
+
+fn default_queue_size() -> usize {
+    EventDelivery::default().queue_size
+}
+
+fn default_max_attempts() -> usize {
+    EventDelivery::default().max_attempts
+}
+
+fn default_retry_delay() -> u64 {
+    EventDelivery::default().retry_delay.as_secs()
+}
+
+impl Config {
+    /// Read and validate `path`, as YAML if it ends in `.yaml` or `.yml`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("yaml" | "yml") => Self::from_yaml(&contents),
+            _ => Self::from_toml(&contents),
+        }
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Self, ConfigError> {
+        let config: Self = toml::from_str(contents)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_yaml(contents: &str) -> Result<Self, ConfigError> {
+        let config: Self = serde_yaml::from_str(contents)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Address to serve Prometheus metrics on, if any
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.server.metrics_addr
+    }
+
+    /// Addresses the server will listen on
+    pub fn addresses(&self) -> Vec<SocketAddr> {
+        self.server_config().addresses()
+    }
+
+    /// Check what can be checked without reading other files
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.backends.is_empty() {
+            return Err(invalid("no backends configured"));
+        }
+        if let Some(ref name) = self.server.default_backend {
+            if !self.backends.contains_key(name) {
+                return Err(invalid(format!(
+                    "server.default_backend: no backend named {name:?}"
+                )));
+            }
+        }
+        if self.server.default_home.is_some() && self.server.default_backend.is_none() {
+            return Err(invalid("server.default_home needs server.default_backend"));
+        }
+        for (name, backend) in &self.backends {
+            if cfg!(not(feature = "s3")) && matches!(backend, BackendSection::S3 { .. }) {
+                return Err(invalid(format!(
+                    "backend {name:?}: built without S3 support"
+                )));
+            }
+        }
+        if cfg!(not(feature = "metrics")) && self.server.metrics_addr.is_some() {
+            return Err(invalid(
+                "server.metrics_addr: built without the metrics feature",
+            ));
+        }
+
+        let mut names = HashSet::new();
+        for user in &self.users {
+            if user.name.is_empty() {
+                return Err(invalid("user with an empty name"));
+            }
+            if !names.insert(user.name.as_str()) {
+                return Err(invalid(format!("user {:?} is listed twice", user.name)));
+            }
+            self.backend_name(user)?;
+            if let Some(ref hash) = user.password_hash {
+                if !is_supported_hash(hash) {
+                    return Err(invalid(format!(
+                        "user {:?}: unsupported password hash format",
+                        user.name
+                    )));
+                }
+            }
+            for line in &user.authorized_keys {
+                AuthorizedKey::parse(line)
+                    .map_err(|e| invalid(format!("user {:?}: bad key: {e}", user.name)))?;
+            }
+            if user.password_hash.is_none()
+                && user.authorized_keys.is_empty()
+                && !self.has_shared_credentials()
+            {
+                return Err(invalid(format!(
+                    "user {:?} has no password_hash or authorized_keys",
+                    user.name
+                )));
+            }
+        }
+
+        let has_hashes = self.users.iter().any(|u| u.password_hash.is_some());
+        if has_hashes && self.auth.password_file.is_some() {
+            return Err(invalid(
+                "use either password_hash on users or auth.password_file, not both",
+            ));
+        }
+        let has_keys = self.users.iter().any(|u| !u.authorized_keys.is_empty());
+        if has_keys && self.auth.authorized_keys_file.is_some() {
+            return Err(invalid(
+                "use either authorized_keys on users or auth.authorized_keys_file, not both",
+            ));
+        }
+        if self.users.is_empty() && !self.has_shared_credentials() {
+            return Err(invalid("no users or authentication configured"));
+        }
+
+        self.permission_policy()?;
+        Ok(())
+    }
+
+    fn has_shared_credentials(&self) -> bool {
+        self.auth.password_file.is_some()
+            || self.auth.authorized_keys_file.is_some()
+            || self.auth.trusted_user_ca_keys.is_some()
+    }
+
+    /// Backend `user` is served from: their own, the default, or the only one
+    fn backend_name<'a>(&'a self, user: &'a UserSection) -> Result<&'a str, ConfigError> {
+        user.backend
+            .as_deref()
+            .or(self.default_backend_name())
+            .ok_or_else(|| {
+                invalid(format!(
+                    "user {:?} needs a backend, or set server.default_backend",
+                    user.name
+                ))
+            })
+            .and_then(|name| {
+                if self.backends.contains_key(name) {
+                    Ok(name)
+                } else {
+                    Err(invalid(format!(
+                        "user {:?}: no backend named {name:?}",
+                        user.name
+                    )))
+                }
+            })
+    }
+
+    /// Backend for users who log in with shared credentials without being
+    /// listed; only an explicit `server.default_backend` lets them in
+    fn unlisted_backend_name(&self) -> Option<&str> {
+        self.server.default_backend.as_deref()
+    }
+
+    /// Home directories, if any user is confined to one
+    fn home_dirs(&self) -> Option<HomeDirs> {
+        let homes: HashMap<String, String> = self
+            .users
+            .iter()
+            .map(|u| (u.name.clone(), u.home.clone().unwrap_or_default()))
+            .collect();
+        if homes.values().all(String::is_empty) && self.server.default_home.is_none() {
+            return None;
+        }
+        Some(HomeDirs {
+            homes,
+            default: self.server.default_home.clone(),
+        })
+    }
+
+    fn default_backend_name(&self) -> Option<&str> {
+        match self.server.default_backend {
+            Some(ref name) => Some(name),
+            None if self.backends.len() == 1 => self.backends.keys().next().map(String::as_str),
+            None => None,
+        }
+    }
+
+    /// Read-only users first, so their denials win over the shared rules
+    fn permission_policy(&self) -> Result<Option<PermissionPolicy>, ConfigError> {
+        let read_only: Vec<&str> = self
+            .users
+            .iter()
+            .filter(|u| u.read_only)
+            .map(|u| u.name.as_str())
+            .collect();
+        if read_only.is_empty() && self.permissions.rules.is_empty() {
+            return Ok(None);
+        }
+        let mut policy = PermissionPolicy::new();
+        for name in read_only {
+            let rule = Rule::deny()
+                .user(&glob_literal(name))?
+                .operations(Operation::MODIFY);
+            policy = policy.rule(rule);
+        }
+        let file = PolicyFile {
+            default: self.permissions.default,
+            rules: self.permissions.rules.clone(),
+        };
+        Ok(Some(file.append_to(policy)?))
+    }
+
+    fn server_config(&self) -> ServerConfig {
+        let server = &self.server;
+        let limits = &self.limits;
+        let mut config = ServerConfig::new()
+            .port(server.port)
+            .shutdown_timeout(Duration::from_secs(server.shutdown_timeout))
+            .max_auth_attempts(limits.max_auth_attempts)
+            .inactivity_timeout(
+                (limits.idle_timeout > 0).then(|| Duration::from_secs(limits.idle_timeout)),
+            )
+            .event_delivery(EventDelivery::new(
+                self.events.queue_size,
+                self.events.max_attempts,
+                Duration::from_secs(self.events.retry_delay),
+            ));
+        for addr in &server.bind {
+            config = config.bind(*addr);
+        }
+        if let Some(threshold) = server.spill_threshold {
+            config = config.spill_threshold(threshold);
+        }
+        if let Some(max) = server.max_file_size {
+            config = config.max_file_size(max);
+        }
+        if server.commit_unclosed_uploads {
+            config = config.unflushed_writes(UnflushedWrites::Commit);
+        }
+        config = config.lenient_setstat(server.lenient_setstat);
+        if let Some(max) = limits.max_connections {
+            config = config.max_connections(max);
+        }
+        if let Some(max) = limits.max_connections_per_ip {
+            config = config.max_connections_per_ip(max);
+        }
+        if let Some(secs) = limits.max_session_duration {
+            config = config.max_session_duration(Duration::from_secs(secs));
+        }
+        if let Some(secs) = limits.keepalive_interval {
+            config = config.keepalive(Duration::from_secs(secs), limits.keepalive_max);
+        }
+        if let Some(ref ban) = limits.ban {
+            config = config.ban(BanPolicy::new(
+                ban.after,
+                Duration::from_secs(ban.find_time),
+                Duration::from_secs(ban.ban_time),
+            ));
+        }
+        config
+    }
+
+    /// Load the files the configuration refers to, without starting anything
+    ///
+    /// Catches missing host keys, password files and backend roots before a
+    /// deploy rather than at startup.
+    pub fn check(&self) -> Result<(), ConfigError> {
+        self.load_host_keys(ServerConfig::new())?;
+        if let Some(ref path) = self.auth.password_file {
+            crate::auth::PasswordFile::load(path).map_err(|e| ConfigError::Io(path.clone(), e))?;
+        }
+        if let Some(ref path) = self.auth.trusted_user_ca_keys {
+            crate::auth::TrustedUserCaKeys::load(path)
+                .map_err(|e| ConfigError::Io(path.clone(), e))?;
+        }
+        for backend in self.backends.values() {
+            if let BackendSection::Local { root, .. } = backend {
+                root.canonicalize()
+                    .map_err(|e| ConfigError::Io(root.clone(), e))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn load_host_keys(&self, mut config: ServerConfig) -> Result<ServerConfig, ConfigError> {
+        if self.server.host_keys.is_empty() {
+            return Ok(config.with_generated_key());
+        }
+        for path in &self.server.host_keys {
+            config = config
+                .with_key_file(path)
+                .map_err(|e| ConfigError::HostKey(path.clone(), e))?;
+        }
+        Ok(config)
+    }
+
+    /// Build the server this configuration describes
+    pub async fn into_server(self) -> Result<Server, ConfigError> {
+        let config = self.load_host_keys(self.server_config())?;
+
+        let mut backends = HashMap::new();
+        for (name, section) in &self.backends {
+            backends.insert(name.as_str(), build_backend(section).await?);
+        }
+        let routes = BackendRoutes {
+            users: self
+                .users
+                .iter()
+                .map(|user| {
+                    let name = self.backend_name(user)?;
+                    Ok((user.name.clone(), backends[name].clone()))
+                })
+                .collect::<Result<_, ConfigError>>()?,
+            default: self
+                .unlisted_backend_name()
+                .map(|name| backends[name].clone()),
+        };
+        let mut server = Server::with_backend_factory(routes).config(config);
+
+        if let Some(homes) = self.home_dirs() {
+            server = server.with_home_dir(move |user| homes.get(user));
+        }
+        if let Some(policy) = self.permission_policy()? {
+            server = server.with_permissions(policy);
+        }
+
+        let credentials = UserCredentials::new(&self.users);
+        if credentials.has_passwords() {
+            server = server.with_password_authenticator(credentials.clone());
+        }
+        if credentials.has_keys() {
+            server = server.with_pubkey_authenticator(credentials);
+        }
+        if let Some(ref path) = self.auth.password_file {
+            server = server
+                .with_password_file(path)
+                .map_err(|e| ConfigError::Io(path.clone(), e))?;
+        }
+        if let Some(ref template) = self.auth.authorized_keys_file {
+            server = server.with_authorized_keys_file(template);
+        }
+        if let Some(ref path) = self.auth.trusted_user_ca_keys {
+            server = server
+                .with_trusted_user_ca_keys_file(path)
+                .map_err(|e| ConfigError::Io(path.clone(), e))?;
+        }
+
+        if let Some(ref path) = self.audit.log {
+            let sink = JsonLinesSink::open(path).map_err(|e| ConfigError::Io(path.clone(), e))?;
+            server = server.with_audit_sink(sink);
+        }
+        if self.audit.tracing {
+            server = server.with_audit_sink(TracingSink);
+        }
+        if let Some(ref url) = self.events.webhook {
+            let hook =
+                WebhookHook::new(url).map_err(|e| invalid(format!("events.webhook: {e}")))?;
+            server = server.with_event_hook(hook);
+        }
+        if let Some(ref command) = self.events.command {
+            server = server.with_event_hook(CommandHook::new(command));
+        }
+        Ok(server)
+    }
+}
+
+/// Escape glob metacharacters so a user name only matches itself
+fn glob_literal(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '*' | '?' | '[' | ']' | '{' | '}' | '\\' => format!("[{c}]"),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+async fn build_backend(section: &BackendSection) -> Result<Arc<dyn Backend>, ConfigError> {
+    Ok(match section {
+        BackendSection::Local { root, allow_chown } => {
+            let root = root
+                .canonicalize()
+                .map_err(|e| ConfigError::Io(root.clone(), e))?;
+            Arc::new(LocalBackend::new(root).with_ownership(*allow_chown))
+        }
+        #[cfg(feature = "s3")]
+        BackendSection::S3 {
+            bucket,
+            prefix,
+            endpoint,
+            region,
+        } => {
+            let config = crate::S3Config::new(bucket).with_prefix(prefix);
+            let backend = match endpoint {
+                Some(endpoint) => {
+                    let region = region.as_deref().unwrap_or("us-east-1");
+                    crate::S3Backend::with_endpoint(config, endpoint, region).await
+                }
+                None => crate::S3Backend::from_env(config).await,
+            };
+            Arc::new(backend)
+        }
+        #[cfg(not(feature = "s3"))]
+        BackendSection::S3 { .. } => return Err(invalid("built without S3 support")),
+        BackendSection::Memory => Arc::new(MemoryBackend::new()),
+    })
+}
+
+/// Serves each configured user from their backend
+struct BackendRoutes {
+    users: HashMap<String, Arc<dyn Backend>>,
+    /// For users authenticated by shared credentials but not listed; they
+    /// are refused without one
+    default: Option<Arc<dyn Backend>>,
+}
+
+/// Home directory of each user, listed or not
+struct HomeDirs {
+    /// Listed users, with an empty home for those not confined
+    homes: HashMap<String, String>,
+    /// Template for unlisted users, `%u` being the login name
+    default: Option<String>,
+}
+
+impl HomeDirs {
+    fn get(&self, user: &str) -> String {
+        match self.homes.get(user) {
+            Some(home) => home.clone(),
+            None => self
+                .default
+                .as_deref()
+                .map(|template| template.replace("%u", user))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[async_trait]
+impl BackendFactory for BackendRoutes {
+    async fn backend(&self, user: &str, _context: &AuthContext) -> BackendResult<Arc<dyn Backend>> {
+        self.users
+            .get(user)
+            .or(self.default.as_ref())
+            .cloned()
+            .ok_or(BackendError::PermissionDenied)
+    }
+}
+
+/// Password hashes and keys given inline for each user
+#[derive(Clone)]
+struct UserCredentials {
+    passwords: Arc<HashMap<String, String>>,
+    keys: Arc<HashMap<String, AuthorizedKeys>>,
+}
+
+impl UserCredentials {
+    fn new(users: &[UserSection]) -> Self {
+        let passwords = users
+            .iter()
+            .filter_map(|u| Some((u.name.clone(), u.password_hash.clone()?)))
+            .collect();
+        let keys = users
+            .iter()
+            .filter(|u| !u.authorized_keys.is_empty())
+            .map(|u| {
+                (
+                    u.name.clone(),
+                    AuthorizedKeys::parse(&u.authorized_keys.join("\n")),
+                )
+            })
+            .collect();
+        Self {
+            passwords: Arc::new(passwords),
+            keys: Arc::new(keys),
+        }
+    }
+
+    fn has_passwords(&self) -> bool {
+        !self.passwords.is_empty()
+    }
+
+    fn has_keys(&self) -> bool {
+        !self.keys.is_empty()
+    }
+}
+
+#[async_trait]
+impl Authenticator for UserCredentials {
+    async fn check_password(&self, user: &str, password: &str) -> bool {
+        check_user_password(self.passwords.get(user).cloned(), password).await
+    }
+
+    async fn check_publickey(&self, user: &str, key: &PublicKey) -> bool {
+        self.check_publickey_from(user, key, None).await
+    }
+
+    async fn check_publickey_from(
+        &self,
+        user: &str,
+        key: &PublicKey,
+        peer: Option<IpAddr>,
+    ) -> bool {
+        self.keys
+            .get(user)
+            .is_some_and(|keys| keys.authorizes(key, peer))
+    }
+}
+
+fn seconds(secs: u64) -> String {
+    format!("{secs}s")
+}
+
+fn optional<T: fmt::Display>(value: Option<T>) -> String {
+    value.map_or_else(|| "unlimited".to_string(), |v| v.to_string())
+}
+
+/// The resolved setup, as printed by `sftp-s3 check-config`
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let addresses: Vec<String> = self.addresses().iter().map(|a| a.to_string()).collect();
+        writeln!(f, "Listen: {}", addresses.join(", "))?;
+        if self.server.host_keys.is_empty() {
+            writeln!(f, "Host keys: generated at startup")?;
+        } else {
+            for path in &self.server.host_keys {
+                writeln!(f, "Host key: {}", path.display())?;
+            }
+        }
+
+        writeln!(f, "Backends:")?;
+        for (name, backend) in &self.backends {
+            let default = if self.default_backend_name() == Some(name.as_str()) {
+                " (default)"
+            } else {
+                ""
+            };
+            match backend {
+                BackendSection::Local { root, allow_chown } => {
+                    let chown = if *allow_chown { ", chown allowed" } else { "" };
+                    writeln!(f, "  {name}: local {}{chown}{default}", root.display())?
+                }
+                BackendSection::S3 {
+                    bucket,
+                    prefix,
+                    endpoint,
+                    region,
+                } => {
+                    write!(f, "  {name}: s3 bucket {bucket:?} prefix {prefix:?}")?;
+                    if let Some(endpoint) = endpoint {
+                        write!(f, " at {endpoint}")?;
+                    }
+                    if let Some(region) = region {
+                        write!(f, " in {region}")?;
+                    }
+                    writeln!(f, "{default}")?;
+                }
+                BackendSection::Memory => writeln!(f, "  {name}: memory{default}")?,
+            }
+        }
+
+        writeln!(f, "Users:")?;
+        for user in &self.users {
+            let mut methods = Vec::new();
+            if user.password_hash.is_some() {
+                methods.push("password".to_string());
+            }
+            if !user.authorized_keys.is_empty() {
+                methods.push(format!("{} key(s)", user.authorized_keys.len()));
+            }
+            if methods.is_empty() {
+                methods.push("shared credentials".to_string());
+            }
+            let backend = self.backend_name(user).unwrap_or("?");
+            write!(
+                f,
+                "  {}: {}; backend {backend}, home {}",
+                user.name,
+                methods.join(", "),
+                user.home.as_deref().unwrap_or("/")
+            )?;
+            if user.read_only {
+                write!(f, ", read-only")?;
+            }
+            writeln!(f)?;
+        }
+        if self.has_shared_credentials() {
+            match self.unlisted_backend_name() {
+                Some(backend) => writeln!(
+                    f,
+                    "  unlisted: backend {backend}, home {}",
+                    self.server.default_home.as_deref().unwrap_or("/")
+                )?,
+                None => writeln!(f, "  unlisted: refused")?,
+            }
+        }
+        if let Some(ref path) = self.auth.password_file {
+            writeln!(f, "  password file: {}", path.display())?;
+        }
+        if let Some(ref template) = self.auth.authorized_keys_file {
+            writeln!(f, "  authorized keys file: {template}")?;
+        }
+        if let Some(ref path) = self.auth.trusted_user_ca_keys {
+            writeln!(f, "  trusted user CA keys: {}", path.display())?;
+        }
+        if !self.permissions.rules.is_empty() {
+            writeln!(
+                f,
+                "Permissions: {} rule(s), default {}",
+                self.permissions.rules.len(),
+                format!("{:?}", self.permissions.default).to_lowercase()
+            )?;
+        }
+
+        let limits = &self.limits;
+        writeln!(
+            f,
+            "Limits: {} connections, {} per IP, {} auth attempts",
+            optional(limits.max_connections),
+            optional(limits.max_connections_per_ip),
+            limits.max_auth_attempts
+        )?;
+        if let Some(ref ban) = limits.ban {
+            writeln!(
+                f,
+                "Ban: after {} failures within {}, for {}",
+                ban.after,
+                seconds(ban.find_time),
+                seconds(ban.ban_time)
+            )?;
+        }
+        let idle = (limits.idle_timeout > 0).then(|| seconds(limits.idle_timeout));
+        writeln!(
+            f,
+            "Timeouts: idle {}, session {}, shutdown {}",
+            idle.unwrap_or_else(|| "disabled".to_string()),
+            optional(limits.max_session_duration.map(seconds)),
+            seconds(self.server.shutdown_timeout)
+        )?;
+        if let Some(secs) = limits.keepalive_interval {
+            writeln!(
+                f,
+                "Keepalive: every {}, up to {} missed",
+                seconds(secs),
+                limits.keepalive_max
+            )?;
+        }
+
+        if let Some(ref path) = self.audit.log {
+            writeln!(f, "Audit log: {}", path.display())?;
+        }
+        if self.audit.tracing {
+            writeln!(f, "Audit log: tracing")?;
+        }
+        if let Some(ref url) = self.events.webhook {
+            writeln!(f, "Event webhook: {url}")?;
+        }
+        if let Some(ref command) = self.events.command {
+            writeln!(f, "Event command: {command}")?;
+        }
+        if let Some(addr) = self.server.metrics_addr {
+            writeln!(f, "Metrics: http://{addr}/metrics")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::AuthMethod;
+
+    const KEY: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIHG3KxTD8Tz/2R6NpdwzC/TU3eNG/xTq+4DkGo4ECvAW ci@build";
+
+    fn context(user: &str) -> AuthContext {
+        AuthContext::new(user, None, AuthMethod::Password)
+    }
+
+    #[tokio::test]
+    async fn test_toml_routes_users_to_backends() {
+        let hash = bcrypt::hash("secret", 4).unwrap();
+        let config = Config::from_toml(&format!(
+            r#"
+            [server]
+            bind = ["127.0.0.1:0"]
+
+            [backends.a]
+            type = "memory"
+
+            [backends.b]
+            type = "memory"
+
+            [[users]]
+            name = "alice"
+            password_hash = "{hash}"
+            backend = "a"
+
+            [[users]]
+            name = "ci"
+            authorized_keys = ["{KEY}"]
+            backend = "b"
+            read_only = true
+            "#
+        ))
+        .unwrap();
+
+        let credentials = UserCredentials::new(&config.users);
+        assert!(credentials.check_password("alice", "secret").await);
+        assert!(!credentials.check_password("ci", "secret").await);
+        let key = PublicKey::from_openssh(KEY).unwrap();
+        assert!(credentials.check_publickey("ci", &key).await);
+        assert!(!credentials.check_publickey("alice", &key).await);
+
+        let policy = config.permission_policy().unwrap().unwrap();
+        assert!(!policy.is_allowed("ci", Operation::Write, "/a"));
+        assert!(policy.is_allowed("ci", Operation::Read, "/a"));
+        assert!(policy.is_allowed("alice", Operation::Write, "/a"));
+
+        let summary = config.to_string();
+        assert!(summary.contains("Listen: 127.0.0.1:0"));
+        assert!(summary.contains("ci: 1 key(s); backend b, home /, read-only"));
+        config.into_server().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_yaml_with_shared_credentials() {
+        let config = Config::from_yaml(
+            r#"
+            limits:
+              max_connections: 10
+              ban:
+                after: 5
+            auth:
+              authorized_keys_file: /etc/sftp/keys/%u
+            backends:
+              main:
+                type: memory
+            users:
+              - name: alice
+                home: tenants/alice
+            permissions:
+              default: deny
+              rule:
+                - effect: allow
+                  operations: [read, list]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.server_config().max_connections, Some(10));
+        assert!(config.server_config().ban.is_some());
+
+        let policy = config.permission_policy().unwrap().unwrap();
+        assert!(policy.is_allowed("bob", Operation::Read, "/x"));
+        assert!(!policy.is_allowed("bob", Operation::Write, "/x"));
+
+        // Unlisted users are refused without an explicit default backend
+        assert_eq!(config.unlisted_backend_name(), None);
+        assert!(config.to_string().contains("unlisted: refused"));
+        let routes = BackendRoutes {
+            users: HashMap::new(),
+            default: None,
+        };
+        assert!(matches!(
+            routes.backend("bob", &context("bob")).await,
+            Err(BackendError::PermissionDenied)
+        ));
+        let homes = config.home_dirs().unwrap();
+        assert_eq!(homes.get("alice"), "tenants/alice");
+        assert_eq!(homes.get("bob"), "");
+    }
+
+    #[test]
+    fn test_unlisted_users_get_default_home() {
+        let config = Config::from_toml(
+            r#"
+            [server]
+            default_backend = "main"
+            default_home = "tenants/%u"
+
+            [auth]
+            password_file = "/etc/sftp/passwords"
+
+            [backends.main]
+            type = "memory"
+
+            [[users]]
+            name = "admin"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.unlisted_backend_name(), Some("main"));
+        let homes = config.home_dirs().unwrap();
+        assert_eq!(homes.get("bob"), "tenants/bob");
+        assert_eq!(homes.get("admin"), "");
+        assert!(config
+            .to_string()
+            .contains("unlisted: backend main, home tenants/%u"));
+
+        let invalid = Config::from_toml(
+            "[server]\ndefault_home = \"t/%u\"\n[backends.a]\ntype = \"memory\"\n[[users]]\nname = \"a\"",
+        );
+        assert!(invalid
+            .unwrap_err()
+            .to_string()
+            .contains("default_home needs server.default_backend"));
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        let invalid = |contents: &str| Config::from_toml(contents).unwrap_err().to_string();
+
+        assert_eq!(
+            invalid("[[users]]\nname = \"a\"\npassword_hash = \"$6$x$y\""),
+            "no backends configured"
+        );
+        assert!(invalid(
+            "[backends.a]\ntype = \"memory\"\n[[users]]\nname = \"a\"\nbackend = \"b\"\npassword_hash = \"$6$x$y\""
+        )
+        .contains("no backend named \"b\""));
+        assert!(invalid(
+            "[backends.a]\ntype = \"memory\"\n[backends.b]\ntype = \"memory\"\n[[users]]\nname = \"a\"\npassword_hash = \"$6$x$y\""
+        )
+        .contains("needs a backend"));
+        assert!(
+            invalid("[backends.a]\ntype = \"memory\"\n[[users]]\nname = \"a\"")
+                .contains("no password_hash or authorized_keys")
+        );
+        assert!(invalid(
+            "[backends.a]\ntype = \"memory\"\n[[users]]\nname = \"a\"\npassword_hash = \"plain\""
+        )
+        .contains("unsupported password hash"));
+        assert!(invalid("[backends.a]\ntype = \"floppy\"").contains("floppy"));
+        assert!(invalid("[server]\nprot = 22").contains("prot"));
+    }
+
+    #[test]
+    fn test_glob_literal() {
+        let policy = PermissionPolicy::new().rule(
+            Rule::deny()
+                .user(&glob_literal("team[1]*"))
+                .unwrap()
+                .operations(Operation::MODIFY),
+        );
+        assert!(!policy.is_allowed("team[1]*", Operation::Write, "/"));
+        assert!(policy.is_allowed("team1x", Operation::Write, "/"));
+    }
+}
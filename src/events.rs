@@ -0,0 +1,496 @@
+//! Hooks notified when files are uploaded, downloaded, removed or renamed
+
+use async_trait::async_trait;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::process::{ExitStatus, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// How long a webhook request or command may take before it counts as failed
+const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Longest wait between retries that backoff grows to
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+/// What happened to a file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum FileEventKind {
+    /// A write handle was closed and its data committed
+    Uploaded {
+        path: String,
+        size: u64,
+        /// Hex SHA-256 of the data, unless the client wrote out of order
+        sha256: Option<String>,
+    },
+    /// A read handle was closed after sending `size` bytes
+    Downloaded {
+        path: String,
+        size: u64,
+    },
+    Removed {
+        path: String,
+    },
+    Renamed {
+        from: String,
+        to: String,
+    },
+}
+
+/// A file event, e.g. `{"user":"alice","event":"uploaded","path":"/in/a.csv",
+/// "size":1024,"sha256":"9f86..."}`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileEvent {
+    pub user: Option<String>,
+    #[serde(flatten)]
+    pub kind: FileEventKind,
+}
+
+impl FileEvent {
+    /// Event name as serialized, e.g. `"uploaded"`
+    pub fn name(&self) -> &'static str {
+        match self.kind {
+            FileEventKind::Uploaded { .. } => "uploaded",
+            FileEventKind::Downloaded { .. } => "downloaded",
+            FileEventKind::Removed { .. } => "removed",
+            FileEventKind::Renamed { .. } => "renamed",
+        }
+    }
+
+    /// The file the event is about; the original path for renames
+    pub fn path(&self) -> &str {
+        match self.kind {
+            FileEventKind::Uploaded { ref path, .. }
+            | FileEventKind::Downloaded { ref path, .. }
+            | FileEventKind::Removed { ref path } => path,
+            FileEventKind::Renamed { ref from, .. } => from,
+        }
+    }
+}
+
+/// Why a hook failed to handle an event
+#[derive(Debug, thiserror::Error)]
+pub enum HookError {
+    #[error("request failed: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("webhook responded with {0}")]
+    Status(reqwest::StatusCode),
+    #[error("could not run command: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("command exited with {0}")]
+    Exit(ExitStatus),
+    #[error("timed out")]
+    Timeout,
+    #[error("{0}")]
+    Other(String),
+}
+
+/// Receives file events
+///
+/// Hooks run on a background task after the operation has completed, so a
+/// slow hook never holds up a transfer. Returning an error retries the event
+/// according to [`EventDelivery`].
+#[async_trait]
+pub trait EventHook: Send + Sync + 'static {
+    async fn on_event(&self, event: &FileEvent) -> Result<(), HookError>;
+}
+
+/// POSTs each event as JSON to a URL
+pub struct WebhookHook {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl WebhookHook {
+    /// Fails if no HTTP client can be set up, e.g. without TLS roots
+    pub fn new(url: impl Into<String>) -> Result<Self, HookError> {
+        let client = reqwest::Client::builder().timeout(HOOK_TIMEOUT).build()?;
+        Ok(Self {
+            url: url.into(),
+            client,
+        })
+    }
+}
+
+#[async_trait]
+impl EventHook for WebhookHook {
+    async fn on_event(&self, event: &FileEvent) -> Result<(), HookError> {
+        let body = serde_json::to_vec(event).map_err(|err| HookError::Other(err.to_string()))?;
+        let response = self
+            .client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(HookError::Status(response.status()));
+        }
+        Ok(())
+    }
+}
+
+/// Runs a shell command for each event
+///
+/// The command gets the event as JSON on stdin and in the environment as
+/// `SFTP_EVENT`, `SFTP_USER`, `SFTP_PATH`, plus `SFTP_SIZE` and
+/// `SFTP_SHA256` for transfers and `SFTP_NEW_PATH` for renames. A non-zero
+/// exit status counts as a failure.
+pub struct CommandHook {
+    command: String,
+}
+
+impl CommandHook {
+    /// Run `command` with `sh -c`
+    pub fn new(command: impl Into<String>) -> Self {
+        Self {
+            command: command.into(),
+        }
+    }
+
+    async fn run(&self, event: &FileEvent) -> Result<(), HookError> {
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(&self.command)
+            .env("SFTP_EVENT", event.name())
+            .env("SFTP_USER", event.user.as_deref().unwrap_or(""))
+            .env("SFTP_PATH", event.path())
+            .stdin(Stdio::piped())
+            .kill_on_drop(true);
+        match event.kind {
+            FileEventKind::Uploaded {
+                size, ref sha256, ..
+            } => {
+                command
+                    .env("SFTP_SIZE", size.to_string())
+                    .env("SFTP_SHA256", sha256.as_deref().unwrap_or(""));
+            }
+            FileEventKind::Downloaded { size, .. } => {
+                command.env("SFTP_SIZE", size.to_string());
+            }
+            FileEventKind::Renamed { ref to, .. } => {
+                command.env("SFTP_NEW_PATH", to);
+            }
+            FileEventKind::Removed { .. } => {}
+        }
+
+        let mut child = command.spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            let json =
+                serde_json::to_vec(event).map_err(|err| HookError::Other(err.to_string()))?;
+            // The command may not read stdin at all
+            let _ = stdin.write_all(&json).await;
+        }
+        let status = child.wait().await?;
+        if !status.success() {
+            return Err(HookError::Exit(status));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventHook for CommandHook {
+    async fn on_event(&self, event: &FileEvent) -> Result<(), HookError> {
+        tokio::time::timeout(HOOK_TIMEOUT, self.run(event))
+            .await
+            .map_err(|_| HookError::Timeout)?
+    }
+}
+
+/// How events are queued and retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventDelivery {
+    /// Events waiting for the hooks beyond which new events are dropped
+    pub queue_size: usize,
+    /// Tries per hook and event, including the first
+    pub max_attempts: usize,
+    /// Wait before the first retry; doubles on each further retry, up to
+    /// five minutes unless the first wait is longer
+    pub retry_delay: Duration,
+}
+
+impl EventDelivery {
+    pub fn new(queue_size: usize, max_attempts: usize, retry_delay: Duration) -> Self {
+        Self {
+            queue_size,
+            max_attempts,
+            retry_delay,
+        }
+    }
+}
+
+impl Default for EventDelivery {
+    fn default() -> Self {
+        Self::new(1024, 3, Duration::from_secs(1))
+    }
+}
+
+/// Queues events for the background task delivering them to the hooks
+#[derive(Clone)]
+pub(crate) struct EventSender {
+    tx: mpsc::Sender<FileEvent>,
+}
+
+impl EventSender {
+    /// Queue `event`, dropping it if the queue is full
+    pub(crate) fn send(&self, event: FileEvent) {
+        match self.tx.try_send(event) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(event)) => {
+                warn!(
+                    event = event.name(),
+                    path = event.path(),
+                    "Event queue full, dropping event"
+                );
+            }
+            Err(mpsc::error::TrySendError::Closed(event)) => {
+                warn!(
+                    event = event.name(),
+                    path = event.path(),
+                    "Event hooks stopped, dropping event"
+                );
+            }
+        }
+    }
+}
+
+/// An event sender bound to one session's user
+#[derive(Clone)]
+pub(crate) struct SessionEvents {
+    sender: EventSender,
+    user: Option<String>,
+}
+
+impl SessionEvents {
+    pub(crate) fn new(sender: EventSender, user: Option<String>) -> Self {
+        Self { sender, user }
+    }
+
+    pub(crate) fn send(&self, kind: FileEventKind) {
+        self.sender.send(FileEvent {
+            user: self.user.clone(),
+            kind,
+        });
+    }
+}
+
+/// Start delivering events to `hooks` on a background task
+///
+/// The task ends once every sender has been dropped and the queue is empty.
+pub(crate) fn spawn(
+    hooks: Vec<Arc<dyn EventHook>>,
+    delivery: EventDelivery,
+) -> (EventSender, JoinHandle<()>) {
+    let (tx, mut rx) = mpsc::channel::<FileEvent>(delivery.queue_size.max(1));
+    let worker = tokio::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            for hook in &hooks {
+                deliver(hook.as_ref(), &event, delivery).await;
+            }
+        }
+    });
+    (EventSender { tx }, worker)
+}
+
+/// Hand `event` to `hook`, retrying with backoff
+async fn deliver(hook: &dyn EventHook, event: &FileEvent, delivery: EventDelivery) {
+    let mut delay = delivery.retry_delay;
+    for attempt in 1..=delivery.max_attempts.max(1) {
+        match hook.on_event(event).await {
+            Ok(()) => {
+                debug!(event = event.name(), path = event.path(), "Delivered event");
+                return;
+            }
+            Err(err) if attempt < delivery.max_attempts => {
+                info!(event = event.name(), path = event.path(), attempt, error = %err, "Event hook failed, retrying");
+                tokio::time::sleep(delay).await;
+                delay = next_delay(delay, delivery);
+            }
+            Err(err) => {
+                warn!(event = event.name(), path = event.path(), attempt, error = %err, "Event hook failed, giving up");
+            }
+        }
+    }
+}
+
+/// Wait before the retry after one that waited `delay`
+fn next_delay(delay: Duration, delivery: EventDelivery) -> Duration {
+    delay
+        .saturating_mul(2)
+        .min(MAX_RETRY_DELAY.max(delivery.retry_delay))
+}
+
+/// SHA-256 of an upload, computed as its writes arrive
+///
+/// Only sequential writes can be hashed this way; a write anywhere but the
+/// current end gives up on the checksum.
+pub(crate) struct StreamingChecksum {
+    hasher: Option<Sha256>,
+    next_offset: u64,
+}
+
+impl StreamingChecksum {
+    pub(crate) fn new() -> Self {
+        Self {
+            hasher: Some(Sha256::new()),
+            next_offset: 0,
+        }
+    }
+
+    pub(crate) fn update(&mut self, offset: u64, data: &[u8]) {
+        if offset != self.next_offset {
+            self.hasher = None;
+        }
+        if let Some(ref mut hasher) = self.hasher {
+            hasher.update(data);
+            self.next_offset += data.len() as u64;
+        }
+    }
+
+    /// Hex digest, if every write was sequential
+    pub(crate) fn finish(self) -> Option<String> {
+        self.hasher.map(|hasher| format!("{:x}", hasher.finalize()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Fails the first `failures` calls, then records events
+    struct Flaky {
+        failures: AtomicUsize,
+        seen: Mutex<Vec<FileEvent>>,
+    }
+
+    #[async_trait]
+    impl EventHook for Flaky {
+        async fn on_event(&self, event: &FileEvent) -> Result<(), HookError> {
+            if self.failures.load(Ordering::SeqCst) > 0 {
+                self.failures.fetch_sub(1, Ordering::SeqCst);
+                return Err(HookError::Other("not yet".into()));
+            }
+            self.seen.lock().push(event.clone());
+            Ok(())
+        }
+    }
+
+    fn removed(path: &str) -> FileEvent {
+        FileEvent {
+            user: Some("alice".into()),
+            kind: FileEventKind::Removed { path: path.into() },
+        }
+    }
+
+    #[test]
+    fn test_event_json() {
+        let event = FileEvent {
+            user: Some("alice".into()),
+            kind: FileEventKind::Uploaded {
+                path: "/in/a.csv".into(),
+                size: 3,
+                sha256: None,
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({
+                "user": "alice",
+                "event": "uploaded",
+                "path": "/in/a.csv",
+                "size": 3,
+                "sha256": null,
+            })
+        );
+    }
+
+    #[test]
+    fn test_streaming_checksum() {
+        let mut checksum = StreamingChecksum::new();
+        checksum.update(0, b"hello ");
+        checksum.update(6, b"world");
+        assert_eq!(
+            checksum.finish().as_deref(),
+            Some("b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9")
+        );
+
+        let mut checksum = StreamingChecksum::new();
+        checksum.update(6, b"world");
+        checksum.update(0, b"hello ");
+        assert!(checksum.finish().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_delivery_retries() {
+        let hook = Arc::new(Flaky {
+            failures: AtomicUsize::new(2),
+            seen: Mutex::new(Vec::new()),
+        });
+        let delivery = EventDelivery::new(8, 3, Duration::from_millis(1));
+        let (sender, worker) = spawn(vec![hook.clone()], delivery);
+
+        sender.send(removed("/a"));
+        sender.send(removed("/b"));
+        drop(sender);
+        worker.await.unwrap();
+
+        // The first event took all three attempts, the second went straight through
+        let seen = hook.seen.lock();
+        assert_eq!(*seen, vec![removed("/a"), removed("/b")]);
+    }
+
+    #[test]
+    fn test_retry_delay_is_capped() {
+        let delivery = EventDelivery::new(8, 100, Duration::from_secs(1));
+        let mut delay = delivery.retry_delay;
+        for _ in 0..100 {
+            delay = next_delay(delay, delivery);
+        }
+        assert_eq!(delay, MAX_RETRY_DELAY);
+
+        // A longer first wait is kept rather than overflowing
+        let delivery = EventDelivery::new(8, 3, Duration::MAX);
+        assert_eq!(next_delay(Duration::MAX, delivery), Duration::MAX);
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_drops_events() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let sender = EventSender { tx };
+        sender.send(removed("/a"));
+        sender.send(removed("/b"));
+        drop(sender);
+
+        assert_eq!(rx.recv().await, Some(removed("/a")));
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_command_hook() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let hook = CommandHook::new(format!(
+            "printf '%s %s ' \"$SFTP_EVENT\" \"$SFTP_PATH\" > {0} && cat >> {0}",
+            out.display()
+        ));
+        hook.on_event(&removed("/a")).await.unwrap();
+
+        let written = std::fs::read_to_string(&out).unwrap();
+        assert!(written.starts_with("removed /a {"));
+        assert!(CommandHook::new("exit 3")
+            .on_event(&removed("/a"))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,493 @@
+use crate::backend::{Backend, BackendError, BackendResult};
+use bytes::Bytes;
+use std::io::SeekFrom;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tracing::debug;
+
+/// Default per-handle memory limit before a write buffer spills to disk
+pub const DEFAULT_SPILL_THRESHOLD: usize = 8 * 1024 * 1024;
+
+/// Chunk size used when streaming a spilled buffer back out
+const COPY_CHUNK: usize = 1024 * 1024;
+
+/// Where buffered write data currently lives
+#[derive(Debug)]
+enum Storage {
+    Memory(Vec<u8>),
+    File(File),
+    /// Only the backend holds the data; see [`WriteBuffer::stored`]
+    Backend,
+}
+
+/// Random-access buffer for a write handle
+///
+/// Data is kept in memory until the file would grow past `threshold` bytes,
+/// then moved to an anonymous temporary file. Writes past the current end
+/// leave zero-filled holes, matching regular file semantics.
+#[derive(Debug)]
+pub struct WriteBuffer {
+    storage: Storage,
+    len: u64,
+    threshold: usize,
+    /// Whether committing would change what the backend stores
+    changed: bool,
+    /// Whether `sync` has stored the contents before
+    synced: bool,
+}
+
+impl WriteBuffer {
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            storage: Storage::Memory(Vec::new()),
+            len: 0,
+            threshold,
+            changed: true,
+            synced: false,
+        }
+    }
+
+    /// Buffer holding the `size` bytes currently stored at `path`
+    ///
+    /// Used to modify an existing file in place. The buffer counts as
+    /// unchanged until something is written to it.
+    pub async fn preload<B: Backend + ?Sized>(
+        backend: &B,
+        path: &str,
+        size: u64,
+        threshold: usize,
+    ) -> BackendResult<Self> {
+        let mut buffer = Self::new(threshold);
+        buffer.fill(backend, path, size).await?;
+        Ok(buffer)
+    }
+
+    /// Buffer for `len` bytes the backend already stores at the handle's path,
+    /// e.g. after a server-side copy
+    ///
+    /// Nothing is read until [`load`](Self::load) is called, and the buffer
+    /// counts as synced, so closing it unchanged stores nothing.
+    pub fn stored(len: u64, threshold: usize) -> Self {
+        Self {
+            storage: Storage::Backend,
+            len,
+            threshold,
+            changed: false,
+            synced: true,
+        }
+    }
+
+    /// Read in the contents of a [`stored`](Self::stored) buffer so it can be
+    /// read from or written to; does nothing for other buffers
+    pub async fn load<B: Backend + ?Sized>(
+        &mut self,
+        backend: &B,
+        path: &str,
+    ) -> BackendResult<()> {
+        if !matches!(self.storage, Storage::Backend) {
+            return Ok(());
+        }
+        let size = self.len;
+        self.storage = Storage::Memory(Vec::new());
+        self.len = 0;
+        self.fill(backend, path, size).await
+    }
+
+    /// Append the first `size` bytes stored at `path`, leaving the buffer unchanged
+    async fn fill<B: Backend + ?Sized>(
+        &mut self,
+        backend: &B,
+        path: &str,
+        size: u64,
+    ) -> BackendResult<()> {
+        while self.len < size {
+            let len = std::cmp::min(size - self.len, COPY_CHUNK as u64) as u32;
+            let data = backend.read_range(path, self.len, len).await?;
+            if data.is_empty() {
+                break;
+            }
+            self.write_at(self.len, &data).await?;
+        }
+        self.changed = false;
+        Ok(())
+    }
+
+    /// Current file size
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether the buffer has moved to a temporary file
+    pub fn is_spilled(&self) -> bool {
+        matches!(self.storage, Storage::File(_))
+    }
+
+    /// False for a preloaded buffer nothing has been written to
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+
+    /// True once `sync` has stored the buffer, even if it is unchanged since
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+
+    /// Read up to `len` bytes at `offset`, fewer at the end of the file
+    pub async fn read_at(&mut self, offset: u64, len: usize) -> BackendResult<Vec<u8>> {
+        if offset >= self.len {
+            return Ok(Vec::new());
+        }
+        let len = std::cmp::min(len as u64, self.len - offset) as usize;
+        match self.storage {
+            Storage::Memory(ref buffer) => {
+                let start = offset as usize;
+                Ok(buffer[start..start + len].to_vec())
+            }
+            Storage::File(ref mut file) => {
+                file.flush().await.map_err(map_io_error)?;
+                file.seek(SeekFrom::Start(offset))
+                    .await
+                    .map_err(map_io_error)?;
+                let mut data = vec![0; len];
+                // Anything short of `len` is a hole, already zero
+                read_full(file, &mut data).await?;
+                Ok(data)
+            }
+            Storage::Backend => Err(not_loaded()),
+        }
+    }
+
+    /// Write `data` at `offset`
+    pub async fn write_at(&mut self, offset: u64, data: &[u8]) -> BackendResult<()> {
+        let end = offset
+            .checked_add(data.len() as u64)
+            .ok_or(BackendError::FileTooLarge)?;
+
+        if let Storage::Memory(_) = self.storage {
+            if end > self.threshold as u64 {
+                self.spill().await?;
+            }
+        }
+
+        match self.storage {
+            Storage::Memory(ref mut buffer) => {
+                let start = offset as usize;
+                let end = end as usize;
+                if end > buffer.len() {
+                    buffer.resize(end, 0);
+                }
+                buffer[start..end].copy_from_slice(data);
+            }
+            Storage::File(ref mut file) => {
+                file.seek(SeekFrom::Start(offset))
+                    .await
+                    .map_err(map_io_error)?;
+                file.write_all(data).await.map_err(map_io_error)?;
+            }
+            Storage::Backend => return Err(not_loaded()),
+        }
+
+        self.len = self.len.max(end);
+        self.changed = true;
+        Ok(())
+    }
+
+    /// Read the whole buffer into memory
+    pub async fn into_bytes(self) -> BackendResult<Bytes> {
+        match self.storage {
+            Storage::Memory(buffer) => Ok(Bytes::from(buffer)),
+            Storage::File(mut file) => read_to_len(&mut file, self.len).await,
+            Storage::Backend => Err(not_loaded()),
+        }
+    }
+
+    /// Store the buffer at `path`, streaming it if the backend supports it
+    pub async fn commit<B: Backend + ?Sized>(self, backend: &B, path: &str) -> BackendResult<()> {
+        match self.storage {
+            Storage::Memory(buffer) => backend.write_file(path, Bytes::from(buffer)).await,
+            Storage::File(mut file) => store_file(&mut file, self.len, backend, path).await,
+            // Nothing was written, so the backend already has it
+            Storage::Backend => Ok(()),
+        }
+    }
+
+    /// Store the current contents at `path` and keep the buffer open
+    ///
+    /// Used for fsync. Later writes are stored by the next sync or commit.
+    pub async fn sync<B: Backend + ?Sized>(
+        &mut self,
+        backend: &B,
+        path: &str,
+    ) -> BackendResult<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        match self.storage {
+            Storage::Memory(ref buffer) => {
+                backend
+                    .write_file(path, Bytes::copy_from_slice(buffer))
+                    .await?
+            }
+            Storage::File(ref mut file) => store_file(file, self.len, backend, path).await?,
+            Storage::Backend => {}
+        }
+        self.changed = false;
+        self.synced = true;
+        Ok(())
+    }
+
+    /// Move in-memory data to a temporary file
+    async fn spill(&mut self) -> BackendResult<()> {
+        let Storage::Memory(ref buffer) = self.storage else {
+            return Ok(());
+        };
+
+        debug!(
+            len = buffer.len(),
+            "Spilling write buffer to temporary file"
+        );
+        let file = tempfile::tempfile().map_err(map_io_error)?;
+        let mut file = File::from_std(file);
+        file.write_all(buffer).await.map_err(map_io_error)?;
+        self.storage = Storage::File(file);
+        Ok(())
+    }
+}
+
+/// Store the first `len` bytes of a spilled buffer at `path`
+async fn store_file<B: Backend + ?Sized>(
+    file: &mut File,
+    len: u64,
+    backend: &B,
+    path: &str,
+) -> BackendResult<()> {
+    let Some(mut writer) = backend.open_writer(path).await? else {
+        return backend
+            .write_file(path, read_to_len(file, len).await?)
+            .await;
+    };
+
+    debug!(path, len, "Streaming spilled write buffer");
+    rewind(file).await?;
+
+    let mut remaining = len;
+    while remaining > 0 {
+        let mut chunk = vec![0; std::cmp::min(remaining, COPY_CHUNK as u64) as usize];
+        let read = read_full(file, &mut chunk).await?;
+        // Past the end of the data on disk means we are inside a trailing hole
+        chunk[read..].fill(0);
+        remaining -= chunk.len() as u64;
+        writer.write(Bytes::from(chunk)).await?;
+    }
+
+    writer.commit().await
+}
+
+/// Read a spilled buffer of `len` bytes into memory
+async fn read_to_len(file: &mut File, len: u64) -> BackendResult<Bytes> {
+    let len = usize::try_from(len).map_err(|_| BackendError::FileTooLarge)?;
+    let mut content = Vec::new();
+    // A sparse file can claim far more than fits in memory
+    content
+        .try_reserve_exact(len)
+        .map_err(|_| BackendError::FileTooLarge)?;
+    rewind(file).await?;
+    file.read_to_end(&mut content).await.map_err(map_io_error)?;
+    // A trailing hole is not materialized by the filesystem
+    content.resize(len, 0);
+    Ok(Bytes::from(content))
+}
+
+/// Flush pending writes and seek back to the start of the file
+async fn rewind(file: &mut File) -> BackendResult<()> {
+    file.flush().await.map_err(map_io_error)?;
+    file.seek(SeekFrom::Start(0)).await.map_err(map_io_error)?;
+    Ok(())
+}
+
+/// Read until `buf` is full or EOF, returning the number of bytes read
+async fn read_full(file: &mut File, buf: &mut [u8]) -> BackendResult<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = file.read(&mut buf[filled..]).await.map_err(map_io_error)?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
+}
+
+fn not_loaded() -> BackendError {
+    BackendError::Other("write buffer contents were not loaded".to_string())
+}
+
+fn map_io_error(err: std::io::Error) -> BackendError {
+    BackendError::Io(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use proptest::prelude::*;
+
+    #[tokio::test]
+    async fn test_small_writes_stay_in_memory() {
+        let mut buffer = WriteBuffer::new(16);
+        buffer.write_at(0, b"hello").await.unwrap();
+        assert!(!buffer.is_spilled());
+        assert_eq!(buffer.into_bytes().await.unwrap().as_ref(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_spills_past_threshold() {
+        let mut buffer = WriteBuffer::new(8);
+        buffer.write_at(0, b"hello").await.unwrap();
+        buffer.write_at(5, b" world").await.unwrap();
+        assert!(buffer.is_spilled());
+        assert_eq!(buffer.len(), 11);
+        assert_eq!(buffer.into_bytes().await.unwrap().as_ref(), b"hello world");
+    }
+
+    #[tokio::test]
+    async fn test_spilled_holes_are_zero_filled() {
+        let mut buffer = WriteBuffer::new(4);
+        buffer.write_at(6, b"cd").await.unwrap();
+        buffer.write_at(0, b"ab").await.unwrap();
+        assert!(buffer.is_spilled());
+        assert_eq!(buffer.into_bytes().await.unwrap().as_ref(), b"ab\0\0\0\0cd");
+    }
+
+    #[tokio::test]
+    async fn test_far_offsets_fail_without_panicking() {
+        for threshold in [usize::MAX, 0] {
+            let mut buffer = WriteBuffer::new(threshold);
+            buffer.write_at(0, b"ab").await.unwrap();
+            assert!(matches!(
+                buffer.write_at(u64::MAX - 1, b"abcd").await,
+                Err(BackendError::FileTooLarge)
+            ));
+            assert_eq!(buffer.len(), 2);
+        }
+
+        // A sparse spill file larger than memory can't be read back
+        let mut buffer = WriteBuffer::new(0);
+        buffer.write_at(1 << 42, b"x").await.unwrap();
+        assert!(matches!(
+            buffer.into_bytes().await,
+            Err(BackendError::FileTooLarge)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_commit_writes_to_backend() {
+        let backend = MemoryBackend::new();
+        let mut buffer = WriteBuffer::new(4);
+        buffer.write_at(0, b"spilled data").await.unwrap();
+        buffer.commit(&backend, "file.txt").await.unwrap();
+
+        let read = backend.read_file("file.txt").await.unwrap();
+        assert_eq!(read.as_ref(), b"spilled data");
+    }
+
+    #[tokio::test]
+    async fn test_preload_and_read_back() {
+        let backend = MemoryBackend::new();
+        backend
+            .write_file("file.txt", Bytes::from_static(b"hello world"))
+            .await
+            .unwrap();
+        let mut buffer = WriteBuffer::preload(&backend, "file.txt", 11, 4)
+            .await
+            .unwrap();
+        assert!(buffer.is_spilled());
+        assert!(!buffer.is_changed());
+        assert_eq!(buffer.read_at(6, 100).await.unwrap(), b"world");
+
+        buffer.write_at(0, b"J").await.unwrap();
+        assert!(buffer.is_changed());
+        assert_eq!(buffer.read_at(0, 5).await.unwrap(), b"Jello");
+        assert!(buffer.read_at(11, 5).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_stored_loads_on_demand() {
+        let backend = MemoryBackend::new();
+        backend
+            .write_file("file.txt", Bytes::from_static(b"hello"))
+            .await
+            .unwrap();
+        let mut buffer = WriteBuffer::stored(5, usize::MAX);
+        assert!(buffer.is_synced());
+        assert!(!buffer.is_changed());
+        assert!(buffer.write_at(5, b"!").await.is_err());
+
+        buffer.load(&backend, "file.txt").await.unwrap();
+        assert!(!buffer.is_changed());
+        buffer.write_at(5, b"!").await.unwrap();
+        buffer.commit(&backend, "file.txt").await.unwrap();
+        assert_eq!(
+            backend.read_file("file.txt").await.unwrap().as_ref(),
+            b"hello!"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sync_stores_and_keeps_buffer() {
+        let backend = MemoryBackend::new();
+        for threshold in [usize::MAX, 0] {
+            let mut buffer = WriteBuffer::new(threshold);
+            buffer.write_at(0, b"hello").await.unwrap();
+            buffer.sync(&backend, "file.txt").await.unwrap();
+            assert!(buffer.is_synced());
+            assert!(!buffer.is_changed());
+            assert_eq!(
+                backend.read_file("file.txt").await.unwrap().as_ref(),
+                b"hello"
+            );
+
+            buffer.write_at(5, b" world").await.unwrap();
+            assert!(buffer.is_changed());
+            assert_eq!(
+                backend.read_file("file.txt").await.unwrap().as_ref(),
+                b"hello"
+            );
+            buffer.commit(&backend, "file.txt").await.unwrap();
+            assert_eq!(
+                backend.read_file("file.txt").await.unwrap().as_ref(),
+                b"hello world"
+            );
+        }
+    }
+
+    proptest! {
+        // Spilled and in-memory buffers agree for any write sequence
+        #[test]
+        fn prop_spill_matches_memory(
+            writes in prop::collection::vec(
+                (0u64..256, prop::collection::vec(any::<u8>(), 0..64)),
+                0..16
+            )
+        ) {
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            rt.block_on(async {
+                let mut memory = WriteBuffer::new(usize::MAX);
+                let mut spilled = WriteBuffer::new(0);
+                for (offset, data) in &writes {
+                    memory.write_at(*offset, data).await.unwrap();
+                    spilled.write_at(*offset, data).await.unwrap();
+                }
+                prop_assert_eq!(memory.len(), spilled.len());
+                let memory = memory.into_bytes().await.unwrap();
+                let spilled = spilled.into_bytes().await.unwrap();
+                prop_assert_eq!(memory, spilled);
+                Ok(())
+            })?
+        }
+    }
+}
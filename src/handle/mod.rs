@@ -1,30 +1,233 @@
+pub mod buffer;
+
+pub use buffer::{WriteBuffer, DEFAULT_SPILL_THRESHOLD};
+
+use crate::backend::{BackendError, BackendResult, FileWriter};
 use bytes::Bytes;
 use parking_lot::RwLock;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Default largest file a client may write: 5 TiB, the S3 object limit
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 5 * 1024 * 1024 * 1024 * 1024;
+
+/// Maximum bytes held for writes that arrive ahead of the streaming position
+const MAX_PENDING_BYTES: usize = 16 * 1024 * 1024;
+
+/// Chunk size used when zero-filling holes in a streaming upload
+const ZERO_FILL_CHUNK: u64 = 64 * 1024;
+
+/// Streaming upload state for a write handle
+///
+/// SFTP writes carry an offset, but a `FileWriter` only appends. Writes that
+/// arrive ahead of the current position are held until the gap is filled;
+/// any gaps left at commit time are zero-filled.
+pub struct Upload {
+    /// Taken when the upload is committed early by `sync`
+    writer: Option<Box<dyn FileWriter>>,
+    /// Bytes handed to the writer so far
+    written: u64,
+    /// Writes received ahead of `written`, keyed by offset
+    pending: BTreeMap<u64, Vec<u8>>,
+    pending_bytes: usize,
+}
+
+impl Upload {
+    pub fn new(writer: Box<dyn FileWriter>) -> Self {
+        Self {
+            writer: Some(writer),
+            written: 0,
+            pending: BTreeMap::new(),
+            pending_bytes: 0,
+        }
+    }
+
+    /// Current file size including writes not yet streamed
+    pub fn len(&self) -> u64 {
+        self.pending
+            .iter()
+            .next_back()
+            .map(|(offset, data)| offset + data.len() as u64)
+            .unwrap_or(0)
+            .max(self.written)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Write `data` at `offset`
+    pub async fn write(&mut self, offset: u64, data: Vec<u8>) -> BackendResult<()> {
+        if self.writer.is_none() {
+            return Err(already_synced());
+        }
+        if offset < self.written {
+            return Err(BackendError::Other(
+                "streaming upload does not support rewriting data".to_string(),
+            ));
+        }
+
+        // Holes are zero-filled at commit, so the end must be representable
+        offset
+            .checked_add(data.len() as u64)
+            .ok_or(BackendError::FileTooLarge)?;
+
+        if offset > self.written {
+            // A retransmitted write replaces the one held at its offset
+            let replaced = self.pending.get(&offset).map_or(0, Vec::len);
+            let pending_bytes = self.pending_bytes - replaced + data.len();
+            if pending_bytes > MAX_PENDING_BYTES {
+                return Err(BackendError::Other(
+                    "too much out-of-order data in streaming upload".to_string(),
+                ));
+            }
+            self.pending_bytes = pending_bytes;
+            self.pending.insert(offset, data);
+            return Ok(());
+        }
+
+        self.append(data).await?;
+
+        // Drain writes that are now contiguous
+        while let Some(entry) = self.pending.first_entry() {
+            if *entry.key() != self.written {
+                break;
+            }
+            let data = entry.remove();
+            self.pending_bytes -= data.len();
+            self.append(data).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Flush pending writes (zero-filling holes) and commit the upload
+    pub async fn commit(mut self) -> BackendResult<()> {
+        self.sync().await
+    }
+
+    /// Commit the upload while the handle stays open, for fsync
+    ///
+    /// A streaming upload can't be extended once committed, so later writes
+    /// fail and the final `commit` has nothing left to do.
+    pub async fn sync(&mut self) -> BackendResult<()> {
+        if self.writer.is_none() {
+            return Ok(());
+        }
+        while let Some((offset, data)) = self.pending.pop_first() {
+            if offset < self.written {
+                return Err(BackendError::Other(
+                    "overlapping writes in streaming upload".to_string(),
+                ));
+            }
+            while self.written < offset {
+                let fill = std::cmp::min(offset - self.written, ZERO_FILL_CHUNK);
+                self.append(vec![0; fill as usize]).await?;
+            }
+            self.append(data).await?;
+        }
+
+        match self.writer.take() {
+            Some(writer) => writer.commit().await,
+            None => Ok(()),
+        }
+    }
+
+    /// Discard the upload, unless `sync` already committed it
+    pub async fn abort(self) -> BackendResult<()> {
+        match self.writer {
+            Some(writer) => writer.abort().await,
+            None => Ok(()),
+        }
+    }
+
+    async fn append(&mut self, data: Vec<u8>) -> BackendResult<()> {
+        let len = data.len() as u64;
+        let writer = self.writer.as_mut().ok_or_else(already_synced)?;
+        writer.write(Bytes::from(data)).await?;
+        self.written += len;
+        Ok(())
+    }
+}
+
+fn already_synced() -> BackendError {
+    BackendError::Other("streaming upload was already committed by fsync".to_string())
+}
+
+impl std::fmt::Debug for Upload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Upload")
+            .field("written", &self.written)
+            .field("pending", &self.pending.len())
+            .finish()
+    }
+}
+
+/// How a write handle was opened
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteMode {
+    /// Every write goes to the end of the file, whatever its offset
+    pub append: bool,
+    /// The handle may also be read from
+    pub read: bool,
+}
 
 /// Types of file handles
 #[derive(Debug, Clone)]
 pub enum HandleType {
     /// Directory handle for listing
     Dir { path: String, read_done: bool },
-    /// Read handle with buffered content (Bytes clone is O(1))
-    Read { path: String, content: Bytes },
-    /// Write handle with accumulating buffer
-    Write { path: String, buffer: Vec<u8> },
+    /// Read handle; data is fetched from the backend per request
+    Read { path: String, size: u64 },
+    /// Write handle accumulating data until close
+    ///
+    /// The buffer is taken out of the `Option` when the handle is closed.
+    Write {
+        path: String,
+        buffer: Arc<Mutex<Option<WriteBuffer>>>,
+        mode: WriteMode,
+    },
+    /// Write handle streaming to the backend as data arrives
+    ///
+    /// The upload is taken out of the `Option` when the handle is closed.
+    /// Never readable.
+    Upload {
+        path: String,
+        upload: Arc<Mutex<Option<Upload>>>,
+        mode: WriteMode,
+    },
+}
+
+/// What happens to write handles still open when a session ends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnflushedWrites {
+    /// Drop buffered data and abort streaming uploads
+    #[default]
+    Discard,
+    /// Store whatever was written, as if the client had closed the handle
+    Commit,
 }
 
 /// Manages file handles for SFTP sessions using numeric IDs
 pub struct HandleManager {
     handles: RwLock<HashMap<u64, HandleType>>,
     next_id: AtomicU64,
+    spill_threshold: usize,
 }
 
 impl HandleManager {
     pub fn new() -> Self {
+        Self::with_spill_threshold(DEFAULT_SPILL_THRESHOLD)
+    }
+
+    /// Create a manager whose write buffers spill to disk past `threshold` bytes
+    pub fn with_spill_threshold(threshold: usize) -> Self {
         Self {
             handles: RwLock::new(HashMap::new()),
             next_id: AtomicU64::new(1),
+            spill_threshold: threshold,
         }
     }
 
@@ -44,26 +247,74 @@ impl HandleManager {
         id.to_string()
     }
 
-    pub fn create_read_handle(&self, path: String, content: Bytes) -> String {
+    pub fn create_read_handle(&self, path: String, size: u64) -> String {
         let id = self.generate_handle();
         self.handles
             .write()
-            .insert(id, HandleType::Read { path, content });
+            .insert(id, HandleType::Read { path, size });
         id.to_string()
     }
 
+    /// Bytes a write buffer holds in memory before spilling to disk
+    pub fn spill_threshold(&self) -> usize {
+        self.spill_threshold
+    }
+
+    /// Write handle for a new, empty file
     pub fn create_write_handle(&self, path: String) -> String {
+        let buffer = WriteBuffer::new(self.spill_threshold);
+        self.create_buffered_handle(path, buffer, WriteMode::default())
+    }
+
+    /// Write handle starting from `buffer`, e.g. a file's current contents
+    pub fn create_buffered_handle(
+        &self,
+        path: String,
+        buffer: WriteBuffer,
+        mode: WriteMode,
+    ) -> String {
         let id = self.generate_handle();
         self.handles.write().insert(
             id,
             HandleType::Write {
                 path,
-                buffer: Vec::new(),
+                buffer: Arc::new(Mutex::new(Some(buffer))),
+                mode,
             },
         );
         id.to_string()
     }
 
+    pub fn create_upload_handle(
+        &self,
+        path: String,
+        writer: Box<dyn FileWriter>,
+        mode: WriteMode,
+    ) -> String {
+        let id = self.generate_handle();
+        self.handles.write().insert(
+            id,
+            HandleType::Upload {
+                path,
+                upload: Arc::new(Mutex::new(Some(Upload::new(writer)))),
+                mode,
+            },
+        );
+        id.to_string()
+    }
+
+    /// Point `handle` at a `len`-byte file the backend already stored
+    ///
+    /// The handle becomes a buffered write handle over the stored file, which
+    /// is only read back if the client writes to it again.
+    pub fn replace_with_stored(&self, handle: &str, path: String, len: u64, mode: WriteMode) {
+        let buffer = Arc::new(Mutex::new(Some(WriteBuffer::stored(
+            len,
+            self.spill_threshold,
+        ))));
+        self.update(handle, HandleType::Write { path, buffer, mode });
+    }
+
     pub fn get(&self, handle: &str) -> Option<HandleType> {
         let id: u64 = handle.parse().ok()?;
         self.handles.read().get(&id).cloned()
@@ -79,6 +330,24 @@ impl HandleManager {
         let id: u64 = handle.parse().ok()?;
         self.handles.write().remove(&id)
     }
+
+    /// Number of open handles
+    pub fn len(&self) -> usize {
+        self.handles.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handles.read().is_empty()
+    }
+
+    /// Remove and return every open handle with its id
+    pub fn drain(&self) -> Vec<(String, HandleType)> {
+        self.handles
+            .write()
+            .drain()
+            .map(|(id, handle)| (id.to_string(), handle))
+            .collect()
+    }
 }
 
 impl Default for HandleManager {
@@ -106,15 +375,14 @@ mod tests {
     #[test]
     fn test_get_returns_created_data() {
         let manager = HandleManager::new();
-        let content = Bytes::from_static(b"hello");
-        let handle = manager.create_read_handle("test.txt".to_string(), content.clone());
+        let handle = manager.create_read_handle("test.txt".to_string(), 5);
 
         let data = manager.get(&handle);
         assert!(data.is_some());
         match data.unwrap() {
-            HandleType::Read { path, content: c } => {
+            HandleType::Read { path, size } => {
                 assert_eq!(path, "test.txt");
-                assert_eq!(c, content);
+                assert_eq!(size, 5);
             }
             _ => panic!("Wrong handle type"),
         }
@@ -130,27 +398,161 @@ mod tests {
         assert!(manager.get(&handle).is_none());
     }
 
+    #[test]
+    fn test_drain_removes_everything() {
+        let manager = HandleManager::new();
+        let write = manager.create_write_handle("a.txt".to_string());
+        let dir = manager.create_dir_handle("dir".to_string());
+
+        assert_eq!(manager.drain().len(), 2);
+        assert!(manager.get(&write).is_none());
+        assert!(manager.get(&dir).is_none());
+        assert!(manager.drain().is_empty());
+    }
+
     #[test]
     fn test_update_modifies_data() {
         let manager = HandleManager::new();
-        let handle = manager.create_write_handle("test.txt".to_string());
+        let handle = manager.create_dir_handle("dir".to_string());
 
         manager.update(
             &handle,
-            HandleType::Write {
-                path: "test.txt".to_string(),
-                buffer: vec![1, 2, 3],
+            HandleType::Dir {
+                path: "dir".to_string(),
+                read_done: true,
             },
         );
 
         match manager.get(&handle).unwrap() {
-            HandleType::Write { buffer, .. } => {
-                assert_eq!(buffer, vec![1, 2, 3]);
+            HandleType::Dir { read_done, .. } => {
+                assert!(read_done);
             }
             _ => panic!("Wrong handle type"),
         }
     }
 
+    /// What a `VecWriter` has received
+    #[derive(Default)]
+    struct Recorded {
+        data: Vec<u8>,
+        committed: bool,
+    }
+
+    /// FileWriter that appends into a shared buffer
+    struct VecWriter(Arc<parking_lot::Mutex<Recorded>>);
+
+    #[async_trait::async_trait]
+    impl FileWriter for VecWriter {
+        async fn write(&mut self, data: Bytes) -> BackendResult<()> {
+            self.0.lock().data.extend_from_slice(&data);
+            Ok(())
+        }
+
+        async fn commit(self: Box<Self>) -> BackendResult<()> {
+            self.0.lock().committed = true;
+            Ok(())
+        }
+
+        async fn abort(self: Box<Self>) -> BackendResult<()> {
+            Ok(())
+        }
+    }
+
+    fn vec_upload() -> (Upload, Arc<parking_lot::Mutex<Recorded>>) {
+        let recorded = Arc::new(parking_lot::Mutex::new(Recorded::default()));
+        let upload = Upload::new(Box::new(VecWriter(recorded.clone())));
+        (upload, recorded)
+    }
+
+    #[tokio::test]
+    async fn test_upload_sequential_writes_stream_immediately() {
+        let (mut upload, recorded) = vec_upload();
+
+        upload.write(0, b"hello ".to_vec()).await.unwrap();
+        assert_eq!(recorded.lock().data, b"hello ");
+        upload.write(6, b"world".to_vec()).await.unwrap();
+        assert_eq!(upload.len(), 11);
+
+        upload.commit().await.unwrap();
+        assert_eq!(recorded.lock().data, b"hello world");
+        assert!(recorded.lock().committed);
+    }
+
+    #[tokio::test]
+    async fn test_upload_retransmitted_writes_count_once() {
+        let (mut upload, recorded) = vec_upload();
+
+        // Resending the same out-of-order chunk must not eat into the limit
+        let chunk = vec![1; MAX_PENDING_BYTES / 2];
+        for _ in 0..4 {
+            upload.write(1, chunk.clone()).await.unwrap();
+        }
+        assert_eq!(upload.pending_bytes, chunk.len());
+
+        upload.write(0, vec![0]).await.unwrap();
+        assert_eq!(upload.pending_bytes, 0);
+        upload.commit().await.unwrap();
+        assert_eq!(recorded.lock().data.len(), chunk.len() + 1);
+    }
+
+    #[tokio::test]
+    async fn test_upload_rejects_offsets_past_u64() {
+        let (mut upload, recorded) = vec_upload();
+
+        assert!(matches!(
+            upload.write(u64::MAX, b"x".to_vec()).await,
+            Err(BackendError::FileTooLarge)
+        ));
+        assert_eq!(upload.pending_bytes, 0);
+        upload.commit().await.unwrap();
+        assert!(recorded.lock().data.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_upload_reorders_out_of_order_writes() {
+        let (mut upload, recorded) = vec_upload();
+
+        upload.write(6, b"world".to_vec()).await.unwrap();
+        assert!(recorded.lock().data.is_empty());
+        assert_eq!(upload.len(), 11);
+
+        upload.write(0, b"hello ".to_vec()).await.unwrap();
+        assert_eq!(recorded.lock().data, b"hello world");
+    }
+
+    #[tokio::test]
+    async fn test_upload_zero_fills_holes_on_commit() {
+        let (mut upload, recorded) = vec_upload();
+
+        upload.write(0, b"ab".to_vec()).await.unwrap();
+        upload.write(4, b"cd".to_vec()).await.unwrap();
+        upload.commit().await.unwrap();
+
+        assert_eq!(recorded.lock().data, b"ab\0\0cd");
+    }
+
+    #[tokio::test]
+    async fn test_upload_rejects_rewrites() {
+        let (mut upload, _) = vec_upload();
+
+        upload.write(0, b"hello".to_vec()).await.unwrap();
+        assert!(upload.write(0, b"j".to_vec()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_upload_sync_commits_early() {
+        let (mut upload, recorded) = vec_upload();
+
+        upload.write(0, b"ab".to_vec()).await.unwrap();
+        upload.write(4, b"cd".to_vec()).await.unwrap();
+        upload.sync().await.unwrap();
+        assert_eq!(recorded.lock().data, b"ab\0\0cd");
+        assert!(recorded.lock().committed);
+
+        assert!(upload.write(6, b"ef".to_vec()).await.is_err());
+        upload.commit().await.unwrap();
+    }
+
     proptest! {
         #[test]
         fn prop_handles_are_unique(count in 1usize..500) {
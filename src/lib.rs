@@ -61,19 +61,46 @@
 //! }
 //! ```
 
+pub mod audit;
+pub mod auth;
 pub mod backend;
+pub mod config;
 pub mod error;
+pub mod events;
 pub mod handle;
+pub mod limits;
+pub mod permissions;
 pub mod server;
 pub mod sftp_handler;
+pub mod shutdown;
 pub mod ssh_handler;
+pub mod telemetry;
 
 // Re-exports for convenience
+pub use audit::{
+    AuditEvent, AuditKind, AuditLog, AuditSink, JsonLinesSink, TracingSink, TransferMode,
+};
+pub use auth::{
+    AuthContext, AuthMethod, Authenticator, AuthorizedKeys, AuthorizedKeysFile, Challenge,
+    ChallengeHandler, ChallengeResult, PasswordFile, Prompt, TrustedUserCaKeys,
+};
 pub use backend::local::LocalBackend;
 pub use backend::memory::MemoryBackend;
-pub use backend::{Backend, BackendError, BackendResult, DirEntry, FileInfo};
+pub use backend::scoped::ScopedBackend;
+pub use backend::{
+    AttrCapabilities, Backend, BackendError, BackendFactory, BackendResult, DirEntry, FileInfo,
+    FileType, FileWriter, SetAttrs, SpaceInfo,
+};
 #[cfg(feature = "s3")]
 pub use backend::{S3Backend, S3Config};
 
+pub use config::{Config, ConfigError};
 pub use error::Error;
-pub use server::{Server, ServerConfig};
+pub use events::{
+    CommandHook, EventDelivery, EventHook, FileEvent, FileEventKind, HookError, WebhookHook,
+};
+pub use handle::UnflushedWrites;
+pub use limits::BanPolicy;
+pub use permissions::{Effect, Operation, PermissionPolicy, Rule};
+pub use server::{MethodSet, Server, ServerConfig};
+pub use shutdown::ShutdownHandle;
@@ -0,0 +1,306 @@
+//! Connection limits and temporary bans for repeated login failures
+
+use crate::telemetry;
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Tracked addresses beyond which stale failure records are pruned
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// Temporarily ban addresses that fail to log in too often, like fail2ban
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BanPolicy {
+    /// Failed logins that trigger a ban
+    pub max_failures: usize,
+    /// Window the failures must fall within
+    pub find_time: Duration,
+    /// How long the address stays banned
+    pub ban_time: Duration,
+}
+
+impl BanPolicy {
+    pub fn new(max_failures: usize, find_time: Duration, ban_time: Duration) -> Self {
+        Self {
+            max_failures,
+            find_time,
+            ban_time,
+        }
+    }
+}
+
+impl Default for BanPolicy {
+    /// fail2ban's sshd defaults: 5 failures in 10 minutes ban for 10 minutes
+    fn default() -> Self {
+        Self::new(5, Duration::from_secs(600), Duration::from_secs(600))
+    }
+}
+
+/// Why a connection was turned away
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub(crate) enum Refusal {
+    #[error("address is banned")]
+    Banned,
+    #[error("too many connections")]
+    TooManyConnections,
+    #[error("too many connections from this address")]
+    TooManyFromAddress,
+}
+
+impl Refusal {
+    /// Short name for metrics labels
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Self::Banned => "banned",
+            Self::TooManyConnections => "max_connections",
+            Self::TooManyFromAddress => "max_connections_per_ip",
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+    failures: HashMap<IpAddr, VecDeque<Instant>>,
+    bans: HashMap<IpAddr, Instant>,
+}
+
+impl State {
+    fn is_banned(&mut self, ip: IpAddr, now: Instant) -> bool {
+        match self.bans.get(&ip) {
+            Some(&until) if until > now => true,
+            Some(_) => {
+                self.bans.remove(&ip);
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn prune(&mut self, find_time: Duration, now: Instant) {
+        self.bans.retain(|_, until| *until > now);
+        self.failures.retain(|_, times| {
+            times
+                .back()
+                .is_some_and(|last| now.duration_since(*last) < find_time)
+        });
+    }
+}
+
+/// Shared connection counts, failure history and bans
+pub(crate) struct Limiter {
+    max_connections: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    ban: Option<BanPolicy>,
+    state: Mutex<State>,
+}
+
+impl Limiter {
+    pub(crate) fn new(
+        max_connections: Option<usize>,
+        max_connections_per_ip: Option<usize>,
+        ban: Option<BanPolicy>,
+    ) -> Self {
+        Self {
+            max_connections,
+            max_connections_per_ip,
+            ban,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Count a new connection from `peer` unless a limit or ban forbids it
+    pub(crate) fn admit(
+        self: &Arc<Self>,
+        peer: Option<IpAddr>,
+    ) -> Result<ConnectionGuard, Refusal> {
+        let mut state = self.state.lock();
+        if let Some(ip) = peer {
+            if state.is_banned(ip, Instant::now()) {
+                return Err(Refusal::Banned);
+            }
+        }
+        if self.max_connections.is_some_and(|max| state.total >= max) {
+            return Err(Refusal::TooManyConnections);
+        }
+        if let (Some(ip), Some(max)) = (peer, self.max_connections_per_ip) {
+            if state.per_ip.get(&ip).copied().unwrap_or(0) >= max {
+                return Err(Refusal::TooManyFromAddress);
+            }
+        }
+
+        state.total += 1;
+        if let Some(ip) = peer {
+            *state.per_ip.entry(ip).or_insert(0) += 1;
+        }
+        telemetry::connection_admitted();
+        Ok(ConnectionGuard {
+            limiter: self.clone(),
+            peer,
+        })
+    }
+
+    fn is_banned(&self, ip: IpAddr) -> bool {
+        self.state.lock().is_banned(ip, Instant::now())
+    }
+
+    /// Record a failed login, returning whether `ip` is now banned
+    fn record_failure(&self, ip: IpAddr) -> bool {
+        self.record_failure_at(ip, Instant::now())
+    }
+
+    fn record_failure_at(&self, ip: IpAddr, now: Instant) -> bool {
+        let Some(policy) = self.ban else {
+            return false;
+        };
+        let mut state = self.state.lock();
+        if state.failures.len() > PRUNE_THRESHOLD {
+            state.prune(policy.find_time, now);
+        }
+
+        let times = state.failures.entry(ip).or_default();
+        times.push_back(now);
+        while times
+            .front()
+            .is_some_and(|first| now.duration_since(*first) >= policy.find_time)
+        {
+            times.pop_front();
+        }
+        if times.len() < policy.max_failures {
+            return false;
+        }
+
+        state.failures.remove(&ip);
+        state.bans.insert(ip, now + policy.ban_time);
+        true
+    }
+
+    fn release(&self, peer: Option<IpAddr>) {
+        telemetry::connection_closed();
+        let mut state = self.state.lock();
+        state.total = state.total.saturating_sub(1);
+        if let Some(ip) = peer {
+            if let Some(count) = state.per_ip.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    state.per_ip.remove(&ip);
+                }
+            }
+        }
+    }
+}
+
+/// An admitted connection; releases its slot when dropped
+pub(crate) struct ConnectionGuard {
+    limiter: Arc<Limiter>,
+    peer: Option<IpAddr>,
+}
+
+impl ConnectionGuard {
+    /// Whether this connection's address has been banned since it connected
+    pub(crate) fn is_banned(&self) -> bool {
+        self.peer.is_some_and(|ip| self.limiter.is_banned(ip))
+    }
+
+    /// Record a failed login from this connection, returning whether its
+    /// address is now banned
+    pub(crate) fn record_failure(&self) -> bool {
+        self.peer.is_some_and(|ip| self.limiter.record_failure(ip))
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.limiter.release(self.peer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn test_max_connections() {
+        let limiter = Arc::new(Limiter::new(Some(2), None, None));
+        let a = limiter.admit(ip("10.0.0.1")).unwrap();
+        let _b = limiter.admit(ip("10.0.0.2")).unwrap();
+        assert_eq!(
+            limiter.admit(ip("10.0.0.3")).err(),
+            Some(Refusal::TooManyConnections)
+        );
+
+        drop(a);
+        assert!(limiter.admit(ip("10.0.0.3")).is_ok());
+    }
+
+    #[test]
+    fn test_max_connections_per_ip() {
+        let limiter = Arc::new(Limiter::new(None, Some(1), None));
+        let a = limiter.admit(ip("10.0.0.1")).unwrap();
+        assert_eq!(
+            limiter.admit(ip("10.0.0.1")).err(),
+            Some(Refusal::TooManyFromAddress)
+        );
+        assert!(limiter.admit(ip("10.0.0.2")).is_ok());
+
+        drop(a);
+        assert!(limiter.admit(ip("10.0.0.1")).is_ok());
+    }
+
+    #[test]
+    fn test_ban_after_repeated_failures() {
+        let policy = BanPolicy::new(3, Duration::from_secs(60), Duration::from_secs(300));
+        let limiter = Arc::new(Limiter::new(None, None, Some(policy)));
+        let guard = limiter.admit(ip("10.0.0.1")).unwrap();
+
+        assert!(!guard.record_failure());
+        assert!(!guard.record_failure());
+        assert!(!guard.is_banned());
+        assert!(guard.record_failure());
+        assert!(guard.is_banned());
+
+        assert_eq!(limiter.admit(ip("10.0.0.1")).err(), Some(Refusal::Banned));
+        assert!(limiter.admit(ip("10.0.0.2")).is_ok());
+    }
+
+    #[test]
+    fn test_failures_outside_window_do_not_count() {
+        let policy = BanPolicy::new(2, Duration::from_secs(60), Duration::from_secs(300));
+        let limiter = Limiter::new(None, None, Some(policy));
+        let addr = ip("10.0.0.1").unwrap();
+        let start = Instant::now();
+
+        assert!(!limiter.record_failure_at(addr, start));
+        assert!(!limiter.record_failure_at(addr, start + Duration::from_secs(61)));
+        assert!(limiter.record_failure_at(addr, start + Duration::from_secs(62)));
+    }
+
+    #[test]
+    fn test_ban_expires() {
+        let policy = BanPolicy::new(1, Duration::from_secs(60), Duration::from_millis(10));
+        let limiter = Arc::new(Limiter::new(None, None, Some(policy)));
+        let addr = ip("10.0.0.1");
+
+        assert!(limiter.admit(addr).unwrap().record_failure());
+        assert_eq!(limiter.admit(addr).err(), Some(Refusal::Banned));
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(limiter.admit(addr).is_ok());
+    }
+
+    #[test]
+    fn test_no_ban_policy() {
+        let limiter = Arc::new(Limiter::new(None, None, None));
+        let guard = limiter.admit(ip("10.0.0.1")).unwrap();
+        for _ in 0..100 {
+            assert!(!guard.record_failure());
+        }
+        assert!(!guard.is_banned());
+    }
+}
@@ -1,18 +1,39 @@
 //! SFTP server with pluggable backends (local filesystem, S3, memory)
 
-use clap::{Parser, Subcommand};
-use sftp_s3::{LocalBackend, MemoryBackend, Server, ServerConfig};
-use std::path::PathBuf;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use sftp_s3::{
+    AuthorizedKeys, BanPolicy, CommandHook, Config, EventDelivery, JsonLinesSink, LocalBackend,
+    MemoryBackend, PermissionPolicy, Server, ServerConfig, TracingSink, UnflushedWrites,
+    WebhookHook,
+};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tracing_subscriber::EnvFilter;
 
 #[derive(Parser)]
 #[command(name = "sftp-s3")]
 #[command(about = "SFTP server with pluggable backends", long_about = None)]
 struct Cli {
+    /// TOML or YAML file describing listeners, users, backends and limits
+    ///
+    /// Can't be combined with the other options or a backend subcommand;
+    /// those set through the environment are ignored.
+    #[arg(long, env = "SFTP_CONFIG", global = true)]
+    config: Option<PathBuf>,
+
     /// Port to listen on
     #[arg(short, long, env = "PORT", default_value = "2222")]
     port: u16,
 
+    /// Address to listen on, e.g. 127.0.0.1:2222 or [::]:2222 (can be repeated)
+    ///
+    /// Overrides --port. Ignored when started through systemd socket activation.
+    #[arg(short, long = "bind", env = "SFTP_BIND", value_delimiter = ',')]
+    bind: Vec<SocketAddr>,
+
     /// Path to host key file (OpenSSH format)
     #[arg(long, env = "HOST_KEY_FILE")]
     host_key_file: Option<PathBuf>,
@@ -25,25 +46,150 @@ struct Cli {
     #[arg(short, long = "user", env = "SFTP_USERS", value_delimiter = ',')]
     users: Vec<String>,
 
+    /// Path to an htpasswd-style file of hashed passwords (bcrypt, argon2id, sha512-crypt)
+    ///
+    /// Reloaded when the server receives SIGHUP.
+    #[arg(long, env = "SFTP_PASSWORD_FILE")]
+    password_file: Option<PathBuf>,
+
     /// Path to authorized_keys file for public key auth
+    ///
+    /// `%u` is replaced with the login name (e.g. /etc/sftp/keys/%u) so each
+    /// user has their own keys; without it the keys apply to every user.
     #[arg(long, env = "AUTHORIZED_KEYS_FILE")]
-    authorized_keys_file: Option<PathBuf>,
+    authorized_keys_file: Option<String>,
 
     /// Authorized public keys (OpenSSH format, newline-separated)
     #[arg(long, env = "AUTHORIZED_KEYS", hide = true)]
     authorized_keys: Option<String>,
 
+    /// File of CA public keys trusted to sign user certificates
+    #[arg(long, env = "TRUSTED_USER_CA_KEYS")]
+    trusted_user_ca_keys: Option<PathBuf>,
+
+    /// TOML file of permission rules (users, operations and path globs)
+    #[arg(long, env = "SFTP_PERMISSIONS")]
+    permissions: Option<PathBuf>,
+
+    /// Deny all uploads, deletes, renames and directory changes
+    #[arg(long, env = "SFTP_READ_ONLY")]
+    read_only: bool,
+
+    /// Bytes an upload may buffer in memory before spilling to a temp file
+    #[arg(long, env = "SPILL_THRESHOLD")]
+    spill_threshold: Option<usize>,
+
+    /// Largest file in bytes a client may write
+    #[arg(long, env = "MAX_FILE_SIZE")]
+    max_file_size: Option<u64>,
+
+    /// Seconds to wait for in-flight transfers on SIGTERM/SIGINT
+    #[arg(long, env = "SHUTDOWN_TIMEOUT", default_value = "30")]
+    shutdown_timeout: u64,
+
+    /// Maximum concurrent connections
+    #[arg(long, env = "MAX_CONNECTIONS")]
+    max_connections: Option<usize>,
+
+    /// Maximum concurrent connections from one IP address
+    #[arg(long, env = "MAX_CONNECTIONS_PER_IP")]
+    max_connections_per_ip: Option<usize>,
+
+    /// Failed logins allowed per connection before disconnecting
+    #[arg(long, env = "MAX_AUTH_ATTEMPTS", default_value = "6")]
+    max_auth_attempts: usize,
+
+    /// Ban an IP address after this many failed logins within --ban-find-time
+    #[arg(long, env = "BAN_AFTER")]
+    ban_after: Option<usize>,
+
+    /// Seconds in which --ban-after failures must occur
+    #[arg(long, env = "BAN_FIND_TIME", default_value = "600")]
+    ban_find_time: u64,
+
+    /// Seconds a banned address stays banned
+    #[arg(long, env = "BAN_TIME", default_value = "600")]
+    ban_time: u64,
+
+    /// Seconds without traffic before a connection is closed (0 disables)
+    #[arg(long, env = "IDLE_TIMEOUT", default_value = "600")]
+    idle_timeout: u64,
+
+    /// Seconds after which any connection is closed
+    #[arg(long, env = "MAX_SESSION_DURATION")]
+    max_session_duration: Option<u64>,
+
+    /// Seconds of client silence before sending a keepalive
+    #[arg(long, env = "KEEPALIVE_INTERVAL")]
+    keepalive_interval: Option<u64>,
+
+    /// Unanswered keepalives before the connection is closed
+    #[arg(long, env = "KEEPALIVE_MAX", default_value = "3")]
+    keepalive_max: usize,
+
+    /// Store uploads that were still open when the connection dropped
+    ///
+    /// By default they are discarded.
+    #[arg(long, env = "COMMIT_UNCLOSED_UPLOADS")]
+    commit_unclosed_uploads: bool,
+
+    /// Report success for setstat requests the backend can't fully honor
+    ///
+    /// By default they fail with "operation unsupported".
+    #[arg(long, env = "LENIENT_SETSTAT")]
+    lenient_setstat: bool,
+
+    /// Append a JSON line per login and file operation to this file
+    #[arg(long, env = "AUDIT_LOG")]
+    audit_log: Option<PathBuf>,
+
+    /// Emit audit events through the regular log (target `sftp_s3::audit`)
+    #[arg(long, env = "AUDIT_TRACING")]
+    audit_tracing: bool,
+
+    /// POST a JSON event to this URL after each upload, download, remove and rename
+    #[arg(long, env = "EVENT_WEBHOOK")]
+    event_webhook: Option<String>,
+
+    /// Run this shell command for each event, with the event as JSON on stdin
+    ///
+    /// Details are also passed as SFTP_EVENT, SFTP_USER, SFTP_PATH,
+    /// SFTP_NEW_PATH, SFTP_SIZE and SFTP_SHA256.
+    #[arg(long, env = "EVENT_COMMAND")]
+    event_command: Option<String>,
+
+    /// Events queued for the hooks before new ones are dropped
+    #[arg(long, env = "EVENT_QUEUE_SIZE", default_value = "1024")]
+    event_queue_size: usize,
+
+    /// Tries per event before a hook gives up on it
+    #[arg(long, env = "EVENT_MAX_ATTEMPTS", default_value = "3")]
+    event_max_attempts: usize,
+
+    /// Seconds before retrying a failed event, doubling each time
+    #[arg(long, env = "EVENT_RETRY_DELAY", default_value = "1")]
+    event_retry_delay: u64,
+
+    /// Serve Prometheus metrics on this address, e.g. 127.0.0.1:9100
+    #[cfg(feature = "metrics")]
+    #[arg(long, env = "METRICS_ADDR")]
+    metrics_addr: Option<SocketAddr>,
+
     #[command(subcommand)]
-    backend: BackendCommand,
+    command: Option<Command>,
 }
 
 #[derive(Subcommand)]
-enum BackendCommand {
+enum Command {
     /// Serve files from local filesystem
     Local {
         /// Root directory to serve
         #[arg(default_value = ".")]
         root: PathBuf,
+
+        /// Let clients change the owner and group of files
+        #[arg(long, env = "ALLOW_CHOWN")]
+        allow_chown: bool,
     },
     /// Serve files from S3 bucket
     #[cfg(feature = "s3")]
@@ -66,38 +212,34 @@ enum BackendCommand {
     },
     /// Use in-memory storage (for testing)
     Memory,
+    /// Validate the --config file and print the resolved setup
+    CheckConfig,
 }
 
-/// Parse an OpenSSH public key line
-fn parse_pubkey(line: &str) -> Option<russh::keys::PublicKey> {
-    let line = line.trim();
-    if line.is_empty() || line.starts_with('#') {
-        return None;
-    }
-    let parts: Vec<&str> = line.split_whitespace().collect();
-    if parts.len() < 2 {
-        return None;
-    }
-    russh::keys::parse_public_key_base64(parts[1]).ok()
-}
-
-/// Load authorized keys from file or string
-fn load_authorized_keys(file: Option<&PathBuf>, data: Option<&str>) -> Vec<russh::keys::PublicKey> {
-    let contents = if let Some(path) = file {
-        match std::fs::read_to_string(path) {
-            Ok(c) => c,
-            Err(e) => {
-                eprintln!("Warning: could not read {}: {}", path.display(), e);
-                return Vec::new();
+/// Options other than --config that were given on the command line, and
+/// environment variables that set any
+fn options_besides_config(matches: &ArgMatches) -> (Vec<String>, Vec<String>) {
+    let mut given = Vec::new();
+    let mut from_env = Vec::new();
+    for arg in Cli::command().get_arguments() {
+        let id = arg.get_id().as_str();
+        if id == "config" {
+            continue;
+        }
+        match matches.value_source(id) {
+            Some(ValueSource::CommandLine) => match arg.get_long() {
+                Some(long) => given.push(format!("--{long}")),
+                None => given.push(id.to_string()),
+            },
+            Some(ValueSource::EnvVariable) => {
+                if let Some(var) = arg.get_env() {
+                    from_env.push(var.to_string_lossy().into_owned());
+                }
             }
+            _ => {}
         }
-    } else if let Some(data) = data {
-        data.to_string()
-    } else {
-        return Vec::new();
-    };
-
-    contents.lines().filter_map(parse_pubkey).collect()
+    }
+    (given, from_env)
 }
 
 /// Parse user:password credentials
@@ -113,9 +255,18 @@ fn parse_users(users: &[String]) -> Vec<(String, String)> {
         .collect()
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let cli = Cli::parse();
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Changing the environment is only sound while the process is still
+    // single-threaded, so take the systemd sockets before the runtime starts
+    let listeners = systemd_listeners()?;
+    tokio::runtime::Runtime::new()?.block_on(run(listeners))
+}
+
+async fn run(
+    systemd: Option<Vec<std::net::TcpListener>>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
 
     // Initialize logging
     tracing_subscriber::fmt()
@@ -124,8 +275,96 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         )
         .init();
 
+    if let Some(ref path) = cli.config {
+        let (given, from_env) = options_besides_config(&matches);
+        if let Some(option) = given.first() {
+            Cli::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    format!("{option} can't be combined with --config; set it in the file"),
+                )
+                .exit();
+        }
+        for var in from_env {
+            tracing::warn!("{var} is ignored with --config; set it in the file");
+        }
+        return match cli.command {
+            None => run_config(path, systemd).await,
+            Some(Command::CheckConfig) => check_config(path),
+            Some(_) => Cli::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "backends are set in the --config file, not with a subcommand",
+                )
+                .exit(),
+        };
+    }
+    let backend = match cli.command {
+        Some(Command::CheckConfig) => Cli::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "check-config needs --config",
+            )
+            .exit(),
+        Some(backend) => backend,
+        None => Cli::command()
+            .error(
+                ErrorKind::MissingSubcommand,
+                "a backend subcommand or --config is required",
+            )
+            .exit(),
+    };
+
+    #[cfg(feature = "metrics")]
+    if let Some(addr) = cli.metrics_addr {
+        sftp_s3::telemetry::install_prometheus(addr)?;
+        eprintln!("Serving metrics on http://{addr}/metrics");
+    }
+
     // Build server config
-    let mut config = ServerConfig::new().port(cli.port);
+    let mut config = ServerConfig::new()
+        .port(cli.port)
+        .shutdown_timeout(Duration::from_secs(cli.shutdown_timeout));
+    for addr in &cli.bind {
+        config = config.bind(*addr);
+    }
+    config = config
+        .max_auth_attempts(cli.max_auth_attempts)
+        .inactivity_timeout((cli.idle_timeout > 0).then(|| Duration::from_secs(cli.idle_timeout)));
+    if let Some(secs) = cli.max_session_duration {
+        config = config.max_session_duration(Duration::from_secs(secs));
+    }
+    if let Some(secs) = cli.keepalive_interval {
+        config = config.keepalive(Duration::from_secs(secs), cli.keepalive_max);
+    }
+    if cli.commit_unclosed_uploads {
+        config = config.unflushed_writes(UnflushedWrites::Commit);
+    }
+    config = config.lenient_setstat(cli.lenient_setstat);
+    if let Some(max) = cli.max_connections {
+        config = config.max_connections(max);
+    }
+    if let Some(max) = cli.max_connections_per_ip {
+        config = config.max_connections_per_ip(max);
+    }
+    if let Some(failures) = cli.ban_after {
+        config = config.ban(BanPolicy::new(
+            failures,
+            Duration::from_secs(cli.ban_find_time),
+            Duration::from_secs(cli.ban_time),
+        ));
+    }
+    config = config.event_delivery(EventDelivery::new(
+        cli.event_queue_size,
+        cli.event_max_attempts,
+        Duration::from_secs(cli.event_retry_delay),
+    ));
+    if let Some(threshold) = cli.spill_threshold {
+        config = config.spill_threshold(threshold);
+    }
+    if let Some(max) = cli.max_file_size {
+        config = config.max_file_size(max);
+    }
 
     // Load host key
     if let Some(ref path) = cli.host_key_file {
@@ -142,42 +381,49 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
 
     // Parse credentials
     let users = parse_users(&cli.users);
-    let authorized_keys = load_authorized_keys(
-        cli.authorized_keys_file.as_ref(),
-        cli.authorized_keys.as_deref(),
-    );
+    let authorized_keys = match (&cli.authorized_keys_file, &cli.authorized_keys) {
+        (Some(_), _) => None,
+        (None, Some(data)) => Some(AuthorizedKeys::parse(data)),
+        (None, None) => None,
+    };
 
-    if users.is_empty() && authorized_keys.is_empty() {
+    if users.is_empty()
+        && authorized_keys.is_none()
+        && cli.authorized_keys_file.is_none()
+        && cli.password_file.is_none()
+        && cli.trusted_user_ca_keys.is_none()
+    {
         eprintln!("Warning: No authentication configured!");
-        eprintln!("         Use --user user:pass or --authorized-keys-file path");
+        eprintln!(
+            "         Use --user user:pass, --password-file path or --authorized-keys-file path"
+        );
     }
 
-    if !authorized_keys.is_empty() {
-        eprintln!("Loaded {} authorized public key(s)", authorized_keys.len());
+    if let Some(ref keys) = authorized_keys {
+        eprintln!("Loaded {} authorized public key(s)", keys.len());
     }
 
-    eprintln!("Starting SFTP server on port {}", cli.port);
+    let listeners = tokio_listeners(systemd)?;
+    match listeners {
+        Some(ref listeners) => {
+            eprintln!("Using {} socket(s) from systemd", listeners.len());
+        }
+        None => {
+            for addr in config.addresses() {
+                eprintln!("Starting SFTP server on {}", addr);
+            }
+        }
+    }
 
-    // Run with appropriate backend
-    match cli.backend {
-        BackendCommand::Local { root } => {
+    // Build server with appropriate backend
+    let mut server = match backend {
+        Command::Local { root, allow_chown } => {
             let root = root.canonicalize()?;
             eprintln!("Backend: local filesystem at {}", root.display());
-
-            let mut server = Server::new(LocalBackend::new(&root)).config(config);
-
-            if !users.is_empty() {
-                server = server.with_users(users);
-            }
-            if !authorized_keys.is_empty() {
-                server = server
-                    .with_pubkey_auth(move |_user, key| authorized_keys.iter().any(|k| k == key));
-            }
-
-            server.run().await
+            Server::new(LocalBackend::new(&root).with_ownership(allow_chown))
         }
         #[cfg(feature = "s3")]
-        BackendCommand::S3 {
+        Command::S3 {
             bucket,
             prefix,
             endpoint,
@@ -192,33 +438,208 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
             } else {
                 sftp_s3::S3Backend::from_env(s3_config).await
             };
+            Server::new(backend)
+        }
+        Command::Memory => {
+            eprintln!("Backend: in-memory (data will be lost on exit)");
+            Server::new(MemoryBackend::new())
+        }
+        Command::CheckConfig => unreachable!("handled above"),
+    }
+    .config(config);
 
-            let mut server = Server::new(backend).config(config);
+    if let Some(ref path) = cli.password_file {
+        if !users.is_empty() {
+            eprintln!("Warning: --password-file takes precedence over --user");
+        }
+        server = server.with_password_file(path)?;
+        eprintln!("Loaded password file from {}", path.display());
+    } else if !users.is_empty() {
+        server = server.with_users(users);
+    }
+    if let Some(ref template) = cli.authorized_keys_file {
+        if cli.authorized_keys.is_some() {
+            eprintln!("Warning: --authorized-keys-file takes precedence over AUTHORIZED_KEYS");
+        }
+        server = server.with_authorized_keys_file(template);
+        eprintln!("Using authorized keys from {}", template);
+    } else if let Some(keys) = authorized_keys {
+        server = server.with_pubkey_authenticator(keys);
+    }
 
-            if !users.is_empty() {
-                server = server.with_users(users);
-            }
-            if !authorized_keys.is_empty() {
-                server = server
-                    .with_pubkey_auth(move |_user, key| authorized_keys.iter().any(|k| k == key));
-            }
+    if let Some(ref path) = cli.trusted_user_ca_keys {
+        server = server.with_trusted_user_ca_keys_file(path)?;
+        eprintln!("Loaded trusted user CA keys from {}", path.display());
+    }
 
-            server.run().await
+    if let Some(ref path) = cli.permissions {
+        if cli.read_only {
+            eprintln!("Warning: --permissions takes precedence over --read-only");
         }
-        BackendCommand::Memory => {
-            eprintln!("Backend: in-memory (data will be lost on exit)");
+        server = server.with_permissions(PermissionPolicy::load(path)?);
+        eprintln!("Loaded permission rules from {}", path.display());
+    } else if cli.read_only {
+        server = server.with_permissions(PermissionPolicy::read_only());
+        eprintln!("Serving read-only");
+    }
 
-            let mut server = Server::new(MemoryBackend::new()).config(config);
+    if let Some(ref path) = cli.audit_log {
+        server = server.with_audit_sink(JsonLinesSink::open(path)?);
+        eprintln!("Writing audit log to {}", path.display());
+    }
+    if cli.audit_tracing {
+        server = server.with_audit_sink(TracingSink);
+    }
 
-            if !users.is_empty() {
-                server = server.with_users(users);
-            }
-            if !authorized_keys.is_empty() {
-                server = server
-                    .with_pubkey_auth(move |_user, key| authorized_keys.iter().any(|k| k == key));
+    if let Some(ref url) = cli.event_webhook {
+        server = server.with_event_hook(WebhookHook::new(url)?);
+        eprintln!("Sending events to {url}");
+    }
+    if let Some(ref command) = cli.event_command {
+        server = server.with_event_hook(CommandHook::new(command));
+        eprintln!("Running `{command}` for each event");
+    }
+
+    let server = server.with_graceful_shutdown(shutdown_signal());
+    match listeners {
+        Some(listeners) => server.run_on_listeners(listeners).await,
+        None => server.run().await,
+    }
+}
+
+/// Serve the setup described by a configuration file
+async fn run_config(
+    path: &Path,
+    systemd: Option<Vec<std::net::TcpListener>>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let config = Config::load(path).unwrap_or_else(|err| config_error(path, err));
+    eprintln!("Loaded configuration from {}", path.display());
+
+    #[cfg(feature = "metrics")]
+    if let Some(addr) = config.metrics_addr() {
+        sftp_s3::telemetry::install_prometheus(addr)?;
+        eprintln!("Serving metrics on http://{addr}/metrics");
+    }
+
+    let listeners = tokio_listeners(systemd)?;
+    match listeners {
+        Some(ref listeners) => eprintln!("Using {} socket(s) from systemd", listeners.len()),
+        None => {
+            for addr in config.addresses() {
+                eprintln!("Starting SFTP server on {}", addr);
             }
+        }
+    }
+
+    let server = config
+        .into_server()
+        .await
+        .unwrap_or_else(|err| config_error(path, err))
+        .with_graceful_shutdown(shutdown_signal());
+    match listeners {
+        Some(listeners) => server.run_on_listeners(listeners).await,
+        None => server.run().await,
+    }
+}
+
+/// Validate a configuration file and print what it resolves to
+fn check_config(path: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let config = Config::load(path).unwrap_or_else(|err| config_error(path, err));
+    if let Err(err) = config.check() {
+        config_error(path, err);
+    }
+    print!("{config}");
+    println!("{}: OK", path.display());
+    Ok(())
+}
+
+fn config_error(path: &Path, err: sftp_s3::ConfigError) -> ! {
+    eprintln!("{}: {err}", path.display());
+    std::process::exit(1)
+}
 
-            server.run().await
+/// Sockets passed in by systemd socket activation, if any
+///
+/// Follows sd_listen_fds(3): `LISTEN_PID` must name this process and
+/// `LISTEN_FDS` counts the descriptors, which start at 3. Call before any
+/// threads are started, as it clears those variables.
+#[cfg(unix)]
+fn systemd_listeners() -> std::io::Result<Option<Vec<std::net::TcpListener>>> {
+    use std::os::unix::io::{FromRawFd, RawFd};
+
+    const SD_LISTEN_FDS_START: RawFd = 3;
+
+    let pid = std::env::var("LISTEN_PID").ok();
+    let fds = std::env::var("LISTEN_FDS").ok();
+    if pid.and_then(|p| p.parse::<u32>().ok()) != Some(std::process::id()) {
+        return Ok(None);
+    }
+    let Some(count) = fds.and_then(|n| n.parse::<RawFd>().ok()) else {
+        return Ok(None);
+    };
+    // Don't let the sockets leak into anything we spawn
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+
+    let mut listeners = Vec::new();
+    for fd in SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count {
+        // SAFETY: systemd hands these descriptors to this process and nothing
+        // else in it owns them
+        let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+        listener.set_nonblocking(true)?;
+        listeners.push(listener);
+    }
+    Ok(Some(listeners))
+}
+
+#[cfg(not(unix))]
+fn systemd_listeners() -> std::io::Result<Option<Vec<std::net::TcpListener>>> {
+    Ok(None)
+}
+
+/// Register sockets from [`systemd_listeners`] with the running runtime
+fn tokio_listeners(
+    listeners: Option<Vec<std::net::TcpListener>>,
+) -> std::io::Result<Option<Vec<tokio::net::TcpListener>>> {
+    listeners
+        .map(|listeners| {
+            listeners
+                .into_iter()
+                .map(tokio::net::TcpListener::from_std)
+                .collect()
+        })
+        .transpose()
+}
+
+/// Resolve on the first SIGTERM or SIGINT
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            eprintln!("Could not listen for SIGINT: {}", err);
+            std::future::pending::<()>().await;
         }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut stream) => {
+                stream.recv().await;
+            }
+            Err(err) => {
+                eprintln!("Could not listen for SIGTERM: {}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
     }
+    eprintln!("Shutting down, waiting for in-flight transfers");
 }
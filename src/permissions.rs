@@ -0,0 +1,356 @@
+//! Per-user, per-path authorization for SFTP operations
+
+use globset::{Glob, GlobBuilder, GlobMatcher};
+use serde::Deserialize;
+use std::path::Path;
+
+/// SFTP operations that can be allowed or denied
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    /// Open a file for reading
+    Read,
+    /// Open a file for writing
+    Write,
+    /// List a directory
+    List,
+    /// Delete a file
+    Remove,
+    /// Rename a file or directory; checked against both paths
+    Rename,
+    /// Create a directory
+    Mkdir,
+    /// Remove a directory
+    Rmdir,
+    /// Change attributes
+    Setstat,
+    /// Create a symbolic or hard link; checked against the new link
+    Link,
+}
+
+impl Operation {
+    pub const ALL: [Operation; 9] = [
+        Operation::Read,
+        Operation::Write,
+        Operation::List,
+        Operation::Remove,
+        Operation::Rename,
+        Operation::Mkdir,
+        Operation::Rmdir,
+        Operation::Setstat,
+        Operation::Link,
+    ];
+
+    /// Operations that change the tree
+    pub const MODIFY: [Operation; 7] = [
+        Operation::Write,
+        Operation::Remove,
+        Operation::Rename,
+        Operation::Mkdir,
+        Operation::Rmdir,
+        Operation::Setstat,
+        Operation::Link,
+    ];
+}
+
+/// Whether a matching rule grants or refuses access
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Effect {
+    #[default]
+    Allow,
+    Deny,
+}
+
+/// Errors building a policy
+#[derive(Debug, thiserror::Error)]
+pub enum PolicyError {
+    #[error("invalid glob {0:?}: {1}")]
+    Glob(String, globset::Error),
+    #[error("could not read policy file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid policy file: {0}")]
+    Parse(#[from] toml::de::Error),
+}
+
+/// A single allow or deny rule
+///
+/// Empty `users`, `operations` or `paths` match everything. User patterns
+/// are globs (`*`, `team-?`). Path patterns are globs over the path as the
+/// user sees it, where `*` stays within one directory and `**` crosses them,
+/// e.g. `/uploads/**`.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    effect: Effect,
+    users: Vec<GlobMatcher>,
+    operations: Vec<Operation>,
+    paths: Vec<GlobMatcher>,
+}
+
+impl Rule {
+    /// Rule that allows the matching requests
+    pub fn allow() -> Self {
+        Self::new(Effect::Allow)
+    }
+
+    /// Rule that denies the matching requests
+    pub fn deny() -> Self {
+        Self::new(Effect::Deny)
+    }
+
+    fn new(effect: Effect) -> Self {
+        Self {
+            effect,
+            users: Vec::new(),
+            operations: Vec::new(),
+            paths: Vec::new(),
+        }
+    }
+
+    /// Only apply to users matching `pattern`
+    pub fn user(mut self, pattern: &str) -> Result<Self, PolicyError> {
+        self.users.push(user_glob(pattern)?);
+        Ok(self)
+    }
+
+    /// Only apply to `operations`
+    pub fn operations(mut self, operations: impl IntoIterator<Item = Operation>) -> Self {
+        self.operations.extend(operations);
+        self
+    }
+
+    /// Only apply to paths matching `pattern`
+    pub fn path(mut self, pattern: &str) -> Result<Self, PolicyError> {
+        self.paths.push(path_glob(pattern)?);
+        Ok(self)
+    }
+
+    fn matches(&self, user: &str, operation: Operation, path: &str) -> bool {
+        (self.users.is_empty() || self.users.iter().any(|g| g.is_match(user)))
+            && (self.operations.is_empty() || self.operations.contains(&operation))
+            && (self.paths.is_empty() || self.paths.iter().any(|g| g.is_match(path)))
+    }
+}
+
+/// Ordered list of rules; the first rule that matches a request decides it
+///
+/// Requests no rule matches get the default effect, which is allow unless
+/// changed with [`PermissionPolicy::default_effect`].
+#[derive(Debug, Clone, Default)]
+pub struct PermissionPolicy {
+    rules: Vec<Rule>,
+    default: Effect,
+}
+
+impl PermissionPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Policy that denies every modifying operation
+    pub fn read_only() -> Self {
+        Self::new().rule(Rule::deny().operations(Operation::MODIFY))
+    }
+
+    /// Append a rule
+    pub fn rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Set the effect for requests that no rule matches
+    pub fn default_effect(mut self, effect: Effect) -> Self {
+        self.default = effect;
+        self
+    }
+
+    /// Whether `user` may perform `operation` on `path`
+    pub fn is_allowed(&self, user: &str, operation: Operation, path: &str) -> bool {
+        let effect = self
+            .rules
+            .iter()
+            .find(|rule| rule.matches(user, operation, path))
+            .map_or(self.default, |rule| rule.effect);
+        effect == Effect::Allow
+    }
+
+    /// Parse a policy from TOML
+    ///
+    /// ```toml
+    /// default = "allow"
+    ///
+    /// [[rule]]
+    /// effect = "deny"
+    /// users = ["auditor-*"]
+    /// operations = ["write", "remove", "rename", "mkdir", "rmdir", "setstat", "link"]
+    ///
+    /// [[rule]]
+    /// effect = "deny"
+    /// users = ["dropbox"]
+    /// operations = ["read", "list"]
+    /// paths = ["/incoming/**"]
+    /// ```
+    pub fn from_toml(contents: &str) -> Result<Self, PolicyError> {
+        let file: PolicyFile = toml::from_str(contents)?;
+        file.try_into()
+    }
+
+    /// Load a TOML policy file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, PolicyError> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+}
+
+/// On-disk form of a policy
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct PolicyFile {
+    #[serde(default)]
+    pub(crate) default: Effect,
+    #[serde(default, rename = "rule")]
+    pub(crate) rules: Vec<RuleFile>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RuleFile {
+    effect: Effect,
+    #[serde(default)]
+    users: Vec<String>,
+    #[serde(default)]
+    operations: Vec<Operation>,
+    #[serde(default)]
+    paths: Vec<String>,
+}
+
+impl TryFrom<PolicyFile> for PermissionPolicy {
+    type Error = PolicyError;
+
+    fn try_from(file: PolicyFile) -> Result<Self, Self::Error> {
+        file.append_to(PermissionPolicy::new())
+    }
+}
+
+impl PolicyFile {
+    /// Add the file's rules after those already in `policy` and take its default
+    pub(crate) fn append_to(
+        self,
+        policy: PermissionPolicy,
+    ) -> Result<PermissionPolicy, PolicyError> {
+        let mut policy = policy.default_effect(self.default);
+        for rule in self.rules {
+            let mut built = Rule::new(rule.effect).operations(rule.operations);
+            for user in &rule.users {
+                built = built.user(user)?;
+            }
+            for path in &rule.paths {
+                built = built.path(path)?;
+            }
+            policy = policy.rule(built);
+        }
+        Ok(policy)
+    }
+}
+
+fn user_glob(pattern: &str) -> Result<GlobMatcher, PolicyError> {
+    Glob::new(pattern)
+        .map(|g| g.compile_matcher())
+        .map_err(|e| PolicyError::Glob(pattern.to_string(), e))
+}
+
+fn path_glob(pattern: &str) -> Result<GlobMatcher, PolicyError> {
+    GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()
+        .map(|g| g.compile_matcher())
+        .map_err(|e| PolicyError::Glob(pattern.to_string(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_allows_everything() {
+        let policy = PermissionPolicy::new();
+        for op in Operation::ALL {
+            assert!(policy.is_allowed("alice", op, "/any/path"));
+        }
+    }
+
+    #[test]
+    fn test_read_only() {
+        let policy = PermissionPolicy::read_only();
+        assert!(policy.is_allowed("alice", Operation::Read, "/file"));
+        assert!(policy.is_allowed("alice", Operation::List, "/"));
+        for op in Operation::MODIFY {
+            assert!(!policy.is_allowed("alice", op, "/file"));
+        }
+    }
+
+    #[test]
+    fn test_first_match_wins() {
+        let policy = PermissionPolicy::new()
+            .default_effect(Effect::Deny)
+            .rule(
+                Rule::allow()
+                    .user("uploader")
+                    .unwrap()
+                    .operations([Operation::Write, Operation::List])
+                    .path("/incoming")
+                    .unwrap()
+                    .path("/incoming/**")
+                    .unwrap(),
+            )
+            .rule(Rule::allow().user("admin").unwrap());
+
+        assert!(policy.is_allowed("uploader", Operation::Write, "/incoming/a.csv"));
+        assert!(policy.is_allowed("uploader", Operation::List, "/incoming"));
+        assert!(!policy.is_allowed("uploader", Operation::Read, "/incoming/a.csv"));
+        assert!(!policy.is_allowed("uploader", Operation::Write, "/other/a.csv"));
+        assert!(policy.is_allowed("admin", Operation::Remove, "/other/a.csv"));
+        assert!(!policy.is_allowed("guest", Operation::Read, "/"));
+    }
+
+    #[test]
+    fn test_single_star_stays_in_directory() {
+        let policy = PermissionPolicy::new().rule(Rule::deny().path("/logs/*").unwrap());
+        assert!(!policy.is_allowed("alice", Operation::Read, "/logs/today"));
+        assert!(policy.is_allowed("alice", Operation::Read, "/logs/2024/today"));
+    }
+
+    #[test]
+    fn test_from_toml() {
+        let policy = PermissionPolicy::from_toml(
+            r#"
+            default = "deny"
+
+            [[rule]]
+            effect = "allow"
+            users = ["team-*"]
+            operations = ["read", "list"]
+
+            [[rule]]
+            effect = "allow"
+            users = ["dropbox"]
+            operations = ["write"]
+            paths = ["/incoming/*"]
+            "#,
+        )
+        .unwrap();
+
+        assert!(policy.is_allowed("team-a", Operation::Read, "/x"));
+        assert!(!policy.is_allowed("team-a", Operation::Write, "/x"));
+        assert!(policy.is_allowed("dropbox", Operation::Write, "/incoming/f"));
+        assert!(!policy.is_allowed("dropbox", Operation::Read, "/incoming/f"));
+    }
+
+    #[test]
+    fn test_from_toml_rejects_bad_input() {
+        assert!(PermissionPolicy::from_toml("[[rule]]\neffect = \"maybe\"").is_err());
+        assert!(PermissionPolicy::from_toml("[[rule]]\neffect = \"deny\"\nops = []").is_err());
+        assert!(
+            PermissionPolicy::from_toml("[[rule]]\neffect = \"deny\"\npaths = [\"[\"]").is_err()
+        );
+    }
+}
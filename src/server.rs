@@ -1,30 +1,95 @@
-use crate::backend::Backend;
+use crate::audit::{AuditLog, AuditSink};
+use crate::auth::{
+    async_password_callback, async_pubkey_callback, Authenticator, AuthorizedKeysFile,
+    ChallengeHandler, PasswordFile, TrustedUserCaKeys,
+};
+use crate::backend::{Backend, BackendFactory, SharedBackend};
+use crate::events::{self, EventDelivery, EventHook};
+use crate::handle::{UnflushedWrites, DEFAULT_MAX_FILE_SIZE, DEFAULT_SPILL_THRESHOLD};
+use crate::limits::BanPolicy;
+use crate::permissions::PermissionPolicy;
+use crate::shutdown::{Connections, ShutdownHandle};
 use crate::ssh_handler::{AuthConfig, SshServer};
+use futures::future::{select_all, BoxFuture};
 use russh::keys::ssh_key::rand_core::OsRng;
 use russh::keys::PublicKey;
 use russh::server::{Config as SshConfig, Server as _};
+use russh::Disconnect;
+pub use russh::MethodSet;
+use std::future::Future;
+use std::net::{Ipv4Addr, SocketAddr};
 use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
-use tracing::info;
+use subtle::ConstantTimeEq;
+use tokio::net::TcpListener;
+use tracing::{debug, info, warn};
+
+/// Pause after a failed accept, e.g. when out of file descriptors
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
 
 /// Server configuration
 #[derive(Clone)]
 pub struct ServerConfig {
-    /// Port to bind to
+    /// Port to bind to on all IPv4 interfaces when no addresses are given
     pub port: u16,
+    /// Addresses to listen on; overrides `port` when non-empty
+    pub bind_addresses: Vec<SocketAddr>,
     /// SSH server keys
     pub keys: Vec<russh::keys::PrivateKey>,
     /// Authentication rejection time
     pub auth_rejection_time: Duration,
+    /// Bytes an upload may buffer in memory before spilling to a temporary file
+    pub spill_threshold: usize,
+    /// Largest file a client may write, in bytes
+    pub max_file_size: u64,
+    /// How long a graceful shutdown waits for in-flight transfers
+    pub shutdown_timeout: Duration,
+    /// Concurrent connections across all clients; unlimited if `None`
+    pub max_connections: Option<usize>,
+    /// Concurrent connections from one IP address; unlimited if `None`
+    pub max_connections_per_ip: Option<usize>,
+    /// Failed logins allowed on one connection before it is dropped
+    pub max_auth_attempts: usize,
+    /// Temporarily ban addresses with repeated login failures
+    pub ban: Option<BanPolicy>,
+    /// Close connections that send nothing for this long
+    pub inactivity_timeout: Option<Duration>,
+    /// Close connections this long after they were opened
+    pub max_session_duration: Option<Duration>,
+    /// Send a keepalive when the client has been quiet this long
+    pub keepalive_interval: Option<Duration>,
+    /// Unanswered keepalives after which the connection is closed
+    pub keepalive_max: usize,
+    /// What to do with write handles still open when a session ends
+    pub unflushed_writes: UnflushedWrites,
+    /// Acknowledge setstat requests the backend can't honor
+    pub lenient_setstat: bool,
+    /// Queue size and retries for event hooks
+    pub event_delivery: EventDelivery,
 }
 
 impl Default for ServerConfig {
     fn default() -> Self {
         Self {
             port: 2222,
+            bind_addresses: Vec::new(),
             keys: Vec::new(),
             auth_rejection_time: Duration::from_secs(3),
+            spill_threshold: DEFAULT_SPILL_THRESHOLD,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            shutdown_timeout: Duration::from_secs(30),
+            max_connections: None,
+            max_connections_per_ip: None,
+            max_auth_attempts: 6,
+            ban: None,
+            inactivity_timeout: Some(Duration::from_secs(600)),
+            max_session_duration: None,
+            keepalive_interval: None,
+            keepalive_max: 3,
+            unflushed_writes: UnflushedWrites::default(),
+            lenient_setstat: false,
+            event_delivery: EventDelivery::default(),
         }
     }
 }
@@ -39,6 +104,129 @@ impl ServerConfig {
         self
     }
 
+    /// Listen on `addr`, which may be IPv4 or IPv6
+    ///
+    /// Call repeatedly to listen on several addresses. Once any address is
+    /// given, `port` is no longer used.
+    pub fn bind(mut self, addr: SocketAddr) -> Self {
+        self.bind_addresses.push(addr);
+        self
+    }
+
+    /// Addresses the server will listen on
+    pub fn addresses(&self) -> Vec<SocketAddr> {
+        if self.bind_addresses.is_empty() {
+            vec![SocketAddr::from((Ipv4Addr::UNSPECIFIED, self.port))]
+        } else {
+            self.bind_addresses.clone()
+        }
+    }
+
+    /// Set the per-handle memory limit for buffered uploads
+    ///
+    /// Only applies to backends without streaming writes; larger uploads are
+    /// spilled to a temporary file until the handle is closed.
+    pub fn spill_threshold(mut self, bytes: usize) -> Self {
+        self.spill_threshold = bytes;
+        self
+    }
+
+    /// Refuse writes that would make a file larger than `bytes`
+    ///
+    /// Bounds what a client can make the server buffer, zero-fill or
+    /// upload with a single write at a far offset. Defaults to 5 TiB.
+    pub fn max_file_size(mut self, bytes: u64) -> Self {
+        self.max_file_size = bytes;
+        self
+    }
+
+    /// Set how long a graceful shutdown waits for in-flight transfers
+    ///
+    /// Sessions still uploading when it expires are disconnected.
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Refuse connections beyond `max` at once
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = Some(max);
+        self
+    }
+
+    /// Refuse connections beyond `max` at once from a single IP address
+    pub fn max_connections_per_ip(mut self, max: usize) -> Self {
+        self.max_connections_per_ip = Some(max);
+        self
+    }
+
+    /// Disconnect after `max` failed logins on one connection (default 6, as sshd)
+    pub fn max_auth_attempts(mut self, max: usize) -> Self {
+        self.max_auth_attempts = max;
+        self
+    }
+
+    /// Ban addresses that fail to log in too often
+    ///
+    /// Banned addresses are refused before the SSH handshake, and their
+    /// unauthenticated sessions are dropped, until the ban expires.
+    pub fn ban(mut self, policy: BanPolicy) -> Self {
+        self.ban = Some(policy);
+        self
+    }
+
+    /// Close connections idle for `timeout` (10 minutes by default); `None` disables
+    pub fn inactivity_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.inactivity_timeout = timeout;
+        self
+    }
+
+    /// Close connections after `duration`, however active they are
+    pub fn max_session_duration(mut self, duration: Duration) -> Self {
+        self.max_session_duration = Some(duration);
+        self
+    }
+
+    /// Probe quiet clients every `interval`, closing after `max_missed` go unanswered
+    ///
+    /// Answered keepalives count as activity, so with keepalives enabled the
+    /// inactivity timeout only catches clients that have gone away.
+    pub fn keepalive(mut self, interval: Duration, max_missed: usize) -> Self {
+        self.keepalive_interval = Some(interval);
+        self.keepalive_max = max_missed;
+        self
+    }
+
+    /// Commit or discard uploads left open when a session ends (default discard)
+    ///
+    /// Committing keeps partial data from dropped connections; discarding
+    /// aborts streaming uploads so nothing incomplete is stored.
+    pub fn unflushed_writes(mut self, policy: UnflushedWrites) -> Self {
+        self.unflushed_writes = policy;
+        self
+    }
+
+    /// Acknowledge setstat requests the backend can't honor (default off)
+    ///
+    /// By default a request changing attributes the backend doesn't store,
+    /// such as ownership on S3, fails with `OpUnsupported`. Lenient sessions
+    /// apply whatever the backend supports and report success, which some
+    /// clients need to finish `put -p` or `rsync -t` style transfers.
+    pub fn lenient_setstat(mut self, lenient: bool) -> Self {
+        self.lenient_setstat = lenient;
+        self
+    }
+
+    /// Queue up to `delivery.queue_size` events for the hooks and retry
+    /// failed deliveries
+    ///
+    /// Events arriving while the queue is full are dropped so hooks can never
+    /// stall transfers.
+    pub fn event_delivery(mut self, delivery: EventDelivery) -> Self {
+        self.event_delivery = delivery;
+        self
+    }
+
     pub fn with_key(mut self, key: russh::keys::PrivateKey) -> Self {
         self.keys.push(key);
         self
@@ -79,18 +267,42 @@ impl ServerConfig {
 }
 
 /// SFTP server builder
-pub struct Server<B: Backend> {
-    backend: Arc<B>,
+pub struct Server {
+    backends: Arc<dyn BackendFactory>,
     config: ServerConfig,
     auth_config: AuthConfig,
+    home_dir: Option<HomeDirCallback>,
+    password_file: Option<Arc<PasswordFile>>,
+    permissions: Option<Arc<PermissionPolicy>>,
+    audit: AuditLog,
+    event_hooks: Vec<Arc<dyn EventHook>>,
+    shutdown: ShutdownHandle,
+    shutdown_signal: Option<BoxFuture<'static, ()>>,
 }
 
-impl<B: Backend> Server<B> {
-    pub fn new(backend: B) -> Self {
+impl Server {
+    /// Serve every user from `backend`
+    pub fn new<B: Backend>(backend: B) -> Self {
+        Self::with_backend_factory(SharedBackend::new(Arc::new(backend)))
+    }
+
+    /// Resolve each user's backend at login with `factory`
+    ///
+    /// Use this instead of [`Server::new`] when users should land on
+    /// different storage. Home directories, if configured, are applied on top
+    /// of the backend the factory returns.
+    pub fn with_backend_factory<F: BackendFactory>(factory: F) -> Self {
         Self {
-            backend: Arc::new(backend),
+            backends: Arc::new(factory),
             config: ServerConfig::default(),
             auth_config: AuthConfig::default(),
+            home_dir: None,
+            password_file: None,
+            permissions: None,
+            audit: AuditLog::new(),
+            event_hooks: Vec::new(),
+            shutdown: ShutdownHandle::new(),
+            shutdown_signal: None,
         }
     }
 
@@ -104,7 +316,21 @@ impl<B: Backend> Server<B> {
     where
         F: Fn(&str, &str) -> bool + Send + Sync + 'static,
     {
-        self.auth_config.password_callback = Some(Arc::new(callback));
+        let callback: PasswordAuthCallback = Arc::new(callback);
+        self.auth_config.password = Some(Arc::new(callback));
+        self
+    }
+
+    /// Set an async password authentication callback
+    ///
+    /// Use this when checking credentials involves I/O, such as a database
+    /// lookup or a call to an auth service.
+    pub fn with_password_auth_async<F, Fut>(mut self, callback: F) -> Self
+    where
+        F: Fn(String, String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = bool> + Send + 'static,
+    {
+        self.auth_config.password = Some(Arc::new(async_password_callback(callback)));
         self
     }
 
@@ -113,7 +339,45 @@ impl<B: Backend> Server<B> {
     where
         F: Fn(&str, &PublicKey) -> bool + Send + Sync + 'static,
     {
-        self.auth_config.pubkey_callback = Some(Arc::new(callback));
+        let callback: PubkeyAuthCallback = Arc::new(callback);
+        self.auth_config.pubkey = Some(Arc::new(callback));
+        self
+    }
+
+    /// Set an async public key authentication callback
+    pub fn with_pubkey_auth_async<F, Fut>(mut self, callback: F) -> Self
+    where
+        F: Fn(String, PublicKey) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = bool> + Send + 'static,
+    {
+        self.auth_config.pubkey = Some(Arc::new(async_pubkey_callback(callback)));
+        self
+    }
+
+    /// Enable keyboard-interactive authentication driven by `handler`
+    ///
+    /// Use this for custom prompts or second factors such as TOTP codes.
+    pub fn with_keyboard_interactive<H: ChallengeHandler>(mut self, handler: H) -> Self {
+        self.auth_config.keyboard_interactive = Some(Arc::new(handler));
+        self
+    }
+
+    /// Require several authentication methods to succeed in order
+    ///
+    /// Each step lists the methods that may satisfy it, for example
+    /// `[MethodSet::PUBLICKEY, MethodSet::KEYBOARD_INTERACTIVE]` requires a key
+    /// and then a one-time code. Clients are told which methods remain after
+    /// each step.
+    pub fn with_required_methods(mut self, steps: Vec<MethodSet>) -> Self {
+        self.auth_config.required_methods = steps;
+        self
+    }
+
+    /// Use `authenticator` for both password and public key logins
+    pub fn with_authenticator<A: Authenticator>(mut self, authenticator: A) -> Self {
+        let authenticator: Arc<dyn Authenticator> = Arc::new(authenticator);
+        self.auth_config.password = Some(authenticator.clone());
+        self.auth_config.pubkey = Some(authenticator);
         self
     }
 
@@ -124,19 +388,167 @@ impl<B: Backend> Server<B> {
             authorized
                 .iter()
                 .find(|(u, _)| u == user)
-                .map(|(_, keys)| keys.iter().any(|k| k == key))
+                .map(|(_, keys)| keys.iter().any(|k| k.key_data() == key.key_data()))
                 .unwrap_or(false)
         })
     }
 
+    /// Authenticate public keys against authorized_keys files
+    ///
+    /// `%u` in `path` is replaced with the login name, e.g.
+    /// `/etc/sftp/keys/%u`, so each user only gets their own keys. Without
+    /// `%u` the file applies to every user. Key options such as `from=` and
+    /// `expiry-time=` are honored.
+    pub fn with_authorized_keys_file(self, path: impl Into<String>) -> Self {
+        self.with_pubkey_authenticator(AuthorizedKeysFile::new(path))
+    }
+
+    /// Use `authenticator` for public key logins
+    pub fn with_pubkey_authenticator<A: Authenticator>(mut self, authenticator: A) -> Self {
+        self.auth_config.pubkey = Some(Arc::new(authenticator));
+        self
+    }
+
+    /// Use `authenticator` for password logins
+    pub fn with_password_authenticator<A: Authenticator>(mut self, authenticator: A) -> Self {
+        self.auth_config.password = Some(Arc::new(authenticator));
+        self
+    }
+
+    /// Accept OpenSSH user certificates signed by any of `ca_keys`
+    ///
+    /// A certificate is accepted when it is a user certificate within its
+    /// validity window and the login name is one of its principals.
+    pub fn with_trusted_user_ca_keys(mut self, ca_keys: Vec<PublicKey>) -> Self {
+        self.auth_config.trusted_user_ca_keys = Some(Arc::new(TrustedUserCaKeys::new(ca_keys)));
+        self
+    }
+
+    /// Load trusted CA keys from a file, like sshd's `TrustedUserCAKeys`
+    pub fn with_trusted_user_ca_keys_file(
+        mut self,
+        path: impl AsRef<Path>,
+    ) -> std::io::Result<Self> {
+        let ca_keys = TrustedUserCaKeys::load(path)?;
+        self.auth_config.trusted_user_ca_keys = Some(Arc::new(ca_keys));
+        Ok(self)
+    }
+
+    /// Confine each user to a home directory within the backend
+    ///
+    /// `home_dir` maps the authenticated username to a backend path (for
+    /// example an S3 prefix like `tenants/alice`), which the user sees as `/`.
+    /// The directory is created on first login if it does not exist.
+    pub fn with_home_dir<F>(mut self, home_dir: F) -> Self
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        self.home_dir = Some(Arc::new(home_dir));
+        self
+    }
+
+    /// Restrict what each user may do with a permission policy
+    ///
+    /// Denied operations fail with `SSH_FX_PERMISSION_DENIED`. Use
+    /// [`PermissionPolicy::read_only`] for download-only access, or rules
+    /// matching users, operations and path globs for finer control.
+    pub fn with_permissions(mut self, permissions: PermissionPolicy) -> Self {
+        self.permissions = Some(Arc::new(permissions));
+        self
+    }
+
+    /// Record logins and file operations to `sink`
+    ///
+    /// Can be called more than once; every sink receives every event. See
+    /// [`JsonLinesSink`](crate::JsonLinesSink) and
+    /// [`TracingSink`](crate::TracingSink), or pass a closure.
+    pub fn with_audit_sink(mut self, sink: impl AuditSink) -> Self {
+        self.audit = self.audit.with_sink(sink);
+        self
+    }
+
+    /// Notify `hook` of uploads, downloads, removals and renames
+    ///
+    /// Hooks run one event at a time on a background task, in the order the
+    /// events happened, after the operation has completed. See
+    /// [`WebhookHook`](crate::WebhookHook) and
+    /// [`CommandHook`](crate::CommandHook).
+    pub fn with_event_hook(mut self, hook: impl EventHook) -> Self {
+        self.event_hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Handle that stops the server once [`Server::run`] is underway
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Shut down gracefully when `signal` completes
+    ///
+    /// The server stops accepting connections, disconnects idle sessions and
+    /// gives in-flight transfers up to [`ServerConfig::shutdown_timeout`] to
+    /// finish before `run` returns.
+    pub fn with_graceful_shutdown<F>(mut self, signal: F) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.shutdown_signal = Some(Box::pin(signal));
+        self
+    }
+
     /// Set static users for password authentication
     pub fn with_users(self, users: Vec<(String, String)>) -> Self {
         let users = Arc::new(users);
-        self.with_password_auth(move |user, pass| users.iter().any(|(u, p)| u == user && p == pass))
+        self.with_password_auth(move |user, pass| {
+            users
+                .iter()
+                .any(|(u, p)| u == user && bool::from(p.as_bytes().ct_eq(pass.as_bytes())))
+        })
+    }
+
+    /// Authenticate passwords against an htpasswd-style file of hashes
+    ///
+    /// Supports bcrypt, argon2id and sha512-crypt hashes. The file is
+    /// reloaded when the process receives SIGHUP while the server is running.
+    pub fn with_password_file(mut self, path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = Arc::new(PasswordFile::load(path)?);
+        self.auth_config.password = Some(file.clone());
+        self.password_file = Some(file);
+        Ok(self)
     }
 
-    /// Run the server
+    /// Run the server on the configured addresses
     pub async fn run(self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut listeners = Vec::new();
+        for addr in self.config.addresses() {
+            listeners.push(TcpListener::bind(addr).await?);
+        }
+        self.run_on_listeners(listeners).await
+    }
+
+    /// Run the server on an already-bound listener
+    ///
+    /// Binding port 0 and reading [`TcpListener::local_addr`] first is handy
+    /// in tests. The configured port and bind addresses are ignored.
+    pub async fn run_on_listener(
+        self,
+        listener: TcpListener,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.run_on_listeners(vec![listener]).await
+    }
+
+    /// Run the server on several already-bound listeners at once
+    ///
+    /// Useful with socket activation, where the service manager hands over
+    /// sockets it bound on our behalf.
+    pub async fn run_on_listeners(
+        self,
+        listeners: Vec<TcpListener>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if listeners.is_empty() {
+            return Err("no listeners to accept connections on".into());
+        }
+
         let mut keys = self.config.keys.clone();
         if keys.is_empty() {
             keys.push(russh::keys::PrivateKey::random(
@@ -146,13 +558,7 @@ impl<B: Backend> Server<B> {
         }
 
         // Determine which auth methods to advertise
-        let mut methods = russh::MethodSet::empty();
-        if self.auth_config.password_callback.is_some() {
-            methods |= russh::MethodSet::PASSWORD;
-        }
-        if self.auth_config.pubkey_callback.is_some() {
-            methods |= russh::MethodSet::PUBLICKEY;
-        }
+        let mut methods = self.auth_config.initial_methods();
         // Default to password if nothing configured
         if methods.is_empty() {
             methods = russh::MethodSet::PASSWORD;
@@ -163,23 +569,144 @@ impl<B: Backend> Server<B> {
             auth_rejection_time_initial: Some(Duration::from_secs(0)),
             methods,
             keys,
+            inactivity_timeout: self.config.inactivity_timeout,
+            keepalive_interval: self.config.keepalive_interval,
+            keepalive_max: self.config.keepalive_max,
             ..Default::default()
         };
 
+        if let Some(ref file) = self.password_file {
+            file.reload_on_sighup();
+        }
+
         let ssh_config = Arc::new(ssh_config);
-        let mut server = SshServer::new(self.backend, self.auth_config);
+        let shutdown_timeout = self.config.shutdown_timeout;
+        let max_session_duration = self.config.max_session_duration;
+        let (events, event_worker) = if self.event_hooks.is_empty() {
+            (None, None)
+        } else {
+            let (sender, worker) = events::spawn(self.event_hooks, self.config.event_delivery);
+            (Some(sender), Some(worker))
+        };
+        let server = SshServer::new(self.backends, self.auth_config, Arc::new(self.config))
+            .with_home_dir(self.home_dir)
+            .with_permissions(self.permissions)
+            .with_audit((!self.audit.is_empty()).then(|| Arc::new(self.audit)))
+            .with_events(events);
 
-        let addr = format!("0.0.0.0:{}", self.config.port);
-        info!(addr = %addr, "Starting SFTP server");
+        if let Some(signal) = self.shutdown_signal {
+            let shutdown = self.shutdown.clone();
+            tokio::spawn(async move {
+                signal.await;
+                shutdown.shutdown();
+            });
+        }
 
-        server
-            .run_on_address(ssh_config, ("0.0.0.0", self.config.port))
-            .await?;
+        for listener in &listeners {
+            info!(addr = %listener.local_addr()?, "Starting SFTP server");
+        }
+
+        serve(
+            server,
+            ssh_config,
+            listeners,
+            self.shutdown,
+            shutdown_timeout,
+            max_session_duration,
+        )
+        .await;
 
+        // Every sender is gone once the sessions are, so the queue runs dry
+        if let Some(worker) = event_worker {
+            if tokio::time::timeout(shutdown_timeout, worker)
+                .await
+                .is_err()
+            {
+                warn!("Event hooks did not finish before shutdown");
+            }
+        }
         Ok(())
     }
 }
 
+/// Accept connections on `listeners` until shutdown, then drain
+async fn serve(
+    mut server: SshServer,
+    ssh_config: Arc<SshConfig>,
+    listeners: Vec<TcpListener>,
+    shutdown: ShutdownHandle,
+    shutdown_timeout: Duration,
+    max_session_duration: Option<Duration>,
+) {
+    let connections = Connections::new();
+
+    loop {
+        let accept = select_all(listeners.iter().map(|listener| Box::pin(listener.accept())));
+        let (socket, peer_addr) = tokio::select! {
+            _ = shutdown.wait() => break,
+            (accepted, _, _) = accept => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    // Usually out of file descriptors; back off instead of spinning
+                    warn!(error = %err, "Failed to accept connection");
+                    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    continue;
+                }
+            },
+        };
+
+        let session = server.new_client(Some(peer_addr));
+        if session.is_refused() {
+            // Close before the handshake so refused clients cost almost nothing
+            continue;
+        }
+        let activity = connections.activity();
+        let session = session.with_activity(activity.clone());
+        let ssh_config = ssh_config.clone();
+        let registry = connections.clone();
+        connections.spawn(async move {
+            let running = match russh::server::run_stream(ssh_config, socket, session).await {
+                Ok(running) => running,
+                Err(err) => {
+                    debug!(%peer_addr, error = %err, "Connection setup failed");
+                    return;
+                }
+            };
+            let handle = running.handle();
+            let id = registry.register(handle.clone(), activity);
+            let result = match max_session_duration {
+                Some(limit) => {
+                    let mut running = running;
+                    match tokio::time::timeout(limit, &mut running).await {
+                        Ok(result) => result,
+                        Err(_) => {
+                            info!(%peer_addr, ?limit, "Session reached maximum duration");
+                            let _ = handle
+                                .disconnect(
+                                    Disconnect::ByApplication,
+                                    "Maximum session duration reached".into(),
+                                    "en".into(),
+                                )
+                                .await;
+                            running.await
+                        }
+                    }
+                }
+                None => running.await,
+            };
+            match result {
+                Ok(()) => debug!(%peer_addr, "Connection closed"),
+                Err(err) => debug!(%peer_addr, error = %err, "Connection closed with error"),
+            }
+            registry.unregister(id);
+        });
+    }
+
+    info!("Shutting down, no longer accepting connections");
+    drop(listeners);
+    connections.drain(shutdown_timeout).await;
+}
+
 /// Convenience function to run a server
 pub async fn run<B: Backend>(
     backend: B,
@@ -194,4 +721,67 @@ pub async fn run<B: Backend>(
 }
 
 // Re-export auth types for advanced usage
-pub use crate::ssh_handler::{PasswordAuthCallback, PubkeyAuthCallback};
+pub use crate::auth::{AsyncPasswordAuthCallback, AsyncPubkeyAuthCallback};
+pub use crate::ssh_handler::{HomeDirCallback, PasswordAuthCallback, PubkeyAuthCallback};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryBackend;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpStream;
+
+    async fn read_banner(addr: SocketAddr) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let mut buf = [0u8; 8];
+        stream.read_exact(&mut buf).await.unwrap();
+        String::from_utf8_lossy(&buf).into_owned()
+    }
+
+    #[test]
+    fn test_addresses() {
+        let config = ServerConfig::new().port(2022);
+        assert_eq!(config.addresses(), vec!["0.0.0.0:2022".parse().unwrap()]);
+
+        let config = config
+            .bind("127.0.0.1:22".parse().unwrap())
+            .bind("[::1]:22".parse().unwrap());
+        assert_eq!(
+            config.addresses(),
+            vec!["127.0.0.1:22".parse().unwrap(), "[::1]:22".parse().unwrap()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_on_listeners() {
+        let mut listeners = vec![TcpListener::bind("127.0.0.1:0").await.unwrap()];
+        // Not every test environment has IPv6 loopback
+        if let Ok(listener) = TcpListener::bind("[::1]:0").await {
+            listeners.push(listener);
+        }
+        let addrs: Vec<SocketAddr> = listeners.iter().map(|l| l.local_addr().unwrap()).collect();
+
+        let server = Server::new(MemoryBackend::new())
+            .config(ServerConfig::new().with_generated_key())
+            .with_users(vec![("user".into(), "pass".into())]);
+        let shutdown = server.shutdown_handle();
+        let running = tokio::spawn(server.run_on_listeners(listeners));
+
+        for addr in addrs {
+            assert_eq!(read_banner(addr).await, "SSH-2.0-");
+        }
+
+        shutdown.shutdown();
+        tokio::time::timeout(Duration::from_secs(5), running)
+            .await
+            .expect("server did not shut down")
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run_on_no_listeners() {
+        let server = Server::new(MemoryBackend::new());
+        assert!(server.run_on_listeners(Vec::new()).await.is_err());
+    }
+}
@@ -1,18 +1,79 @@
-use crate::backend::{normalize_path, Backend, BackendError, FileInfo};
-use crate::handle::{HandleManager, HandleType};
+use crate::audit::{AuditKind, AuditLog, SessionAudit, TransferMode};
+use crate::backend::{link_target_path, normalize_path, Backend, BackendError, FileInfo, SetAttrs};
+use crate::events::{EventSender, FileEventKind, SessionEvents, StreamingChecksum};
+use crate::handle::{
+    HandleManager, HandleType, UnflushedWrites, Upload, WriteBuffer, WriteMode,
+    DEFAULT_MAX_FILE_SIZE,
+};
+use crate::permissions::{Operation, PermissionPolicy};
+use crate::shutdown::Activity;
 use bytes::Bytes;
+use russh_sftp::extensions::{
+    self, FsyncExtension, HardlinkExtension, LimitsExtension, Statvfs, StatvfsExtension,
+};
 use russh_sftp::protocol::{
-    Attrs, Data, File, FileAttributes, Handle, Name, OpenFlags, Status, StatusCode, Version,
+    Attrs, Data, ExtendedReply, File, FileAttributes, Handle, Name, OpenFlags, Packet, Status,
+    StatusCode, Version,
 };
+use russh_sftp::server::Handler;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tracing::debug;
+use tracing::{debug, info, warn};
+
+/// Rename that replaces an existing target, as `rename(2)` does
+const POSIX_RENAME: &str = "posix-rename@openssh.com";
+
+/// Largest packet advertised through `limits@openssh.com`, the OpenSSH value
+const MAX_PACKET_LEN: u64 = 256 * 1024;
+
+/// Largest read or write payload, leaving room for the packet header
+const MAX_DATA_LEN: u32 = MAX_PACKET_LEN as u32 - 1024;
+
+/// File and directory handles one session may hold open at once
+const MAX_OPEN_HANDLES: usize = 1024;
+
+/// Unit of the block counts in statvfs replies
+const STATVFS_BLOCK_SIZE: u64 = 4096;
+
+/// Copy between two open handles, from the filexfer extensions draft
+const COPY_DATA: &str = "copy-data";
+
+/// Copy one path to another, from the filexfer extensions draft
+const COPY_FILE: &str = "copy-file";
+
+/// Request data of `posix-rename@openssh.com`
+#[derive(Serialize, Deserialize)]
+struct PosixRenameExtension {
+    oldpath: String,
+    newpath: String,
+}
+
+/// Request data of `copy-data`; a length of 0 copies to the end of the file
+#[derive(Serialize, Deserialize)]
+struct CopyDataExtension {
+    read_from_handle: String,
+    read_from_offset: u64,
+    read_data_length: u64,
+    write_to_handle: String,
+    write_to_offset: u64,
+}
+
+/// Request data of `copy-file`
+#[derive(Serialize, Deserialize)]
+struct CopyFileExtension {
+    source: String,
+    destination: String,
+    /// SSH boolean, replace `destination` if nonzero
+    overwrite: u8,
+}
 
 /// Convert FileInfo to russh_sftp FileAttributes
 fn to_file_attributes(info: &FileInfo) -> FileAttributes {
     FileAttributes {
         size: Some(info.size),
-        permissions: Some(info.permissions),
+        permissions: Some(info.mode()),
         mtime: Some(info.mtime),
         atime: Some(info.atime),
         uid: Some(info.uid),
@@ -21,18 +82,699 @@ fn to_file_attributes(info: &FileInfo) -> FileAttributes {
     }
 }
 
+/// Convert the settable parts of client FileAttributes to SetAttrs
+fn to_set_attrs(attrs: &FileAttributes) -> SetAttrs {
+    SetAttrs {
+        size: attrs.size,
+        // Clients may send the file type bits along with the mode
+        permissions: attrs.permissions.map(|mode| mode & 0o7777),
+        uid: attrs.uid,
+        gid: attrs.gid,
+        atime: attrs.atime,
+        mtime: attrs.mtime,
+    }
+}
+
+/// Resolve `.` and `..` in a client path, clamping `..` at the root like chroot
+fn canonical_path(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            name => parts.push(name),
+        }
+    }
+    format!("/{}", parts.join("/"))
+}
+
 /// SFTP session handler that delegates to a backend
-pub struct SftpHandler<B: Backend> {
+pub struct SftpHandler<B: Backend + ?Sized> {
     backend: Arc<B>,
     handles: HandleManager,
+    /// Authenticated user this session belongs to
+    user: Option<String>,
+    /// Rules deciding which operations the user may perform
+    permissions: Option<Arc<PermissionPolicy>>,
+    /// Open files, which a graceful shutdown waits for
+    activity: Option<Activity>,
+    /// What to do with write handles left open when the session ends
+    unflushed_writes: UnflushedWrites,
+    /// Where file operations are recorded
+    audit: Option<Arc<AuditLog>>,
+    /// Remote address of the client, for the audit log
+    peer_addr: Option<SocketAddr>,
+    /// Bytes read or written through each open file handle
+    transferred: HashMap<String, u64>,
+    /// Where upload, download, remove and rename events are queued
+    events: Option<EventSender>,
+    /// Checksums of open uploads, tracked only when events are enabled
+    checksums: HashMap<String, StreamingChecksum>,
+    /// Acknowledge setstat requests the backend can't honor instead of failing
+    lenient_setstat: bool,
+    /// Attributes set on open write handles, applied once the upload is stored
+    pending_attrs: HashMap<String, SetAttrs>,
+    /// Writes ending past this many bytes are refused
+    max_file_size: u64,
 }
 
-impl<B: Backend> SftpHandler<B> {
+impl<B: Backend + ?Sized> SftpHandler<B> {
     pub fn new(backend: Arc<B>) -> Self {
         Self {
             backend,
             handles: HandleManager::new(),
+            user: None,
+            permissions: None,
+            activity: None,
+            unflushed_writes: UnflushedWrites::default(),
+            audit: None,
+            peer_addr: None,
+            transferred: HashMap::new(),
+            events: None,
+            checksums: HashMap::new(),
+            lenient_setstat: false,
+            pending_attrs: HashMap::new(),
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+        }
+    }
+
+    /// Set the authenticated user for this session
+    pub fn with_user(mut self, user: impl Into<String>) -> Self {
+        self.user = Some(user.into());
+        self
+    }
+
+    /// Authenticated user for this session, if known
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    /// Spill write buffers to a temporary file once they exceed `threshold` bytes
+    pub fn with_spill_threshold(mut self, threshold: usize) -> Self {
+        self.handles = HandleManager::with_spill_threshold(threshold);
+        self
+    }
+
+    /// Refuse writes that would make a file larger than `bytes`
+    pub fn with_max_file_size(mut self, bytes: u64) -> Self {
+        self.max_file_size = bytes;
+        self
+    }
+
+    /// Check every operation against `permissions`
+    pub fn with_permissions(mut self, permissions: Arc<PermissionPolicy>) -> Self {
+        self.permissions = Some(permissions);
+        self
+    }
+
+    /// Commit or discard write handles the client never closed
+    pub fn with_unflushed_writes(mut self, policy: UnflushedWrites) -> Self {
+        self.unflushed_writes = policy;
+        self
+    }
+
+    /// Acknowledge setstat requests the backend can't honor, applying what it can
+    pub fn with_lenient_setstat(mut self, lenient: bool) -> Self {
+        self.lenient_setstat = lenient;
+        self
+    }
+
+    /// Record file operations to `audit`
+    pub fn with_audit(mut self, audit: Arc<AuditLog>) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Record the remote address of the client
+    pub fn with_peer_addr(mut self, peer_addr: Option<SocketAddr>) -> Self {
+        self.peer_addr = peer_addr;
+        self
+    }
+
+    /// Queue file events for the server's event hooks
+    pub(crate) fn with_events(mut self, events: EventSender) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Report open files to the connection's shutdown tracker
+    pub(crate) fn with_activity(mut self, activity: Activity) -> Self {
+        self.activity = Some(activity);
+        self
+    }
+
+    /// Fail with `PermissionDenied` unless the policy allows `operation` on `path`
+    fn authorize(&self, operation: Operation, path: &str) -> Result<(), StatusCode> {
+        let Some(ref permissions) = self.permissions else {
+            return Ok(());
+        };
+        let user = self.user.as_deref().unwrap_or_default();
+        let path = canonical_path(path);
+        if permissions.is_allowed(user, operation, &path) {
+            Ok(())
+        } else {
+            info!(user, ?operation, path, "Operation denied by policy");
+            Err(StatusCode::PermissionDenied)
+        }
+    }
+
+    /// Authorize `operation` on `path` and on where its symlinks lead
+    ///
+    /// A link in a writable directory must not let a client reach a file the
+    /// policy protects. The last component is only followed if
+    /// `follow_last`, as for the request itself.
+    async fn authorize_resolved(
+        &self,
+        operation: Operation,
+        path: &str,
+        follow_last: bool,
+    ) -> Result<(), StatusCode> {
+        self.authorize(operation, path)?;
+        if self.permissions.is_none() {
+            return Ok(());
+        }
+        let resolved = self
+            .backend
+            .resolve_links(&normalize_path(path), follow_last)
+            .await?;
+        let resolved = format!("/{resolved}");
+        if resolved != canonical_path(path) {
+            self.authorize(operation, &resolved)?;
+        }
+        Ok(())
+    }
+
+    fn session_audit(&self) -> Option<SessionAudit> {
+        let log = self.audit.clone()?;
+        Some(SessionAudit::new(log, self.user.clone(), self.peer_addr))
+    }
+
+    /// Record `kind` with the outcome of `result`
+    fn audit<T>(&self, kind: AuditKind, result: &Result<T, StatusCode>) {
+        if let Some(audit) = self.session_audit() {
+            audit.record(kind, result.as_ref().err().map(|code| format!("{code:?}")));
+        }
+    }
+
+    fn session_events(&self) -> Option<SessionEvents> {
+        let sender = self.events.clone()?;
+        Some(SessionEvents::new(sender, self.user.clone()))
+    }
+
+    /// Queue `kind` for the event hooks if `result` succeeded
+    fn notify<T>(&self, kind: FileEventKind, result: &Result<T, StatusCode>) {
+        if let (Ok(_), Some(events)) = (result, self.session_events()) {
+            events.send(kind);
+        }
+    }
+
+    /// The part of `attrs` to apply, or `OpUnsupported` if the backend can't
+    /// honor all of it and the session isn't lenient
+    fn settable_attrs(&self, attrs: &FileAttributes) -> Result<SetAttrs, StatusCode> {
+        let attrs = to_set_attrs(attrs);
+        let capabilities = self.backend.attr_capabilities();
+        if capabilities.supports(&attrs) {
+            Ok(attrs)
+        } else if self.lenient_setstat {
+            Ok(capabilities.filter(attrs))
+        } else {
+            Err(StatusCode::OpUnsupported)
+        }
+    }
+
+    async fn apply_attrs(&self, path: &str, attrs: SetAttrs) -> Result<(), StatusCode> {
+        if attrs.is_empty() {
+            return Ok(());
+        }
+        match self.backend.set_attrs(path, attrs).await {
+            Err(BackendError::Unsupported) if self.lenient_setstat => Ok(()),
+            result => result.map_err(StatusCode::from),
+        }
+    }
+
+    /// Handle `hardlink@openssh.com`
+    async fn hardlink(
+        &mut self,
+        id: u32,
+        oldpath: String,
+        newpath: String,
+    ) -> Result<Status, StatusCode> {
+        debug!(id, from = %oldpath, to = %newpath, "Creating hard link");
+        let result = async {
+            // The new name shares the data, so it may only be made by someone
+            // who could read and change the file anyway
+            for operation in [Operation::Read, Operation::Write, Operation::Setstat] {
+                self.authorize_resolved(operation, &oldpath, false).await?;
+            }
+            self.authorize_resolved(Operation::Link, &newpath, false)
+                .await?;
+            self.backend
+                .hard_link(&normalize_path(&oldpath), &normalize_path(&newpath))
+                .await
+                .map_err(StatusCode::from)
+        }
+        .await;
+        let (from, to) = (canonical_path(&oldpath), canonical_path(&newpath));
+        self.audit(AuditKind::Hardlink { from, to }, &result);
+        result?;
+
+        Ok(ok_status(id))
+    }
+
+    /// Handle `copy-file` with a backend copy
+    async fn copy_file(
+        &mut self,
+        id: u32,
+        source: String,
+        destination: String,
+        overwrite: bool,
+    ) -> Result<Status, StatusCode> {
+        debug!(id, from = %source, to = %destination, overwrite, "Copying file");
+        let result = async {
+            self.authorize_resolved(Operation::Read, &source, true)
+                .await?;
+            self.authorize_resolved(Operation::Write, &destination, true)
+                .await?;
+            let destination = normalize_path(&destination);
+            if !overwrite {
+                match self.backend.symlink_info(&destination).await {
+                    Ok(_) => return Err(StatusCode::Failure),
+                    Err(BackendError::NotFound) => {}
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            self.backend
+                .copy(&normalize_path(&source), &destination)
+                .await
+                .map_err(StatusCode::from)
+        }
+        .await;
+        let (from, to) = (canonical_path(&source), canonical_path(&destination));
+        self.audit(AuditKind::Copy { from, to }, &result);
+        result?;
+
+        Ok(ok_status(id))
+    }
+
+    /// Handle `copy-data` between two handles open in this session
+    ///
+    /// The handles were authorized when opened. Data is moved in chunks
+    /// through the usual read and write paths unless the whole file can be
+    /// copied by the backend.
+    async fn copy_data(
+        &mut self,
+        id: u32,
+        request: CopyDataExtension,
+    ) -> Result<Status, StatusCode> {
+        let CopyDataExtension {
+            read_from_handle: source,
+            read_from_offset: offset,
+            read_data_length: len,
+            write_to_handle: destination,
+            write_to_offset: write_offset,
+        } = request;
+        debug!(id, from = %source, to = %destination, offset, len, "Copying data");
+
+        if source == destination {
+            let read_end = if len == 0 {
+                u64::MAX
+            } else {
+                offset.saturating_add(len)
+            };
+            let write_end = write_offset.saturating_add(read_end - offset);
+            if offset < write_end && write_offset < read_end {
+                return Err(StatusCode::Failure);
+            }
+        }
+        if offset == 0 && write_offset == 0 && self.backend_copy(&source, &destination, len).await?
+        {
+            return Ok(ok_status(id));
+        }
+
+        let mut copied = 0;
+        while len == 0 || copied < len {
+            let chunk = if len == 0 {
+                MAX_DATA_LEN
+            } else {
+                (len - copied).min(MAX_DATA_LEN as u64) as u32
+            };
+            let data = match self
+                .read(id, source.clone(), offset.saturating_add(copied), chunk)
+                .await
+            {
+                Ok(data) => data.data,
+                Err(StatusCode::Eof) => break,
+                Err(err) => return Err(err),
+            };
+            let read = data.len() as u64;
+            self.write(
+                id,
+                destination.clone(),
+                write_offset.saturating_add(copied),
+                data,
+            )
+            .await?;
+            copied += read;
+        }
+
+        Ok(ok_status(id))
+    }
+
+    /// Copy a whole file into a fresh write handle with [`Backend::copy`]
+    ///
+    /// Returns false, leaving both handles alone, when the copy isn't a whole
+    /// file into an empty write-only handle.
+    async fn backend_copy(
+        &mut self,
+        source: &str,
+        destination: &str,
+        len: u64,
+    ) -> Result<bool, StatusCode> {
+        let Some(HandleType::Read { path: from, size }) = self.handles.get(source) else {
+            return Ok(false);
+        };
+        if len != 0 && len < size {
+            return Ok(false);
+        }
+        let (to, mode, fresh) = match self.handles.get(destination) {
+            Some(HandleType::Write { path, buffer, mode }) => {
+                let fresh = buffer
+                    .lock()
+                    .await
+                    .as_ref()
+                    .is_some_and(WriteBuffer::is_empty);
+                (path, mode, fresh)
+            }
+            Some(HandleType::Upload { path, upload, mode }) => {
+                let fresh = upload.lock().await.as_ref().is_some_and(Upload::is_empty);
+                (path, mode, fresh)
+            }
+            _ => return Ok(false),
+        };
+        if !fresh || mode.read || from == to {
+            return Ok(false);
+        }
+
+        self.backend.copy(&from, &to).await?;
+
+        // The handle's own, empty, data must not be committed over the copy
+        if let Some(HandleType::Upload { upload, .. }) = self.handles.get(destination) {
+            if let Some(upload) = upload.lock().await.take() {
+                if let Err(err) = upload.abort().await {
+                    warn!(path = %to, error = %err, "Could not abort replaced upload");
+                }
+            }
+        }
+        self.handles
+            .replace_with_stored(destination, to, size, mode);
+        // The data never passed through, so there is nothing to hash
+        self.checksums.remove(destination);
+        Ok(true)
+    }
+
+    /// Refuse a write of `len` bytes at `offset` ending past the maximum file size
+    fn check_file_size(&self, offset: u64, len: usize) -> Result<(), StatusCode> {
+        match offset.checked_add(len as u64) {
+            Some(end) if end <= self.max_file_size => Ok(()),
+            _ => Err(BackendError::FileTooLarge.into()),
+        }
+    }
+
+    /// Handle `fsync@openssh.com` by storing what was written so far
+    async fn fsync(&mut self, id: u32, handle: String) -> Result<Status, StatusCode> {
+        debug!(id, handle = %handle, "Syncing handle");
+        match self.handles.get(&handle).ok_or(StatusCode::Failure)? {
+            HandleType::Write { path, buffer, .. } => {
+                let mut buffer = buffer.lock().await;
+                let buffer = buffer.as_mut().ok_or(StatusCode::Failure)?;
+                buffer.sync(self.backend.as_ref(), &path).await?;
+            }
+            HandleType::Upload { upload, .. } => {
+                let mut upload = upload.lock().await;
+                upload.as_mut().ok_or(StatusCode::Failure)?.sync().await?;
+            }
+            // Reads have nothing to store
+            HandleType::Read { .. } => {}
+            HandleType::Dir { .. } => return Err(StatusCode::Failure),
+        }
+
+        Ok(ok_status(id))
+    }
+
+    /// Handle `statvfs@openssh.com` from the backend's space info
+    async fn statvfs(&self, id: u32, path: String) -> Result<Packet, StatusCode> {
+        debug!(id, path = %path, "Getting filesystem stats");
+        let space = self.backend.space_info(&normalize_path(&path)).await?;
+        let blocks = |bytes: u64| bytes / STATVFS_BLOCK_SIZE;
+        extended_reply(
+            id,
+            &Statvfs {
+                block_size: STATVFS_BLOCK_SIZE,
+                fragment_size: STATVFS_BLOCK_SIZE,
+                blocks: blocks(space.total),
+                blocks_free: blocks(space.free),
+                blocks_avail: blocks(space.available),
+                inodes: 0,
+                inodes_free: 0,
+                inodes_avail: 0,
+                fs_id: 0,
+                flags: 0,
+                name_max: 255,
+            },
+        )
+    }
+
+    /// Refuse a new handle once the session holds `MAX_OPEN_HANDLES`
+    fn check_handle_limit(&self) -> Result<(), StatusCode> {
+        if self.handles.len() >= MAX_OPEN_HANDLES {
+            warn!(user = ?self.user, "Too many open handles");
+            return Err(StatusCode::Failure);
+        }
+        Ok(())
+    }
+
+    fn count_transferred(&mut self, handle: &str, bytes: usize) {
+        *self.transferred.entry(handle.to_string()).or_default() += bytes as u64;
+    }
+
+    /// Open a file handle, stat-ing reads and preparing the upload for writes
+    async fn open_handle(&mut self, path: &str, pflags: OpenFlags) -> Result<String, StatusCode> {
+        let write = pflags.contains(OpenFlags::WRITE);
+        let read = pflags.contains(OpenFlags::READ) || !write;
+        let create = pflags.contains(OpenFlags::CREATE);
+        let truncate = pflags.contains(OpenFlags::TRUNCATE);
+        let exclusive = pflags.contains(OpenFlags::EXCLUDE);
+        self.check_handle_limit()?;
+        if write || create {
+            self.authorize_resolved(Operation::Write, path, true)
+                .await?;
+        }
+        if read {
+            self.authorize_resolved(Operation::Read, path, true).await?;
+        }
+        let normalized = normalize_path(path);
+
+        // Replacing a file outright doesn't depend on what is there, so skip
+        // the lookup for the common upload case
+        let existing = if write && create && truncate && !exclusive && !read {
+            None
+        } else {
+            match self.backend.file_info(&normalized).await {
+                Ok(info) if info.is_dir => return Err(StatusCode::Failure),
+                Ok(info) => Some(info),
+                Err(BackendError::NotFound) => None,
+                Err(err) => return Err(err.into()),
+            }
+        };
+        match existing {
+            Some(_) if create && exclusive => return Err(StatusCode::Failure),
+            None if !create => return Err(StatusCode::NoSuchFile),
+            _ => {}
+        }
+
+        if !write {
+            // Read mode: data is fetched per read request
+            let size = match existing {
+                Some(info) => info.size,
+                None => {
+                    self.backend
+                        .write_file(&normalized, Bytes::new())
+                        .await
+                        .map_err(StatusCode::from)?;
+                    0
+                }
+            };
+            self.file_opened();
+            return Ok(self
+                .handles
+                .create_read_handle(normalized.into_owned(), size));
+        }
+
+        let mode = WriteMode {
+            append: pflags.contains(OpenFlags::APPEND),
+            read,
+        };
+        let threshold = self.handles.spill_threshold();
+        let whole_file = truncate || existing.is_none();
+        let handle = match existing.filter(|_| !truncate) {
+            // Updating an existing file: start from its current contents
+            Some(info) => {
+                let buffer =
+                    WriteBuffer::preload(self.backend.as_ref(), &normalized, info.size, threshold)
+                        .await
+                        .map_err(StatusCode::from)?;
+                self.handles
+                    .create_buffered_handle(normalized.into_owned(), buffer, mode)
+            }
+            // Streamed data can't be read back
+            None if read => self.handles.create_buffered_handle(
+                normalized.into_owned(),
+                WriteBuffer::new(threshold),
+                mode,
+            ),
+            // Stream to the backend if it can, otherwise buffer
+            None => match self
+                .backend
+                .open_writer(&normalized)
+                .await
+                .map_err(StatusCode::from)?
+            {
+                Some(writer) => {
+                    self.handles
+                        .create_upload_handle(normalized.into_owned(), writer, mode)
+                }
+                None => self.handles.create_buffered_handle(
+                    normalized.into_owned(),
+                    WriteBuffer::new(threshold),
+                    mode,
+                ),
+            },
+        };
+        self.file_opened();
+        // Only whole-file uploads can be hashed as they arrive
+        if self.events.is_some() && whole_file {
+            self.checksums
+                .insert(handle.clone(), StreamingChecksum::new());
+        }
+        Ok(handle)
+    }
+
+    fn file_opened(&self) {
+        if let Some(ref activity) = self.activity {
+            activity.file_opened();
+        }
+    }
+
+    fn file_closed(&self) {
+        if let Some(ref activity) = self.activity {
+            activity.file_closed();
+        }
+    }
+}
+
+/// Release write handles left open at the end of a session
+///
+/// Buffers are dropped either way, which also removes spill files.
+async fn release_handles<B: Backend + ?Sized>(
+    backend: Arc<B>,
+    handles: Vec<(HandleType, Option<StreamingChecksum>)>,
+    policy: UnflushedWrites,
+    activity: Option<Activity>,
+    audit: Option<SessionAudit>,
+    events: Option<SessionEvents>,
+) {
+    for (handle, checksum) in handles {
+        let (path, bytes, result) = match handle {
+            HandleType::Write { path, buffer, .. } => {
+                let buffer = buffer.lock().await.take();
+                let bytes = buffer.as_ref().map_or(0, |b| b.len());
+                let result = match (buffer, policy) {
+                    (Some(buffer), UnflushedWrites::Commit) if buffer.is_changed() => {
+                        buffer.commit(backend.as_ref(), &path).await
+                    }
+                    _ => Ok(()),
+                };
+                (path, bytes, result)
+            }
+            HandleType::Upload { path, upload, .. } => {
+                let upload = upload.lock().await.take();
+                let bytes = upload.as_ref().map_or(0, |u| u.len());
+                let result = match (upload, policy) {
+                    (Some(upload), UnflushedWrites::Commit) => upload.commit().await,
+                    (Some(upload), UnflushedWrites::Discard) => upload.abort().await,
+                    (None, _) => Ok(()),
+                };
+                (path, bytes, result)
+            }
+            _ => continue,
+        };
+        if let Some(ref activity) = activity {
+            activity.file_closed();
+        }
+        let error = match (&result, policy) {
+            (Err(err), _) => Some(err.to_string()),
+            (Ok(()), UnflushedWrites::Discard) => {
+                Some("discarded: session ended before close".to_string())
+            }
+            (Ok(()), UnflushedWrites::Commit) => None,
+        };
+        if let (None, Some(ref events)) = (&error, &events) {
+            events.send(FileEventKind::Uploaded {
+                path: canonical_path(&path),
+                size: bytes,
+                sha256: checksum.and_then(StreamingChecksum::finish),
+            });
+        }
+        if let Some(ref audit) = audit {
+            let kind = AuditKind::Close {
+                path: canonical_path(&path),
+                mode: TransferMode::Write,
+                bytes,
+            };
+            audit.record(kind, error);
+        }
+        match result {
+            Ok(()) => info!(path, ?policy, "Released write handle left open by client"),
+            Err(err) => warn!(path, ?policy, error = %err, "Could not release write handle"),
+        }
+    }
+}
+
+impl<B: Backend + ?Sized> Drop for SftpHandler<B> {
+    fn drop(&mut self) {
+        let mut handles: Vec<(HandleType, Option<StreamingChecksum>)> = Vec::new();
+        for (id, handle) in self.handles.drain() {
+            match handle {
+                // Downloads have nothing to release
+                HandleType::Read { .. } => self.file_closed(),
+                HandleType::Write { .. } | HandleType::Upload { .. } => {
+                    let checksum = self.checksums.remove(&id);
+                    handles.push((handle, checksum));
+                }
+                _ => {}
+            }
         }
+        if handles.is_empty() {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            warn!(
+                count = handles.len(),
+                "Dropping unclosed write handles outside a runtime"
+            );
+            return;
+        };
+        // The activity keeps a graceful shutdown waiting until this finishes
+        runtime.spawn(release_handles(
+            self.backend.clone(),
+            handles,
+            self.unflushed_writes,
+            self.activity.take(),
+            self.session_audit(),
+            self.session_events(),
+        ));
     }
 }
 
@@ -46,12 +788,24 @@ impl From<BackendError> for StatusCode {
             BackendError::NotADirectory => StatusCode::NoSuchFile,
             BackendError::IsADirectory => StatusCode::Failure,
             BackendError::DirectoryNotEmpty => StatusCode::Failure,
+            BackendError::Unsupported => StatusCode::OpUnsupported,
+            BackendError::NotASymlink => StatusCode::Failure,
+            BackendError::SymlinkLoop => StatusCode::Failure,
+            BackendError::FileTooLarge => StatusCode::Failure,
             BackendError::Io(_) => StatusCode::Failure,
             BackendError::Other(_) => StatusCode::Failure,
         }
     }
 }
 
+fn extended_reply<T: Serialize>(id: u32, reply: &T) -> Result<Packet, StatusCode> {
+    let data = russh_sftp::ser::to_bytes(reply).map_err(|_| StatusCode::Failure)?;
+    Ok(Packet::ExtendedReply(ExtendedReply {
+        id,
+        data: data.to_vec(),
+    }))
+}
+
 fn ok_status(id: u32) -> Status {
     Status {
         id,
@@ -61,7 +815,7 @@ fn ok_status(id: u32) -> Status {
     }
 }
 
-impl<B: Backend> russh_sftp::server::Handler for SftpHandler<B> {
+impl<B: Backend + ?Sized> russh_sftp::server::Handler for SftpHandler<B> {
     type Error = StatusCode;
 
     fn unimplemented(&self) -> Self::Error {
@@ -73,27 +827,101 @@ impl<B: Backend> russh_sftp::server::Handler for SftpHandler<B> {
         version: u32,
         _extensions: HashMap<String, String>,
     ) -> Result<Version, Self::Error> {
-        debug!(version, "SFTP init");
-        Ok(Version::new())
+        debug!(version, user = ?self.user, "SFTP init");
+        let mut version = Version::new();
+        if self.backend.atomic_rename() {
+            version
+                .extensions
+                .insert(POSIX_RENAME.to_string(), "1".to_string());
+        }
+        for (name, revision) in [
+            (extensions::STATVFS, "2"),
+            (extensions::FSYNC, "1"),
+            (extensions::HARDLINK, "1"),
+            (extensions::LIMITS, "1"),
+            (COPY_DATA, "1"),
+            (COPY_FILE, "1"),
+        ] {
+            version
+                .extensions
+                .insert(name.to_string(), revision.to_string());
+        }
+        Ok(version)
     }
 
     async fn close(&mut self, id: u32, handle: String) -> Result<Status, Self::Error> {
         debug!(id, handle = %handle, "Closing handle");
 
-        // If it's a write handle, flush the buffer to backend
-        if let Some(HandleType::Write { path, buffer }) = self.handles.get(&handle) {
-            self.backend
-                .write_file(&path, Bytes::from(buffer))
-                .await
-                .map_err(StatusCode::from)?;
+        let bytes = self.transferred.remove(&handle).unwrap_or(0);
+        let checksum = self.checksums.remove(&handle);
+        let mut changed = true;
+        let (result, closed) = match self.handles.remove(&handle) {
+            // Flush the buffer to the backend
+            Some(HandleType::Write { path, buffer, .. }) => {
+                let (size, result) = match buffer.lock().await.take() {
+                    // Nothing written since opening or the last fsync
+                    Some(buffer) if !buffer.is_changed() => {
+                        changed = buffer.is_synced();
+                        (buffer.len(), Ok(()))
+                    }
+                    Some(buffer) => (
+                        buffer.len(),
+                        buffer.commit(self.backend.as_ref(), &path).await,
+                    ),
+                    None => (0, Ok(())),
+                };
+                self.file_closed();
+                (result, Some((path, TransferMode::Write, size)))
+            }
+            // Complete the streaming upload
+            Some(HandleType::Upload { path, upload, .. }) => {
+                let (size, result) = match upload.lock().await.take() {
+                    Some(upload) => (upload.len(), upload.commit().await),
+                    None => (0, Ok(())),
+                };
+                self.file_closed();
+                (result, Some((path, TransferMode::Write, size)))
+            }
+            Some(HandleType::Read { path, .. }) => {
+                self.file_closed();
+                (Ok(()), Some((path, TransferMode::Read, bytes)))
+            }
+            _ => (Ok(()), None),
+        };
+        let mut result = result.map_err(StatusCode::from);
+        if let (Ok(()), Some(attrs), Some((path, TransferMode::Write, _))) =
+            (&result, self.pending_attrs.remove(&handle), &closed)
+        {
+            result = self.apply_attrs(path, attrs).await;
+        }
+        if let Some((path, mode, size)) = closed {
+            let path = canonical_path(&path);
+            let event = match mode {
+                TransferMode::Write => FileEventKind::Uploaded {
+                    path: path.clone(),
+                    size,
+                    sha256: checksum.and_then(StreamingChecksum::finish),
+                },
+                TransferMode::Read => FileEventKind::Downloaded {
+                    path: path.clone(),
+                    size,
+                },
+            };
+            if changed {
+                self.notify(event, &result);
+            }
+            self.audit(AuditKind::Close { path, mode, bytes }, &result);
         }
+        result?;
 
-        self.handles.remove(&handle);
         Ok(ok_status(id))
     }
 
     async fn opendir(&mut self, id: u32, path: String) -> Result<Handle, Self::Error> {
         debug!(id, path = %path, "Opening directory");
+        self.check_handle_limit()?;
+        self.authorize_resolved(Operation::List, &path, true)
+            .await?;
         let normalized = normalize_path(&path);
 
         // Verify it's a directory
@@ -160,21 +988,15 @@ impl<B: Backend> russh_sftp::server::Handler for SftpHandler<B> {
         _attrs: FileAttributes,
     ) -> Result<Handle, Self::Error> {
         debug!(id, path = %path, ?pflags, "Opening file");
-        let normalized = normalize_path(&path);
-
-        let handle = if pflags.contains(OpenFlags::WRITE) {
-            // Write mode: create empty buffer
-            self.handles.create_write_handle(normalized.into_owned())
+        let mode = if pflags.contains(OpenFlags::WRITE) {
+            TransferMode::Write
         } else {
-            // Read mode: load file content (returns Bytes)
-            let content = self
-                .backend
-                .read_file(&normalized)
-                .await
-                .map_err(StatusCode::from)?;
-            self.handles
-                .create_read_handle(normalized.into_owned(), content)
+            TransferMode::Read
         };
+        let result = self.open_handle(&path, pflags).await;
+        let path = canonical_path(&path);
+        self.audit(AuditKind::Open { path, mode }, &result);
+        let handle = result?;
 
         Ok(Handle { id, handle })
     }
@@ -187,20 +1009,45 @@ impl<B: Backend> russh_sftp::server::Handler for SftpHandler<B> {
         len: u32,
     ) -> Result<Data, Self::Error> {
         debug!(id, handle = %handle, offset, len, "Reading file");
+        let len = len.min(MAX_DATA_LEN);
 
         let handle_data = self.handles.get(&handle).ok_or(StatusCode::Failure)?;
 
         match handle_data {
-            HandleType::Read { content, .. } => {
-                let start = offset as usize;
-                if start >= content.len() {
+            HandleType::Read { path, size } => {
+                if offset >= size {
                     return Err(StatusCode::Eof);
                 }
 
-                let end = std::cmp::min(start + len as usize, content.len());
-                // Use Bytes::slice for efficient sub-range, then convert to Vec for protocol
-                let data = content.slice(start..end).to_vec();
+                // Clamp to the size seen at open so backends never see a range past EOF
+                let len = std::cmp::min(len as u64, size - offset) as u32;
+                let data = self
+                    .backend
+                    .read_range(&path, offset, len)
+                    .await
+                    .map_err(StatusCode::from)?;
+                if data.is_empty() {
+                    return Err(StatusCode::Eof);
+                }
+                self.count_transferred(&handle, data.len());
 
+                Ok(Data {
+                    id,
+                    data: data.to_vec(),
+                })
+            }
+            // Opened for both reading and writing: read back the buffer
+            HandleType::Write { path, buffer, mode } if mode.read => {
+                let mut buffer = buffer.lock().await;
+                let buffer = buffer.as_mut().ok_or(StatusCode::Failure)?;
+                buffer.load(self.backend.as_ref(), &path).await?;
+                let data = buffer
+                    .read_at(offset, len as usize)
+                    .await
+                    .map_err(StatusCode::from)?;
+                if data.is_empty() {
+                    return Err(StatusCode::Eof);
+                }
                 Ok(Data { id, data })
             }
             _ => Err(StatusCode::Failure),
@@ -219,24 +1066,38 @@ impl<B: Backend> russh_sftp::server::Handler for SftpHandler<B> {
         let handle_data = self.handles.get(&handle).ok_or(StatusCode::Failure)?;
 
         match handle_data {
-            HandleType::Write { path, mut buffer } => {
-                // Handle writes at offset
-                let start = offset as usize;
-                if start > buffer.len() {
-                    buffer.resize(start, 0);
-                }
-                if start == buffer.len() {
-                    buffer.extend_from_slice(&data);
-                } else {
-                    let end = start + data.len();
-                    if end > buffer.len() {
-                        buffer.resize(end, 0);
-                    }
-                    buffer[start..end].copy_from_slice(&data);
+            HandleType::Write { path, buffer, mode } => {
+                let mut buffer = buffer.lock().await;
+                let buffer = buffer.as_mut().ok_or(StatusCode::Failure)?;
+                buffer.load(self.backend.as_ref(), &path).await?;
+                let offset = if mode.append { buffer.len() } else { offset };
+                self.check_file_size(offset, data.len())?;
+                buffer
+                    .write_at(offset, &data)
+                    .await
+                    .map_err(StatusCode::from)?;
+                self.count_transferred(&handle, data.len());
+                if let Some(checksum) = self.checksums.get_mut(&handle) {
+                    checksum.update(offset, &data);
                 }
 
-                self.handles
-                    .update(&handle, HandleType::Write { path, buffer });
+                Ok(ok_status(id))
+            }
+            HandleType::Upload { upload, mode, .. } => {
+                let mut upload = upload.lock().await;
+                let upload = upload.as_mut().ok_or(StatusCode::Failure)?;
+                let offset = if mode.append { upload.len() } else { offset };
+                let len = data.len();
+                self.check_file_size(offset, len)?;
+                // The data moves into the upload, so hash it first
+                if let Some(checksum) = self.checksums.get_mut(&handle) {
+                    checksum.update(offset, &data);
+                }
+                if let Err(err) = upload.write(offset, data).await {
+                    self.checksums.remove(&handle);
+                    return Err(err.into());
+                }
+                self.count_transferred(&handle, len);
 
                 Ok(ok_status(id))
             }
@@ -259,16 +1120,32 @@ impl<B: Backend> russh_sftp::server::Handler for SftpHandler<B> {
     }
 
     async fn lstat(&mut self, id: u32, path: String) -> Result<Attrs, Self::Error> {
-        // No symlink support, same as stat
-        self.stat(id, path).await
+        debug!(id, path = %path, "Getting link stats");
+        let info = self
+            .backend
+            .symlink_info(&normalize_path(&path))
+            .await
+            .map_err(StatusCode::from)?;
+
+        Ok(Attrs {
+            id,
+            attrs: to_file_attributes(&info),
+        })
     }
 
     async fn fstat(&mut self, id: u32, handle: String) -> Result<Attrs, Self::Error> {
         let handle_data = self.handles.get(&handle).ok_or(StatusCode::Failure)?;
 
         let (path, size) = match handle_data {
-            HandleType::Read { path, content } => (path, content.len() as u64),
-            HandleType::Write { path, buffer } => (path, buffer.len() as u64),
+            HandleType::Read { path, size } => (path, size),
+            HandleType::Write { path, buffer, .. } => {
+                let size = buffer.lock().await.as_ref().map_or(0, |b| b.len());
+                (path, size)
+            }
+            HandleType::Upload { path, upload, .. } => {
+                let size = upload.lock().await.as_ref().map_or(0, |u| u.len());
+                (path, size)
+            }
             HandleType::Dir { .. } => {
                 return Ok(Attrs {
                     id,
@@ -291,12 +1168,8 @@ impl<B: Backend> russh_sftp::server::Handler for SftpHandler<B> {
     }
 
     async fn realpath(&mut self, id: u32, path: String) -> Result<Name, Self::Error> {
-        let normalized = normalize_path(&path);
-        let absolute = if normalized.is_empty() {
-            "/".to_string()
-        } else {
-            format!("/{}", normalized)
-        };
+        // Relative paths resolve against "/", which is the user's home when scoped
+        let absolute = canonical_path(&path);
 
         Ok(Name {
             id,
@@ -311,30 +1184,56 @@ impl<B: Backend> russh_sftp::server::Handler for SftpHandler<B> {
         _attrs: FileAttributes,
     ) -> Result<Status, Self::Error> {
         debug!(id, path = %path, "Creating directory");
-        self.backend
-            .make_dir(&normalize_path(&path))
-            .await
-            .map_err(StatusCode::from)?;
+        let result = async {
+            self.authorize_resolved(Operation::Mkdir, &path, false)
+                .await?;
+            self.backend
+                .make_dir(&normalize_path(&path))
+                .await
+                .map_err(StatusCode::from)
+        }
+        .await;
+        let path = canonical_path(&path);
+        self.audit(AuditKind::Mkdir { path }, &result);
+        result?;
 
         Ok(ok_status(id))
     }
 
     async fn rmdir(&mut self, id: u32, path: String) -> Result<Status, Self::Error> {
         debug!(id, path = %path, "Removing directory");
-        self.backend
-            .del_dir(&normalize_path(&path))
-            .await
-            .map_err(StatusCode::from)?;
+        let result = async {
+            self.authorize_resolved(Operation::Rmdir, &path, false)
+                .await?;
+            self.backend
+                .del_dir(&normalize_path(&path))
+                .await
+                .map_err(StatusCode::from)
+        }
+        .await;
+        let path = canonical_path(&path);
+        self.audit(AuditKind::Rmdir { path }, &result);
+        result?;
 
         Ok(ok_status(id))
     }
 
     async fn remove(&mut self, id: u32, path: String) -> Result<Status, Self::Error> {
         debug!(id, path = %path, "Removing file");
-        self.backend
-            .delete(&normalize_path(&path))
-            .await
-            .map_err(StatusCode::from)?;
+        let result = async {
+            self.authorize_resolved(Operation::Remove, &path, false)
+                .await?;
+            self.backend
+                .delete(&normalize_path(&path))
+                .await
+                .map_err(StatusCode::from)
+        }
+        .await;
+        let path = canonical_path(&path);
+        let event = FileEventKind::Removed { path: path.clone() };
+        self.notify(event, &result);
+        self.audit(AuditKind::Remove { path }, &result);
+        result?;
 
         Ok(ok_status(id))
     }
@@ -346,10 +1245,25 @@ impl<B: Backend> russh_sftp::server::Handler for SftpHandler<B> {
         newpath: String,
     ) -> Result<Status, Self::Error> {
         debug!(id, from = %oldpath, to = %newpath, "Renaming");
-        self.backend
-            .rename(&normalize_path(&oldpath), &normalize_path(&newpath))
-            .await
-            .map_err(StatusCode::from)?;
+        let result = async {
+            self.authorize_resolved(Operation::Rename, &oldpath, false)
+                .await?;
+            self.authorize_resolved(Operation::Rename, &newpath, false)
+                .await?;
+            self.backend
+                .rename(&normalize_path(&oldpath), &normalize_path(&newpath))
+                .await
+                .map_err(StatusCode::from)
+        }
+        .await;
+        let (from, to) = (canonical_path(&oldpath), canonical_path(&newpath));
+        let event = FileEventKind::Renamed {
+            from: from.clone(),
+            to: to.clone(),
+        };
+        self.notify(event, &result);
+        self.audit(AuditKind::Rename { from, to }, &result);
+        result?;
 
         Ok(ok_status(id))
     }
@@ -357,20 +1271,1064 @@ impl<B: Backend> russh_sftp::server::Handler for SftpHandler<B> {
     async fn setstat(
         &mut self,
         id: u32,
-        _path: String,
-        _attrs: FileAttributes,
+        path: String,
+        attrs: FileAttributes,
     ) -> Result<Status, Self::Error> {
-        // S3 doesn't support setting attributes, just acknowledge
+        debug!(id, path = %path, "Setting attributes");
+        let result = async {
+            self.authorize_resolved(Operation::Setstat, &path, true)
+                .await?;
+            let attrs = self.settable_attrs(&attrs)?;
+            self.apply_attrs(&normalize_path(&path), attrs).await
+        }
+        .await;
+        let path = canonical_path(&path);
+        self.audit(AuditKind::Setstat { path }, &result);
+        result?;
         Ok(ok_status(id))
     }
 
     async fn fsetstat(
         &mut self,
         id: u32,
-        _handle: String,
-        _attrs: FileAttributes,
+        handle: String,
+        attrs: FileAttributes,
     ) -> Result<Status, Self::Error> {
-        // S3 doesn't support setting attributes, just acknowledge
+        let (path, deferred) = match self.handles.get(&handle).ok_or(StatusCode::Failure)? {
+            HandleType::Dir { path, .. } | HandleType::Read { path, .. } => (path, false),
+            HandleType::Write { path, .. } | HandleType::Upload { path, .. } => (path, true),
+        };
+        let result = async {
+            self.authorize_resolved(Operation::Setstat, &path, true)
+                .await?;
+            let attrs = self.settable_attrs(&attrs)?;
+            if deferred {
+                // The file isn't stored until close, so hold the attributes
+                // until then rather than have the commit overwrite them
+                let pending = self.pending_attrs.entry(handle.clone()).or_default();
+                *pending = pending.merge(attrs);
+                Ok(())
+            } else {
+                self.apply_attrs(&path, attrs).await
+            }
+        }
+        .await;
+        let path = canonical_path(&path);
+        self.audit(AuditKind::Setstat { path }, &result);
+        result?;
         Ok(ok_status(id))
     }
+
+    async fn readlink(&mut self, id: u32, path: String) -> Result<Name, Self::Error> {
+        debug!(id, path = %path, "Reading link");
+        let target = self
+            .backend
+            .read_link(&normalize_path(&path))
+            .await
+            .map_err(StatusCode::from)?;
+
+        Ok(Name {
+            id,
+            files: vec![File::dummy(target)],
+        })
+    }
+
+    // OpenSSH sends the target first, the reverse of the draft the field
+    // names follow, and every common client does the same
+    async fn symlink(
+        &mut self,
+        id: u32,
+        target: String,
+        path: String,
+    ) -> Result<Status, Self::Error> {
+        debug!(id, path = %path, target = %target, "Creating symlink");
+        let result = async {
+            self.authorize_resolved(Operation::Link, &path, false)
+                .await?;
+            // Links must not open a way around the policy for the target;
+            // requests through the link are checked where it leads
+            let target_path = link_target_path(&path, &target);
+            self.authorize_resolved(Operation::Read, &target_path, true)
+                .await?;
+            self.backend
+                .symlink(&normalize_path(&path), &target)
+                .await
+                .map_err(StatusCode::from)
+        }
+        .await;
+        let path = canonical_path(&path);
+        self.audit(AuditKind::Symlink { path, target }, &result);
+        result?;
+
+        Ok(ok_status(id))
+    }
+
+    async fn extended(
+        &mut self,
+        id: u32,
+        request: String,
+        data: Vec<u8>,
+    ) -> Result<Packet, Self::Error> {
+        debug!(id, request = %request, "Extended request");
+        let mut data = Bytes::from(data);
+        let bad_message = |_| StatusCode::BadMessage;
+        match request.as_str() {
+            // Only offered when the backend's rename already is one
+            POSIX_RENAME if self.backend.atomic_rename() => {
+                let PosixRenameExtension { oldpath, newpath } =
+                    russh_sftp::de::from_bytes(&mut data).map_err(bad_message)?;
+                self.rename(id, oldpath, newpath).await.map(Packet::Status)
+            }
+            extensions::STATVFS => {
+                let StatvfsExtension { path } =
+                    russh_sftp::de::from_bytes(&mut data).map_err(bad_message)?;
+                self.statvfs(id, path).await
+            }
+            extensions::FSYNC => {
+                let FsyncExtension { handle } =
+                    russh_sftp::de::from_bytes(&mut data).map_err(bad_message)?;
+                self.fsync(id, handle).await.map(Packet::Status)
+            }
+            extensions::HARDLINK => {
+                let HardlinkExtension { oldpath, newpath } =
+                    russh_sftp::de::from_bytes(&mut data).map_err(bad_message)?;
+                self.hardlink(id, oldpath, newpath)
+                    .await
+                    .map(Packet::Status)
+            }
+            COPY_DATA => {
+                let request = russh_sftp::de::from_bytes(&mut data).map_err(bad_message)?;
+                self.copy_data(id, request).await.map(Packet::Status)
+            }
+            COPY_FILE => {
+                let CopyFileExtension {
+                    source,
+                    destination,
+                    overwrite,
+                } = russh_sftp::de::from_bytes(&mut data).map_err(bad_message)?;
+                self.copy_file(id, source, destination, overwrite != 0)
+                    .await
+                    .map(Packet::Status)
+            }
+            extensions::LIMITS => extended_reply(
+                id,
+                &LimitsExtension {
+                    max_packet_len: MAX_PACKET_LEN,
+                    max_read_len: MAX_DATA_LEN as u64,
+                    max_write_len: MAX_DATA_LEN as u64,
+                    max_open_handles: MAX_OPEN_HANDLES as u64,
+                },
+            ),
+            _ => Err(StatusCode::OpUnsupported),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuditEvent;
+    use crate::backend::MemoryBackend;
+    use crate::events::{EventDelivery, EventHook, FileEvent, HookError};
+    use crate::permissions::Rule;
+    use russh_sftp::server::Handler;
+    use std::time::Duration;
+
+    /// Open `path` for writing, write `data` and drop the handler without closing
+    async fn abandon_upload(backend: Arc<MemoryBackend>, policy: UnflushedWrites) {
+        let mut handler = SftpHandler::new(backend).with_unflushed_writes(policy);
+        let flags = OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE;
+        let handle = handler
+            .open(1, "/partial.txt".into(), flags, FileAttributes::default())
+            .await
+            .unwrap();
+        handler
+            .write(2, handle.handle, 0, b"half an upload".to_vec())
+            .await
+            .unwrap();
+        drop(handler);
+    }
+
+    async fn wait_for_file(backend: &MemoryBackend, path: &str) -> Option<FileInfo> {
+        for _ in 0..50 {
+            if let Ok(info) = backend.file_info(path).await {
+                return Some(info);
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        None
+    }
+
+    #[tokio::test]
+    async fn test_unclosed_write_discarded_by_default() {
+        let backend = Arc::new(MemoryBackend::new());
+        abandon_upload(backend.clone(), UnflushedWrites::default()).await;
+        assert!(wait_for_file(&backend, "/partial.txt").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_unclosed_write_committed() {
+        let backend = Arc::new(MemoryBackend::new());
+        abandon_upload(backend.clone(), UnflushedWrites::Commit).await;
+        let info = wait_for_file(&backend, "/partial.txt").await.unwrap();
+        assert_eq!(info.size, 14);
+    }
+
+    #[tokio::test]
+    async fn test_closed_write_is_not_released_twice() {
+        let backend = Arc::new(MemoryBackend::new());
+        let mut handler = SftpHandler::new(backend.clone());
+        let handle = handler
+            .open(
+                1,
+                "/done.txt".into(),
+                OpenFlags::WRITE | OpenFlags::CREATE,
+                FileAttributes::default(),
+            )
+            .await
+            .unwrap();
+        handler
+            .write(2, handle.handle.clone(), 0, b"complete".to_vec())
+            .await
+            .unwrap();
+        handler.close(3, handle.handle).await.unwrap();
+        drop(handler);
+
+        let info = wait_for_file(&backend, "/done.txt").await.unwrap();
+        assert_eq!(info.size, 8);
+    }
+
+    #[tokio::test]
+    async fn test_audit_records_transfers_and_denials() {
+        let events = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let seen = events.clone();
+        let log = AuditLog::new().with_sink(move |e: &AuditEvent| seen.lock().push(e.clone()));
+        let backend = Arc::new(MemoryBackend::new());
+        let mut handler = SftpHandler::new(backend)
+            .with_user("alice")
+            .with_permissions(Arc::new(PermissionPolicy::read_only()))
+            .with_audit(Arc::new(log));
+
+        assert!(handler.remove(1, "/a.txt".into()).await.is_err());
+        let flags = OpenFlags::WRITE | OpenFlags::CREATE;
+        assert!(handler
+            .open(2, "a.txt".into(), flags, FileAttributes::default())
+            .await
+            .is_err());
+
+        let events = events.lock();
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|e| !e.success));
+        assert_eq!(events[0].user.as_deref(), Some("alice"));
+        assert_eq!(
+            events[0].kind,
+            AuditKind::Remove {
+                path: "/a.txt".into()
+            }
+        );
+        assert_eq!(
+            events[1].kind,
+            AuditKind::Open {
+                path: "/a.txt".into(),
+                mode: TransferMode::Write
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_audit_close_counts_bytes() {
+        let events = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let seen = events.clone();
+        let log = AuditLog::new().with_sink(move |e: &AuditEvent| seen.lock().push(e.clone()));
+        let backend = Arc::new(MemoryBackend::new());
+        let mut handler = SftpHandler::new(backend).with_audit(Arc::new(log));
+
+        let flags = OpenFlags::WRITE | OpenFlags::CREATE;
+        let handle = handler
+            .open(1, "/b.txt".into(), flags, FileAttributes::default())
+            .await
+            .unwrap()
+            .handle;
+        handler
+            .write(2, handle.clone(), 0, b"hello ".to_vec())
+            .await
+            .unwrap();
+        handler
+            .write(3, handle.clone(), 6, b"world".to_vec())
+            .await
+            .unwrap();
+        handler.close(4, handle).await.unwrap();
+
+        let handle = handler
+            .open(
+                5,
+                "/b.txt".into(),
+                OpenFlags::READ,
+                FileAttributes::default(),
+            )
+            .await
+            .unwrap()
+            .handle;
+        handler.read(6, handle.clone(), 0, 1024).await.unwrap();
+        handler.close(7, handle).await.unwrap();
+
+        let kinds: Vec<AuditKind> = events.lock().iter().map(|e| e.kind.clone()).collect();
+        assert_eq!(
+            kinds[1],
+            AuditKind::Close {
+                path: "/b.txt".into(),
+                mode: TransferMode::Write,
+                bytes: 11
+            }
+        );
+        assert_eq!(
+            kinds[3],
+            AuditKind::Close {
+                path: "/b.txt".into(),
+                mode: TransferMode::Read,
+                bytes: 11
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_events_after_commit() {
+        struct Record(Arc<parking_lot::Mutex<Vec<FileEvent>>>);
+
+        #[async_trait::async_trait]
+        impl EventHook for Record {
+            async fn on_event(&self, event: &FileEvent) -> Result<(), HookError> {
+                self.0.lock().push(event.clone());
+                Ok(())
+            }
+        }
+
+        let events = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let (sender, worker) = crate::events::spawn(
+            vec![Arc::new(Record(events.clone()))],
+            EventDelivery::default(),
+        );
+        let backend = Arc::new(MemoryBackend::new());
+        let mut handler = SftpHandler::new(backend)
+            .with_user("alice")
+            .with_events(sender);
+
+        let flags = OpenFlags::WRITE | OpenFlags::CREATE;
+        let handle = handler
+            .open(1, "/in.txt".into(), flags, FileAttributes::default())
+            .await
+            .unwrap()
+            .handle;
+        handler
+            .write(2, handle.clone(), 0, b"hello world".to_vec())
+            .await
+            .unwrap();
+        handler.close(3, handle).await.unwrap();
+        handler
+            .rename(4, "/in.txt".into(), "/done.txt".into())
+            .await
+            .unwrap();
+        handler.remove(5, "/done.txt".into()).await.unwrap();
+        drop(handler);
+        worker.await.unwrap();
+
+        let kinds: Vec<FileEventKind> = events.lock().iter().map(|e| e.kind.clone()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                FileEventKind::Uploaded {
+                    path: "/in.txt".into(),
+                    size: 11,
+                    sha256: Some(
+                        "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9".into()
+                    ),
+                },
+                FileEventKind::Renamed {
+                    from: "/in.txt".into(),
+                    to: "/done.txt".into(),
+                },
+                FileEventKind::Removed {
+                    path: "/done.txt".into()
+                },
+            ]
+        );
+        assert_eq!(events.lock()[0].user.as_deref(), Some("alice"));
+    }
+
+    async fn open_with(
+        handler: &mut SftpHandler<MemoryBackend>,
+        path: &str,
+        flags: OpenFlags,
+    ) -> Result<String, StatusCode> {
+        handler
+            .open(1, path.into(), flags, FileAttributes::default())
+            .await
+            .map(|h| h.handle)
+    }
+
+    #[tokio::test]
+    async fn test_open_downloads_count_as_activity() {
+        let backend = Arc::new(MemoryBackend::new());
+        backend
+            .write_file("/a.txt", Bytes::from_static(b"hello"))
+            .await
+            .unwrap();
+        let activity = crate::shutdown::Connections::new().activity();
+        let mut handler = SftpHandler::new(backend).with_activity(activity.clone());
+
+        let handle = open_with(&mut handler, "/a.txt", OpenFlags::READ)
+            .await
+            .unwrap();
+        assert!(!activity.is_idle());
+        handler.close(2, handle).await.unwrap();
+        assert!(activity.is_idle());
+
+        // Downloads left open are released with the session
+        open_with(&mut handler, "/a.txt", OpenFlags::READ)
+            .await
+            .unwrap();
+        assert!(!activity.is_idle());
+        drop(handler);
+        assert!(activity.is_idle());
+    }
+
+    #[tokio::test]
+    async fn test_open_create_and_exclusive() {
+        let backend = Arc::new(MemoryBackend::new());
+        backend
+            .write_file("/a.txt", Bytes::from_static(b"old"))
+            .await
+            .unwrap();
+        let mut handler = SftpHandler::new(backend.clone());
+
+        let exclusive = OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::EXCLUDE;
+        assert_eq!(
+            open_with(&mut handler, "/a.txt", exclusive).await,
+            Err(StatusCode::Failure)
+        );
+        assert_eq!(
+            open_with(&mut handler, "/new.txt", OpenFlags::WRITE).await,
+            Err(StatusCode::NoSuchFile)
+        );
+        let handle = open_with(&mut handler, "/new.txt", exclusive)
+            .await
+            .unwrap();
+        handler.close(2, handle).await.unwrap();
+        assert_eq!(backend.file_info("/new.txt").await.unwrap().size, 0);
+    }
+
+    #[tokio::test]
+    async fn test_open_without_truncate_keeps_contents() {
+        let backend = Arc::new(MemoryBackend::new());
+        backend
+            .write_file("/a.txt", Bytes::from_static(b"hello world"))
+            .await
+            .unwrap();
+        let mut handler = SftpHandler::new(backend.clone());
+
+        let handle = open_with(&mut handler, "/a.txt", OpenFlags::WRITE)
+            .await
+            .unwrap();
+        handler
+            .write(2, handle.clone(), 0, b"HELLO".to_vec())
+            .await
+            .unwrap();
+        handler.close(3, handle).await.unwrap();
+        assert_eq!(
+            backend.read_file("/a.txt").await.unwrap().as_ref(),
+            b"HELLO world"
+        );
+
+        // Append ignores the offset the client sends
+        let handle = open_with(&mut handler, "/a.txt", OpenFlags::WRITE | OpenFlags::APPEND)
+            .await
+            .unwrap();
+        handler
+            .write(4, handle.clone(), 0, b"!".to_vec())
+            .await
+            .unwrap();
+        handler.close(5, handle).await.unwrap();
+        assert_eq!(
+            backend.read_file("/a.txt").await.unwrap().as_ref(),
+            b"HELLO world!"
+        );
+
+        let truncate = OpenFlags::WRITE | OpenFlags::TRUNCATE;
+        let handle = open_with(&mut handler, "/a.txt", truncate).await.unwrap();
+        handler.close(6, handle).await.unwrap();
+        assert_eq!(backend.file_info("/a.txt").await.unwrap().size, 0);
+    }
+
+    #[tokio::test]
+    async fn test_read_write_handle_reads_back() {
+        let backend = Arc::new(MemoryBackend::new());
+        backend
+            .write_file("/a.txt", Bytes::from_static(b"abc"))
+            .await
+            .unwrap();
+        let mut handler = SftpHandler::new(backend.clone());
+
+        let handle = open_with(&mut handler, "/a.txt", OpenFlags::READ | OpenFlags::WRITE)
+            .await
+            .unwrap();
+        handler
+            .write(2, handle.clone(), 3, b"def".to_vec())
+            .await
+            .unwrap();
+        let data = handler.read(3, handle.clone(), 1, 100).await.unwrap();
+        assert_eq!(data.data, b"bcdef");
+        assert_eq!(
+            handler.read(4, handle.clone(), 6, 100).await.unwrap_err(),
+            StatusCode::Eof
+        );
+        handler.close(5, handle).await.unwrap();
+        assert_eq!(
+            backend.read_file("/a.txt").await.unwrap().as_ref(),
+            b"abcdef"
+        );
+
+        // Write-only handles can't be read
+        let handle = open_with(&mut handler, "/a.txt", OpenFlags::WRITE)
+            .await
+            .unwrap();
+        assert!(handler.read(6, handle, 0, 10).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_setstat_applies_supported_attrs() {
+        let backend = Arc::new(MemoryBackend::new());
+        backend
+            .write_file("/a.txt", Bytes::from_static(b"hello"))
+            .await
+            .unwrap();
+        let mut handler = SftpHandler::new(backend.clone());
+
+        let attrs = FileAttributes {
+            size: Some(2),
+            permissions: Some(0o100600),
+            mtime: Some(1_000_000),
+            ..FileAttributes::empty()
+        };
+        handler.setstat(1, "/a.txt".into(), attrs).await.unwrap();
+        let info = backend.file_info("/a.txt").await.unwrap();
+        assert_eq!((info.size, info.mtime), (2, 1_000_000));
+        assert_eq!(info.permissions & 0o7777, 0o600);
+
+        // The memory backend has no owners to change
+        let chown = FileAttributes {
+            uid: Some(1000),
+            mtime: Some(2_000_000),
+            ..FileAttributes::empty()
+        };
+        assert_eq!(
+            handler
+                .setstat(2, "/a.txt".into(), chown.clone())
+                .await
+                .unwrap_err(),
+            StatusCode::OpUnsupported
+        );
+        assert_eq!(backend.file_info("/a.txt").await.unwrap().mtime, 1_000_000);
+
+        // Lenient sessions apply what they can and report success
+        let mut handler = SftpHandler::new(backend.clone()).with_lenient_setstat(true);
+        handler.setstat(3, "/a.txt".into(), chown).await.unwrap();
+        assert_eq!(backend.file_info("/a.txt").await.unwrap().mtime, 2_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_fsetstat_on_upload_applied_after_close() {
+        let backend = Arc::new(MemoryBackend::new());
+        let mut handler = SftpHandler::new(backend.clone());
+
+        let flags = OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE;
+        let handle = open_with(&mut handler, "/a.txt", flags).await.unwrap();
+        handler
+            .write(2, handle.clone(), 0, b"hello".to_vec())
+            .await
+            .unwrap();
+        let attrs = FileAttributes {
+            permissions: Some(0o640),
+            mtime: Some(1_000_000),
+            ..FileAttributes::empty()
+        };
+        handler.fsetstat(3, handle.clone(), attrs).await.unwrap();
+        handler.close(4, handle).await.unwrap();
+
+        let info = backend.file_info("/a.txt").await.unwrap();
+        assert_eq!((info.size, info.mtime), (5, 1_000_000));
+        assert_eq!(info.permissions & 0o7777, 0o640);
+    }
+
+    #[tokio::test]
+    async fn test_symlink_readlink_and_lstat() {
+        let backend = Arc::new(MemoryBackend::new());
+        backend
+            .write_file("/a.txt", Bytes::from_static(b"hello"))
+            .await
+            .unwrap();
+        let mut handler = SftpHandler::new(backend.clone());
+
+        handler
+            .symlink(1, "a.txt".into(), "/link".into())
+            .await
+            .unwrap();
+        let name = handler.readlink(2, "/link".into()).await.unwrap();
+        assert_eq!(name.files[0].filename, "a.txt");
+
+        let link = handler.lstat(3, "/link".into()).await.unwrap().attrs;
+        assert!(link.is_symlink());
+        let file = handler.stat(4, "/link".into()).await.unwrap().attrs;
+        assert!(file.is_regular());
+        assert_eq!(file.size, Some(5));
+        assert_eq!(
+            handler.readlink(5, "/a.txt".into()).await.unwrap_err(),
+            StatusCode::Failure
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hardlink_extension() {
+        let backend = Arc::new(MemoryBackend::new());
+        backend
+            .write_file("/a.txt", Bytes::from_static(b"hello"))
+            .await
+            .unwrap();
+        let mut handler = SftpHandler::new(backend.clone());
+
+        let version = handler.init(3, HashMap::new()).await.unwrap();
+        assert_eq!(
+            version
+                .extensions
+                .get(extensions::HARDLINK)
+                .map(String::as_str),
+            Some("1")
+        );
+        let request = HardlinkExtension {
+            oldpath: "/a.txt".into(),
+            newpath: "/b.txt".into(),
+        };
+        let packet = handler
+            .extended(1, extensions::HARDLINK.into(), request.try_into().unwrap())
+            .await
+            .unwrap();
+        assert!(matches!(packet, Packet::Status(_)));
+        assert_eq!(
+            backend.read_file("/b.txt").await.unwrap().as_ref(),
+            b"hello"
+        );
+        assert_eq!(
+            handler
+                .extended(2, "unknown@example.com".into(), Vec::new())
+                .await
+                .unwrap_err(),
+            StatusCode::OpUnsupported
+        );
+    }
+
+    #[tokio::test]
+    async fn test_links_checked_against_policy() {
+        let backend = Arc::new(MemoryBackend::new());
+        backend
+            .write_file("/private/secret.txt", Bytes::from_static(b"secret"))
+            .await
+            .unwrap();
+        let policy = PermissionPolicy::new().rule(
+            Rule::deny()
+                .operations([Operation::Read])
+                .path("/private/**")
+                .unwrap(),
+        );
+        let mut handler = SftpHandler::new(backend.clone()).with_permissions(Arc::new(policy));
+
+        // Neither kind of link may expose a file the user can't read
+        assert_eq!(
+            handler
+                .symlink(1, "../private/secret.txt".into(), "/pub/link".into())
+                .await
+                .unwrap_err(),
+            StatusCode::PermissionDenied
+        );
+        let request = HardlinkExtension {
+            oldpath: "/private/secret.txt".into(),
+            newpath: "/pub/copy".into(),
+        };
+        assert_eq!(
+            handler
+                .extended(2, extensions::HARDLINK.into(), request.try_into().unwrap())
+                .await
+                .unwrap_err(),
+            StatusCode::PermissionDenied
+        );
+        assert!(backend.symlink_info("pub/link").await.is_err());
+        assert!(backend.file_info("pub/copy").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_write_denial_holds_through_links() {
+        let backend = Arc::new(MemoryBackend::new());
+        backend
+            .write_file("/protected/data.txt", Bytes::from_static(b"keep"))
+            .await
+            .unwrap();
+        let policy = PermissionPolicy::new().rule(
+            Rule::deny()
+                .operations([Operation::Write, Operation::Setstat, Operation::Remove])
+                .path("/protected/**")
+                .unwrap(),
+        );
+        let mut handler = SftpHandler::new(backend.clone()).with_permissions(Arc::new(policy));
+
+        // Readable, so the links themselves may be made
+        handler
+            .symlink(1, "../protected/data.txt".into(), "/pub/file".into())
+            .await
+            .unwrap();
+        handler
+            .symlink(2, "/protected".into(), "/pub/dir".into())
+            .await
+            .unwrap();
+
+        let write = OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE;
+        for path in ["/pub/file", "/pub/dir/data.txt", "/pub/dir/new.txt"] {
+            assert_eq!(
+                open_with(&mut handler, path, write).await,
+                Err(StatusCode::PermissionDenied),
+                "{path}"
+            );
+        }
+        let attrs = FileAttributes {
+            size: Some(0),
+            ..FileAttributes::empty()
+        };
+        assert_eq!(
+            handler
+                .setstat(3, "/pub/file".into(), attrs)
+                .await
+                .unwrap_err(),
+            StatusCode::PermissionDenied
+        );
+        assert_eq!(
+            handler
+                .remove(4, "/pub/dir/data.txt".into())
+                .await
+                .unwrap_err(),
+            StatusCode::PermissionDenied
+        );
+        // A hard link would share the data without any link to follow
+        let request = HardlinkExtension {
+            oldpath: "/protected/data.txt".into(),
+            newpath: "/pub/hard".into(),
+        };
+        assert_eq!(
+            handler
+                .extended(5, extensions::HARDLINK.into(), request.try_into().unwrap())
+                .await
+                .unwrap_err(),
+            StatusCode::PermissionDenied
+        );
+
+        assert_eq!(
+            backend
+                .read_file("/protected/data.txt")
+                .await
+                .unwrap()
+                .as_ref(),
+            b"keep"
+        );
+        // Removing the link itself is still allowed
+        handler.remove(6, "/pub/file".into()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_posix_rename_replaces_target() {
+        let backend = Arc::new(MemoryBackend::new());
+        for (path, content) in [("/a.txt", "new"), ("/b.txt", "old")] {
+            backend
+                .write_file(path, Bytes::from(content))
+                .await
+                .unwrap();
+        }
+        let mut handler = SftpHandler::new(backend.clone());
+
+        let request = PosixRenameExtension {
+            oldpath: "/a.txt".into(),
+            newpath: "/b.txt".into(),
+        };
+        let data = russh_sftp::ser::to_bytes(&request).unwrap().to_vec();
+        let packet = handler
+            .extended(1, POSIX_RENAME.into(), data)
+            .await
+            .unwrap();
+        assert!(matches!(packet, Packet::Status(_)));
+        assert!(backend.file_info("/a.txt").await.is_err());
+        assert_eq!(backend.read_file("/b.txt").await.unwrap().as_ref(), b"new");
+    }
+
+    #[tokio::test]
+    async fn test_fsync_stores_write_before_close() {
+        let backend = Arc::new(MemoryBackend::new());
+        let mut handler = SftpHandler::new(backend.clone());
+        let fsync = |handle: &str| FsyncExtension {
+            handle: handle.to_string(),
+        };
+
+        let flags = OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE;
+        let handle = open_with(&mut handler, "/a.txt", flags).await.unwrap();
+        handler
+            .write(2, handle.clone(), 0, b"hello".to_vec())
+            .await
+            .unwrap();
+        let request = fsync(&handle).try_into().unwrap();
+        handler
+            .extended(3, extensions::FSYNC.into(), request)
+            .await
+            .unwrap();
+        assert_eq!(
+            backend.read_file("/a.txt").await.unwrap().as_ref(),
+            b"hello"
+        );
+
+        handler
+            .write(4, handle.clone(), 5, b" world".to_vec())
+            .await
+            .unwrap();
+        handler.close(5, handle.clone()).await.unwrap();
+        assert_eq!(
+            backend.read_file("/a.txt").await.unwrap().as_ref(),
+            b"hello world"
+        );
+
+        let request = fsync(&handle).try_into().unwrap();
+        assert_eq!(
+            handler
+                .extended(6, extensions::FSYNC.into(), request)
+                .await
+                .unwrap_err(),
+            StatusCode::Failure
+        );
+    }
+
+    #[tokio::test]
+    async fn test_limits_advertised_and_enforced() {
+        let backend = Arc::new(MemoryBackend::new());
+        let mut handler = SftpHandler::new(backend.clone());
+
+        let version = handler.init(3, HashMap::new()).await.unwrap();
+        for name in [
+            POSIX_RENAME,
+            extensions::STATVFS,
+            extensions::FSYNC,
+            extensions::LIMITS,
+        ] {
+            assert!(version.extensions.contains_key(name), "{name}");
+        }
+
+        let packet = handler
+            .extended(1, extensions::LIMITS.into(), Vec::new())
+            .await
+            .unwrap();
+        let Packet::ExtendedReply(reply) = packet else {
+            panic!("expected an extended reply");
+        };
+        let limits: LimitsExtension = russh_sftp::de::from_bytes(&mut reply.data.into()).unwrap();
+        assert_eq!(limits.max_packet_len, MAX_PACKET_LEN);
+        assert_eq!(limits.max_open_handles, MAX_OPEN_HANDLES as u64);
+
+        for _ in 0..MAX_OPEN_HANDLES {
+            handler.opendir(2, "/".into()).await.unwrap();
+        }
+        assert_eq!(
+            handler.opendir(3, "/".into()).await.unwrap_err(),
+            StatusCode::Failure
+        );
+    }
+
+    #[tokio::test]
+    async fn test_statvfs_from_backend_space() {
+        let request = || StatvfsExtension { path: "/".into() }.try_into().unwrap();
+
+        let mut handler = SftpHandler::new(Arc::new(MemoryBackend::new()));
+        assert_eq!(
+            handler
+                .extended(1, extensions::STATVFS.into(), request())
+                .await
+                .unwrap_err(),
+            StatusCode::OpUnsupported
+        );
+
+        #[cfg(unix)]
+        {
+            let dir = tempfile::TempDir::new().unwrap();
+            let backend = Arc::new(crate::backend::LocalBackend::new(dir.path()));
+            let mut handler = SftpHandler::new(backend);
+            let packet = handler
+                .extended(2, extensions::STATVFS.into(), request())
+                .await
+                .unwrap();
+            let Packet::ExtendedReply(reply) = packet else {
+                panic!("expected an extended reply");
+            };
+            let stats: Statvfs = russh_sftp::de::from_bytes(&mut reply.data.into()).unwrap();
+            assert_eq!(stats.block_size, STATVFS_BLOCK_SIZE);
+            assert!(stats.blocks > 0);
+            assert!(stats.blocks_avail <= stats.blocks_free);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_copy_file_extension() {
+        let backend = Arc::new(MemoryBackend::new());
+        for (path, content) in [("/a.txt", "hello"), ("/b.txt", "old")] {
+            backend
+                .write_file(path, Bytes::from(content))
+                .await
+                .unwrap();
+        }
+        let mut handler = SftpHandler::new(backend.clone());
+        let copy = |destination: &str, overwrite| {
+            let request = CopyFileExtension {
+                source: "/a.txt".into(),
+                destination: destination.into(),
+                overwrite,
+            };
+            russh_sftp::ser::to_bytes(&request).unwrap().to_vec()
+        };
+
+        assert_eq!(
+            handler
+                .extended(1, COPY_FILE.into(), copy("/b.txt", 0))
+                .await
+                .unwrap_err(),
+            StatusCode::Failure
+        );
+        assert_eq!(backend.read_file("/b.txt").await.unwrap().as_ref(), b"old");
+        for (id, destination) in [(2, "/b.txt"), (3, "/c.txt")] {
+            handler
+                .extended(id, COPY_FILE.into(), copy(destination, 1))
+                .await
+                .unwrap();
+            assert_eq!(
+                backend.read_file(destination).await.unwrap().as_ref(),
+                b"hello"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_writes_past_max_file_size_fail() {
+        let backend = Arc::new(MemoryBackend::new());
+        backend
+            .write_file("/a.txt", Bytes::from_static(b"abcd"))
+            .await
+            .unwrap();
+        let mut handler = SftpHandler::new(backend.clone()).with_max_file_size(8);
+        let flags = OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE;
+        let handle = open_with(&mut handler, "/b.txt", flags).await.unwrap();
+
+        for offset in [u64::MAX - 1, 1 << 50, 6] {
+            assert_eq!(
+                handler
+                    .write(2, handle.clone(), offset, b"abcd".to_vec())
+                    .await
+                    .unwrap_err(),
+                StatusCode::Failure
+            );
+        }
+        handler
+            .write(3, handle.clone(), 4, b"abcd".to_vec())
+            .await
+            .unwrap();
+
+        // copy-data writes through the same check
+        let source = open_with(&mut handler, "/a.txt", OpenFlags::READ)
+            .await
+            .unwrap();
+        let request = CopyDataExtension {
+            read_from_handle: source,
+            read_from_offset: 0,
+            read_data_length: 4,
+            write_to_handle: handle.clone(),
+            write_to_offset: u64::MAX - 2,
+        };
+        let data = russh_sftp::ser::to_bytes(&request).unwrap().to_vec();
+        assert_eq!(
+            handler
+                .extended(4, COPY_DATA.into(), data)
+                .await
+                .unwrap_err(),
+            StatusCode::Failure
+        );
+
+        handler.close(5, handle).await.unwrap();
+        assert_eq!(
+            backend.read_file("/b.txt").await.unwrap().as_ref(),
+            b"\0\0\0\0abcd"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_copy_data_between_handles() {
+        let backend = Arc::new(MemoryBackend::new());
+        backend
+            .write_file("/a.txt", Bytes::from_static(b"hello world"))
+            .await
+            .unwrap();
+        let mut handler = SftpHandler::new(backend.clone());
+        let copy = |source: &str, offset, len, destination: &str| {
+            let request = CopyDataExtension {
+                read_from_handle: source.into(),
+                read_from_offset: offset,
+                read_data_length: len,
+                write_to_handle: destination.into(),
+                write_to_offset: 0,
+            };
+            russh_sftp::ser::to_bytes(&request).unwrap().to_vec()
+        };
+
+        let write = OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE;
+        let source = open_with(&mut handler, "/a.txt", OpenFlags::READ)
+            .await
+            .unwrap();
+
+        // A whole file is copied by the backend before the handle is closed
+        let whole = open_with(&mut handler, "/b.txt", write).await.unwrap();
+        handler
+            .extended(2, COPY_DATA.into(), copy(&source, 0, 0, &whole))
+            .await
+            .unwrap();
+        assert_eq!(
+            backend.read_file("/b.txt").await.unwrap().as_ref(),
+            b"hello world"
+        );
+        // The handle stays writable
+        handler
+            .write(3, whole.clone(), 11, b"!".to_vec())
+            .await
+            .unwrap();
+        handler.close(4, whole).await.unwrap();
+        assert_eq!(
+            backend.read_file("/b.txt").await.unwrap().as_ref(),
+            b"hello world!"
+        );
+
+        // A range goes through the handles
+        let part = open_with(&mut handler, "/c.txt", write).await.unwrap();
+        handler
+            .extended(5, COPY_DATA.into(), copy(&source, 6, 5, &part))
+            .await
+            .unwrap();
+        handler.close(6, part).await.unwrap();
+        assert_eq!(
+            backend.read_file("/c.txt").await.unwrap().as_ref(),
+            b"world"
+        );
+
+        assert_eq!(
+            handler
+                .extended(7, COPY_DATA.into(), copy(&source, 0, 0, &source))
+                .await
+                .unwrap_err(),
+            StatusCode::Failure
+        );
+    }
 }
@@ -0,0 +1,227 @@
+//! Graceful shutdown and connection draining
+
+use parking_lot::Mutex;
+use russh::server::Handle;
+use russh::Disconnect;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::task_tracker::TaskTrackerToken;
+use tokio_util::task::TaskTracker;
+use tracing::{info, warn};
+
+/// How often idle sessions are looked for while draining
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long sessions get to unwind after being disconnected at the deadline
+const FORCED_CLOSE_GRACE: Duration = Duration::from_secs(1);
+
+/// Stops a running [`Server`](crate::Server)
+///
+/// Cloning the handle is cheap; any clone can trigger shutdown. Once
+/// triggered the server stops accepting connections, waits for in-flight
+/// transfers to finish and returns from `run`.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownHandle {
+    token: CancellationToken,
+}
+
+impl ShutdownHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Begin shutting down
+    pub fn shutdown(&self) {
+        self.token.cancel();
+    }
+
+    /// Whether shutdown has been requested
+    pub fn is_shutdown(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Wait until shutdown is requested
+    pub async fn wait(&self) {
+        self.token.cancelled().await
+    }
+}
+
+/// Open files on one connection, whether being uploaded or downloaded
+///
+/// Clones share the count. Every clone also keeps the server from finishing
+/// its drain, so SFTP handlers running on their own tasks are waited for
+/// even after the SSH session has ended.
+#[derive(Debug, Clone)]
+pub(crate) struct Activity {
+    open_files: Arc<AtomicUsize>,
+    _token: TaskTrackerToken,
+}
+
+impl Activity {
+    pub(crate) fn file_opened(&self) {
+        self.open_files.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn file_closed(&self) {
+        self.open_files.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn is_idle(&self) -> bool {
+        self.open_files.load(Ordering::SeqCst) == 0
+    }
+}
+
+/// Live connections and the tasks serving them
+#[derive(Clone, Default)]
+pub(crate) struct Connections {
+    tracker: TaskTracker,
+    sessions: Arc<Mutex<HashMap<usize, (Handle, Activity)>>>,
+    next_id: Arc<AtomicUsize>,
+}
+
+impl Connections {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Activity tracker for a new connection
+    pub(crate) fn activity(&self) -> Activity {
+        Activity {
+            open_files: Arc::new(AtomicUsize::new(0)),
+            _token: self.tracker.token(),
+        }
+    }
+
+    /// Run a connection's task, counting it towards the drain
+    pub(crate) fn spawn<F>(&self, task: F)
+    where
+        F: std::future::Future<Output = ()> + Send + 'static,
+    {
+        self.tracker.spawn(task);
+    }
+
+    /// Remember an established session so it can be disconnected later
+    pub(crate) fn register(&self, handle: Handle, activity: Activity) -> usize {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.sessions.lock().insert(id, (handle, activity));
+        id
+    }
+
+    pub(crate) fn unregister(&self, id: usize) {
+        self.sessions.lock().remove(&id);
+    }
+
+    /// Number of established sessions
+    pub(crate) fn len(&self) -> usize {
+        self.sessions.lock().len()
+    }
+
+    /// Disconnect sessions matching `filter`
+    async fn disconnect_where(&self, filter: impl Fn(&Activity) -> bool) {
+        let handles: Vec<Handle> = self
+            .sessions
+            .lock()
+            .values()
+            .filter(|(_, activity)| filter(activity))
+            .map(|(handle, _)| handle.clone())
+            .collect();
+        for handle in handles {
+            // The session may already be gone, which is what we want anyway
+            let _ = handle
+                .disconnect(
+                    Disconnect::ByApplication,
+                    "Server shutting down".into(),
+                    "en".into(),
+                )
+                .await;
+        }
+    }
+
+    /// Let in-flight transfers finish, then close everything
+    ///
+    /// Sessions without an open file are disconnected straight away; the
+    /// rest are disconnected as their files are closed or when `timeout`
+    /// runs out, whichever comes first.
+    pub(crate) async fn drain(&self, timeout: Duration) {
+        self.tracker.close();
+        let deadline = Instant::now() + timeout;
+        info!(sessions = self.len(), ?timeout, "Draining sessions");
+
+        loop {
+            self.disconnect_where(Activity::is_idle).await;
+            tokio::select! {
+                _ = self.tracker.wait() => {
+                    info!("All sessions closed");
+                    return;
+                }
+                _ = tokio::time::sleep_until(deadline) => break,
+                _ = tokio::time::sleep(DRAIN_POLL_INTERVAL) => {}
+            }
+        }
+
+        warn!(
+            sessions = self.len(),
+            "Shutdown timeout reached, disconnecting remaining sessions"
+        );
+        self.disconnect_where(|_| true).await;
+        if tokio::time::timeout(FORCED_CLOSE_GRACE, self.tracker.wait())
+            .await
+            .is_err()
+        {
+            warn!("Sessions still running after forced disconnect");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_shutdown_handle() {
+        let handle = ShutdownHandle::new();
+        let clone = handle.clone();
+        assert!(!handle.is_shutdown());
+
+        let waiter = tokio::spawn(async move { clone.wait().await });
+        handle.shutdown();
+        waiter.await.unwrap();
+        assert!(handle.is_shutdown());
+    }
+
+    #[tokio::test]
+    async fn test_drain_waits_for_activity() {
+        let connections = Connections::new();
+        let activity = connections.activity();
+        activity.file_opened();
+
+        let released = activity.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            released.file_closed();
+            drop(released);
+        });
+        drop(activity);
+
+        let started = Instant::now();
+        connections.drain(Duration::from_secs(5)).await;
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_drain_gives_up_at_deadline() {
+        let connections = Connections::new();
+        let activity = connections.activity();
+        activity.file_opened();
+
+        let started = Instant::now();
+        connections.drain(Duration::from_millis(50)).await;
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert!(started.elapsed() < Duration::from_secs(3));
+        drop(activity);
+    }
+}
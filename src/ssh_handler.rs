@@ -1,101 +1,485 @@
-use crate::backend::Backend;
+use crate::audit::{AuditKind, AuditLog, SessionAudit};
+use crate::auth::{
+    AuthContext, AuthMethod, Authenticator, Challenge, ChallengeHandler, ChallengeResult,
+    TrustedUserCaKeys,
+};
+pub use crate::auth::{PasswordAuthCallback, PubkeyAuthCallback};
+use crate::backend::{Backend, BackendError, BackendFactory, BackendResult, ScopedBackend};
+use crate::events::EventSender;
+use crate::limits::{ConnectionGuard, Limiter, Refusal};
+use crate::permissions::PermissionPolicy;
+use crate::server::ServerConfig;
 use crate::sftp_handler::SftpHandler;
+use crate::shutdown::Activity;
+use crate::telemetry;
 use async_trait::async_trait;
-use russh::keys::PublicKey;
-use russh::server::{Auth, Msg, Session};
-use russh::{Channel, ChannelId};
+use russh::keys::{Certificate, PublicKey};
+use russh::server::{Auth, Msg, Response, Session};
+use russh::{Channel, ChannelId, MethodSet};
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
-/// Password authentication callback type
-pub type PasswordAuthCallback = Arc<dyn Fn(&str, &str) -> bool + Send + Sync>;
-
-/// Public key authentication callback type
-/// Returns true if the given public key is authorized for the user
-pub type PubkeyAuthCallback = Arc<dyn Fn(&str, &PublicKey) -> bool + Send + Sync>;
+/// Home directory callback type
+/// Maps an authenticated username to the backend path that becomes their `/`
+pub type HomeDirCallback = Arc<dyn Fn(&str) -> String + Send + Sync>;
 
 /// Authentication configuration
 #[derive(Clone, Default)]
 pub struct AuthConfig {
-    pub password_callback: Option<PasswordAuthCallback>,
-    pub pubkey_callback: Option<PubkeyAuthCallback>,
+    /// Checks password logins
+    pub password: Option<Arc<dyn Authenticator>>,
+    /// Checks public key logins
+    pub pubkey: Option<Arc<dyn Authenticator>>,
+    /// CA keys whose user certificates are accepted
+    pub trusted_user_ca_keys: Option<Arc<TrustedUserCaKeys>>,
+    /// Drives keyboard-interactive logins
+    pub keyboard_interactive: Option<Arc<dyn ChallengeHandler>>,
+    /// Methods that must succeed in order, one per step
+    ///
+    /// Empty means any single configured method is enough.
+    pub required_methods: Vec<MethodSet>,
+}
+
+impl AuthConfig {
+    /// Methods to advertise before the client has authenticated
+    pub fn initial_methods(&self) -> MethodSet {
+        if let Some(first) = self.required_methods.first() {
+            return *first;
+        }
+
+        let mut methods = MethodSet::empty();
+        if self.password.is_some() {
+            methods |= MethodSet::PASSWORD;
+        }
+        if self.pubkey.is_some() || self.trusted_user_ca_keys.is_some() {
+            methods |= MethodSet::PUBLICKEY;
+        }
+        if self.keyboard_interactive.is_some() {
+            methods |= MethodSet::KEYBOARD_INTERACTIVE;
+        }
+        methods
+    }
 }
 
 /// SSH server that creates sessions for each connection
-pub struct SshServer<B: Backend> {
-    backend: Arc<B>,
+pub struct SshServer {
+    backends: Arc<dyn BackendFactory>,
     auth_config: AuthConfig,
+    config: Arc<ServerConfig>,
+    home_dir: Option<HomeDirCallback>,
+    permissions: Option<Arc<PermissionPolicy>>,
+    audit: Option<Arc<AuditLog>>,
+    events: Option<EventSender>,
+    limiter: Arc<Limiter>,
 }
 
-impl<B: Backend> SshServer<B> {
-    pub fn new(backend: Arc<B>, auth_config: AuthConfig) -> Self {
+impl SshServer {
+    pub fn new(
+        backends: Arc<dyn BackendFactory>,
+        auth_config: AuthConfig,
+        config: Arc<ServerConfig>,
+    ) -> Self {
+        let limiter = Arc::new(Limiter::new(
+            config.max_connections,
+            config.max_connections_per_ip,
+            config.ban,
+        ));
         Self {
-            backend,
+            backends,
             auth_config,
+            config,
+            home_dir: None,
+            permissions: None,
+            audit: None,
+            events: None,
+            limiter,
         }
     }
+
+    /// Confine each user to the home directory returned by `home_dir`
+    pub fn with_home_dir(mut self, home_dir: Option<HomeDirCallback>) -> Self {
+        self.home_dir = home_dir;
+        self
+    }
+
+    /// Check SFTP operations against `permissions`
+    pub fn with_permissions(mut self, permissions: Option<Arc<PermissionPolicy>>) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Record logins and file operations to `audit`
+    pub fn with_audit(mut self, audit: Option<Arc<AuditLog>>) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// Queue file events for the server's event hooks
+    pub(crate) fn with_events(mut self, events: Option<EventSender>) -> Self {
+        self.events = events;
+        self
+    }
 }
 
-impl<B: Backend> Clone for SshServer<B> {
+impl Clone for SshServer {
     fn clone(&self) -> Self {
         Self {
-            backend: self.backend.clone(),
+            backends: self.backends.clone(),
             auth_config: self.auth_config.clone(),
+            config: self.config.clone(),
+            home_dir: self.home_dir.clone(),
+            permissions: self.permissions.clone(),
+            audit: self.audit.clone(),
+            events: self.events.clone(),
+            limiter: self.limiter.clone(),
         }
     }
 }
 
-impl<B: Backend> russh::server::Server for SshServer<B> {
-    type Handler = SshSession<B>;
+impl russh::server::Server for SshServer {
+    type Handler = SshSession;
+
+    fn new_client(&mut self, addr: Option<SocketAddr>) -> Self::Handler {
+        let session = SshSession::new(
+            self.backends.clone(),
+            self.auth_config.clone(),
+            self.config.clone(),
+        )
+        .with_peer_addr(addr)
+        .with_home_dir(self.home_dir.clone())
+        .with_permissions(self.permissions.clone())
+        .with_audit(self.audit.clone())
+        .with_events(self.events.clone());
 
-    fn new_client(&mut self, addr: Option<std::net::SocketAddr>) -> Self::Handler {
-        info!(?addr, "New SSH connection");
-        SshSession::new(self.backend.clone(), self.auth_config.clone())
+        match self.limiter.admit(addr.map(|addr| addr.ip())) {
+            Ok(connection) => {
+                info!(?addr, "New SSH connection");
+                session.with_connection(connection)
+            }
+            Err(refusal) => {
+                warn!(?addr, reason = %refusal, "Refusing SSH connection");
+                telemetry::connection_refused(refusal.as_str());
+                session.refused(refusal)
+            }
+        }
     }
 }
 
 /// Individual SSH session handler
-pub struct SshSession<B: Backend> {
-    backend: Arc<B>,
+pub struct SshSession {
+    backends: Arc<dyn BackendFactory>,
     auth_config: AuthConfig,
+    config: Arc<ServerConfig>,
+    home_dir: Option<HomeDirCallback>,
+    permissions: Option<Arc<PermissionPolicy>>,
+    audit: Option<Arc<AuditLog>>,
+    events: Option<EventSender>,
+    activity: Option<Activity>,
+    peer_addr: Option<SocketAddr>,
+    /// Slot held against the connection limits
+    connection: Option<ConnectionGuard>,
+    /// Set when the connection was over a limit or from a banned address
+    refusal: Option<Refusal>,
+    /// Failed logins on this connection
+    failed_attempts: usize,
+    /// Set once authentication has succeeded
+    auth: Option<AuthContext>,
+    /// User and number of required methods completed so far
+    progress: Option<(String, usize)>,
+    /// Keyboard-interactive challenge awaiting a response
+    challenge: Option<Challenge>,
     channels: Arc<Mutex<HashMap<ChannelId, Channel<Msg>>>>,
 }
 
-impl<B: Backend> SshSession<B> {
-    pub fn new(backend: Arc<B>, auth_config: AuthConfig) -> Self {
+impl SshSession {
+    pub fn new(
+        backends: Arc<dyn BackendFactory>,
+        auth_config: AuthConfig,
+        config: Arc<ServerConfig>,
+    ) -> Self {
         Self {
-            backend,
+            backends,
             auth_config,
+            config,
+            home_dir: None,
+            permissions: None,
+            audit: None,
+            events: None,
+            activity: None,
+            peer_addr: None,
+            connection: None,
+            refusal: None,
+            failed_attempts: 0,
+            auth: None,
+            progress: None,
+            challenge: None,
             channels: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
+    /// Confine the user to the home directory returned by `home_dir`
+    pub fn with_home_dir(mut self, home_dir: Option<HomeDirCallback>) -> Self {
+        self.home_dir = home_dir;
+        self
+    }
+
+    /// Check SFTP operations against `permissions`
+    pub fn with_permissions(mut self, permissions: Option<Arc<PermissionPolicy>>) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Record logins and file operations to `audit`
+    pub fn with_audit(mut self, audit: Option<Arc<AuditLog>>) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// Queue file events for the server's event hooks
+    pub(crate) fn with_events(mut self, events: Option<EventSender>) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Report open files to the connection's shutdown tracker
+    pub(crate) fn with_activity(mut self, activity: Activity) -> Self {
+        self.activity = Some(activity);
+        self
+    }
+
+    /// Record the remote address of the client
+    pub fn with_peer_addr(mut self, peer_addr: Option<SocketAddr>) -> Self {
+        self.peer_addr = peer_addr;
+        self
+    }
+
+    /// Hold `connection` until the session ends
+    pub(crate) fn with_connection(mut self, connection: ConnectionGuard) -> Self {
+        self.connection = Some(connection);
+        self
+    }
+
+    /// Mark the session as refused, so it is dropped without authenticating
+    pub(crate) fn refused(mut self, refusal: Refusal) -> Self {
+        self.refusal = Some(refusal);
+        self
+    }
+
+    /// Whether the connection was over a limit or from a banned address
+    pub fn is_refused(&self) -> bool {
+        self.refusal.is_some()
+    }
+
+    /// Drop the connection if it was refused or its address has since been banned
+    fn check_admitted(&self) -> Result<(), russh::Error> {
+        let banned = self.connection.as_ref().is_some_and(|c| c.is_banned());
+        if self.refusal.is_some() || banned {
+            debug!(peer = ?self.peer_addr, "Dropping connection from refused address");
+            return Err(russh::Error::Disconnect);
+        }
+        Ok(())
+    }
+
+    /// Count a login attempt with `method` and audit it, failed if `error` is set
+    fn record_auth(&self, user: &str, method: &'static str, error: Option<&str>) {
+        telemetry::auth_attempt(method, error.is_none());
+        if let Some(ref log) = self.audit {
+            let audit = SessionAudit::new(log.clone(), Some(user.to_string()), self.peer_addr);
+            let kind = AuditKind::Auth {
+                method: method.to_string(),
+            };
+            audit.record(kind, error.map(String::from));
+        }
+    }
+
+    /// Reject a failed login, dropping the connection once it or its
+    /// address has failed too often
+    fn fail(&mut self, user: &str, method: &'static str) -> Result<Auth, russh::Error> {
+        self.record_auth(user, method, Some("rejected"));
+        self.failed_attempts += 1;
+        if self.connection.as_ref().is_some_and(|c| c.record_failure()) {
+            warn!(user, peer = ?self.peer_addr, "Banning address after repeated login failures");
+            return Err(russh::Error::Disconnect);
+        }
+        if self.failed_attempts >= self.config.max_auth_attempts {
+            info!(user, peer = ?self.peer_addr, attempts = self.failed_attempts, "Too many authentication failures");
+            return Err(russh::Error::Disconnect);
+        }
+        Ok(self.reject(user))
+    }
+
+    /// Whether the public key authenticator accepts `key` for `user`
+    async fn key_authorized(&self, user: &str, key: &PublicKey) -> bool {
+        let Some(ref authenticator) = self.auth_config.pubkey else {
+            return false;
+        };
+        let peer = self.peer_addr.map(|addr| addr.ip());
+        authenticator.check_publickey_from(user, key, peer).await
+    }
+
+    /// Index of the next required step for `user`
+    fn current_step(&self, user: &str) -> usize {
+        match self.progress {
+            Some((ref u, step)) if u == user => step,
+            _ => 0,
+        }
+    }
+
+    /// Whether `method` may be attempted by `user` right now
+    fn method_allowed(&self, user: &str, method: MethodSet) -> bool {
+        match self
+            .auth_config
+            .required_methods
+            .get(self.current_step(user))
+        {
+            Some(step) => step.contains(method),
+            None => true,
+        }
+    }
+
+    /// Reject an attempt, keeping the current step's methods on offer
+    fn reject(&self, user: &str) -> Auth {
+        Auth::Reject {
+            proceed_with_methods: self
+                .auth_config
+                .required_methods
+                .get(self.current_step(user))
+                .copied(),
+        }
+    }
+
+    /// Record a successful method, accepting once every required step is done
+    fn accept(&mut self, user: &str, method: AuthMethod) -> Auth {
+        self.record_auth(user, method.name(), None);
+        let required = &self.auth_config.required_methods;
+        let step = self.current_step(user) + 1;
+        if step < required.len() {
+            info!(
+                user,
+                step,
+                total = required.len(),
+                "Partial authentication success"
+            );
+            self.progress = Some((user.to_string(), step));
+            return Auth::Reject {
+                proceed_with_methods: Some(required[step]),
+            };
+        }
+
+        self.progress = None;
+        self.auth = Some(AuthContext::new(user, self.peer_addr, method));
+        Auth::Accept
+    }
+
+    /// Handle the outcome of a keyboard-interactive round
+    fn challenge_result(
+        &mut self,
+        user: &str,
+        result: ChallengeResult,
+    ) -> Result<Auth, russh::Error> {
+        match result {
+            ChallengeResult::Accept => {
+                info!(user, "Keyboard-interactive authentication successful");
+                self.challenge = None;
+                Ok(self.accept(user, AuthMethod::KeyboardInteractive))
+            }
+            ChallengeResult::Continue(next) => {
+                self.challenge = Some(next.clone());
+                Ok(next.into_auth())
+            }
+            ChallengeResult::Reject => {
+                info!(user, "Keyboard-interactive authentication failed");
+                self.challenge = None;
+                self.fail(user, AuthMethod::KeyboardInteractive.name())
+            }
+        }
+    }
+
+    /// Resolve the backend for the authenticated user, scoped to their home if configured
+    async fn user_backend(&self, auth: &AuthContext) -> BackendResult<Arc<dyn Backend>> {
+        let backend = self.backends.backend(&auth.user, auth).await?;
+        #[cfg(feature = "metrics")]
+        let backend: Arc<dyn Backend> = Arc::new(crate::backend::MeteredBackend::new(backend));
+        let Some(ref home_dir) = self.home_dir else {
+            return Ok(backend);
+        };
+        let user = auth.user.as_str();
+
+        // A name like `..` or `a/b` would put the home outside the user's own
+        if user.is_empty() || user == "." || user == ".." || user.contains('/') {
+            warn!(user, "Refusing home directory for unusable username");
+            return Err(BackendError::PermissionDenied);
+        }
+        let home = home_dir(user);
+        let scoped = ScopedBackend::new(backend, &home).inspect_err(|_| {
+            warn!(user, home = %home, "Refusing home directory outside the backend root");
+        })?;
+        debug!(
+            user,
+            home = scoped.root(),
+            "Scoping session to home directory"
+        );
+        if let Err(err) = scoped.create_root().await {
+            warn!(user, home = scoped.root(), error = %err, "Could not create home directory");
+        }
+        Ok(Arc::new(scoped))
+    }
+
     async fn get_channel(&self, channel_id: ChannelId) -> Option<Channel<Msg>> {
         self.channels.lock().await.remove(&channel_id)
     }
 }
 
 #[async_trait]
-impl<B: Backend> russh::server::Handler for SshSession<B> {
+impl russh::server::Handler for SshSession {
     type Error = russh::Error;
 
     async fn auth_password(&mut self, user: &str, password: &str) -> Result<Auth, Self::Error> {
         debug!(user, "Password authentication attempt");
+        self.check_admitted()?;
 
-        if let Some(ref callback) = self.auth_config.password_callback {
-            let result = callback(user, password);
+        if !self.method_allowed(user, MethodSet::PASSWORD) {
+            return Ok(self.reject(user));
+        }
+
+        if let Some(ref authenticator) = self.auth_config.password {
+            let result = authenticator.check_password(user, password).await;
             if result {
                 info!(user, "Password authentication successful");
-                return Ok(Auth::Accept);
+                return Ok(self.accept(user, AuthMethod::Password));
             }
         }
 
         info!(user, "Password authentication failed");
-        Ok(Auth::Reject {
-            proceed_with_methods: None,
-        })
+        self.fail(user, AuthMethod::Password.name())
+    }
+
+    /// Only ask for a signature with keys that could log in
+    ///
+    /// Clients try every key in their agent, so refusing a key here is not a
+    /// failed login and doesn't count toward attempt limits or bans.
+    async fn auth_publickey_offered(
+        &mut self,
+        user: &str,
+        public_key: &PublicKey,
+    ) -> Result<Auth, Self::Error> {
+        self.check_admitted()?;
+        if !self.method_allowed(user, MethodSet::PUBLICKEY) {
+            return Ok(self.reject(user));
+        }
+        // A certificate is offered as its bare key, which only the CA check
+        // after signing can vouch for
+        if self.auth_config.trusted_user_ca_keys.is_some()
+            || self.key_authorized(user, public_key).await
+        {
+            Ok(Auth::Accept)
+        } else {
+            debug!(user, key_type = ?public_key.algorithm(), "Refusing unknown public key");
+            Ok(self.reject(user))
+        }
     }
 
     async fn auth_publickey(
@@ -104,19 +488,96 @@ impl<B: Backend> russh::server::Handler for SshSession<B> {
         public_key: &PublicKey,
     ) -> Result<Auth, Self::Error> {
         debug!(user, key_type = ?public_key.algorithm(), "Public key authentication attempt");
+        self.check_admitted()?;
 
-        if let Some(ref callback) = self.auth_config.pubkey_callback {
-            let result = callback(user, public_key);
-            if result {
-                info!(user, "Public key authentication successful");
-                return Ok(Auth::Accept);
-            }
+        if !self.method_allowed(user, MethodSet::PUBLICKEY) {
+            return Ok(self.reject(user));
         }
 
+        if self.key_authorized(user, public_key).await {
+            info!(user, "Public key authentication successful");
+            return Ok(self.accept(user, AuthMethod::PublicKey(public_key.clone())));
+        }
+
+        // Only keys let through as a possible certificate's get here, so like
+        // keys refused when offered this is not counted as a failed login
         info!(user, "Public key authentication failed");
-        Ok(Auth::Reject {
-            proceed_with_methods: None,
-        })
+        self.record_auth(user, "publickey", Some("rejected"));
+        Ok(self.reject(user))
+    }
+
+    async fn auth_openssh_certificate(
+        &mut self,
+        user: &str,
+        certificate: &Certificate,
+    ) -> Result<Auth, Self::Error> {
+        debug!(
+            user,
+            key_id = certificate.key_id(),
+            "Certificate authentication attempt"
+        );
+        self.check_admitted()?;
+
+        if !self.method_allowed(user, MethodSet::PUBLICKEY) {
+            return Ok(self.reject(user));
+        }
+
+        if let Some(ref cas) = self.auth_config.trusted_user_ca_keys {
+            let peer = self.peer_addr.map(|addr| addr.ip());
+            match cas.verify(user, certificate, peer) {
+                Ok(()) => {
+                    info!(
+                        user,
+                        key_id = certificate.key_id(),
+                        serial = certificate.serial(),
+                        "Certificate authentication successful"
+                    );
+                    let method = AuthMethod::Certificate(Box::new(certificate.clone()));
+                    return Ok(self.accept(user, method));
+                }
+                Err(err) => {
+                    info!(user, key_id = certificate.key_id(), error = %err, "Certificate rejected");
+                }
+            }
+        }
+
+        info!(user, "Certificate authentication failed");
+        self.fail(user, "certificate")
+    }
+
+    async fn auth_keyboard_interactive(
+        &mut self,
+        user: &str,
+        _submethods: &str,
+        response: Option<Response<'async_trait>>,
+    ) -> Result<Auth, Self::Error> {
+        self.check_admitted()?;
+        let Some(handler) = self.auth_config.keyboard_interactive.clone() else {
+            return Ok(self.reject(user));
+        };
+        if !self.method_allowed(user, MethodSet::KEYBOARD_INTERACTIVE) {
+            return Ok(self.reject(user));
+        }
+
+        let result = match (response, self.challenge.take()) {
+            (None, _) => {
+                debug!(user, "Keyboard-interactive authentication attempt");
+                match handler.begin(user).await {
+                    Some(challenge) => ChallengeResult::Continue(challenge),
+                    None => ChallengeResult::Reject,
+                }
+            }
+            (Some(response), Some(challenge)) => {
+                let responses: Vec<String> = response
+                    .map(|r| String::from_utf8_lossy(&r).into_owned())
+                    .collect();
+                handler.respond(user, &challenge, &responses).await
+            }
+            // A response without an outstanding challenge
+            (Some(_), None) => ChallengeResult::Reject,
+        };
+
+        self.challenge_result(user, result)
     }
 
     async fn channel_open_session(
@@ -137,11 +598,45 @@ impl<B: Backend> russh::server::Handler for SshSession<B> {
     ) -> Result<(), Self::Error> {
         debug!(channel_id = ?channel_id, name, "Subsystem request");
 
+        let Some(auth) = self.auth.clone() else {
+            session.channel_failure(channel_id)?;
+            return Ok(());
+        };
+
         if name == "sftp" {
             if let Some(channel) = self.get_channel(channel_id).await {
-                let sftp_handler = SftpHandler::new(self.backend.clone());
+                let backend = match self.user_backend(&auth).await {
+                    Ok(backend) => backend,
+                    Err(err) => {
+                        warn!(user = auth.user, error = %err, "Could not resolve backend");
+                        session.channel_failure(channel_id)?;
+                        return Ok(());
+                    }
+                };
+                let mut sftp_handler = SftpHandler::new(backend)
+                    .with_spill_threshold(self.config.spill_threshold)
+                    .with_max_file_size(self.config.max_file_size)
+                    .with_unflushed_writes(self.config.unflushed_writes)
+                    .with_lenient_setstat(self.config.lenient_setstat)
+                    .with_user(auth.user)
+                    .with_peer_addr(self.peer_addr);
+                if let Some(ref audit) = self.audit {
+                    sftp_handler = sftp_handler.with_audit(audit.clone());
+                }
+                if let Some(ref events) = self.events {
+                    sftp_handler = sftp_handler.with_events(events.clone());
+                }
+                if let Some(ref permissions) = self.permissions {
+                    sftp_handler = sftp_handler.with_permissions(permissions.clone());
+                }
+                if let Some(ref activity) = self.activity {
+                    sftp_handler = sftp_handler.with_activity(activity.clone());
+                }
                 session.channel_success(channel_id)?;
 
+                #[cfg(feature = "metrics")]
+                let sftp_handler = telemetry::MeteredHandler::new(sftp_handler);
+
                 // Run SFTP handler (blocking until session ends)
                 russh_sftp::server::run(channel.into_stream(), sftp_handler).await;
             }
@@ -162,3 +657,232 @@ impl<B: Backend> russh::server::Handler for SshSession<B> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::PasswordAuthCallback;
+    use crate::backend::{MemoryBackend, SharedBackend};
+    use crate::limits::BanPolicy;
+    use russh::keys::ssh_key::rand_core::OsRng;
+    use russh::keys::{Algorithm, PrivateKey};
+    use russh::server::{Handler, Server as _};
+    use std::time::Duration;
+
+    fn session(auth_config: AuthConfig) -> SshSession {
+        let backends = Arc::new(SharedBackend::new(Arc::new(MemoryBackend::new())));
+        SshSession::new(backends, auth_config, Arc::new(ServerConfig::default()))
+    }
+
+    fn password_server(config: ServerConfig) -> SshServer {
+        let backends = Arc::new(SharedBackend::new(Arc::new(MemoryBackend::new())));
+        let password: PasswordAuthCallback = Arc::new(|_, pass| pass == "pw");
+        let auth_config = AuthConfig {
+            password: Some(Arc::new(password)),
+            ..Default::default()
+        };
+        SshServer::new(backends, auth_config, Arc::new(config))
+    }
+
+    fn peer(addr: &str) -> Option<SocketAddr> {
+        Some(addr.parse().unwrap())
+    }
+
+    fn two_factor_config() -> AuthConfig {
+        let password: PasswordAuthCallback = Arc::new(|_, pass| pass == "pw");
+        let pubkey: PubkeyAuthCallback = Arc::new(|_, _| true);
+        AuthConfig {
+            password: Some(Arc::new(password)),
+            pubkey: Some(Arc::new(pubkey)),
+            required_methods: vec![MethodSet::PASSWORD, MethodSet::PUBLICKEY],
+            ..Default::default()
+        }
+    }
+
+    fn random_key() -> PublicKey {
+        PrivateKey::random(&mut OsRng, Algorithm::Ed25519)
+            .unwrap()
+            .public_key()
+            .clone()
+    }
+
+    #[tokio::test]
+    async fn test_single_method_accepts() {
+        let password: PasswordAuthCallback = Arc::new(|_, pass| pass == "pw");
+        let mut session = session(AuthConfig {
+            password: Some(Arc::new(password)),
+            ..Default::default()
+        });
+
+        let auth = session.auth_password("alice", "wrong").await.unwrap();
+        assert!(matches!(auth, Auth::Reject { .. }));
+        let auth = session.auth_password("alice", "pw").await.unwrap();
+        assert_eq!(auth, Auth::Accept);
+        assert_eq!(session.auth.unwrap().user, "alice");
+    }
+
+    #[tokio::test]
+    async fn test_required_methods_in_sequence() {
+        let mut session = session(two_factor_config());
+
+        let auth = session.auth_password("alice", "pw").await.unwrap();
+        assert_eq!(
+            auth,
+            Auth::Reject {
+                proceed_with_methods: Some(MethodSet::PUBLICKEY)
+            }
+        );
+        assert!(session.auth.is_none());
+
+        let auth = session
+            .auth_publickey("alice", &random_key())
+            .await
+            .unwrap();
+        assert_eq!(auth, Auth::Accept);
+        assert!(matches!(
+            session.auth.unwrap().method,
+            AuthMethod::PublicKey(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_required_methods_out_of_order() {
+        let mut session = session(two_factor_config());
+
+        let auth = session
+            .auth_publickey("alice", &random_key())
+            .await
+            .unwrap();
+        assert_eq!(
+            auth,
+            Auth::Reject {
+                proceed_with_methods: Some(MethodSet::PASSWORD)
+            }
+        );
+        assert!(session.auth.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_progress_does_not_carry_across_users() {
+        let mut session = session(two_factor_config());
+
+        session.auth_password("alice", "pw").await.unwrap();
+        let auth = session.auth_publickey("bob", &random_key()).await.unwrap();
+        assert!(matches!(auth, Auth::Reject { .. }));
+        assert!(session.auth.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_home_dir_cannot_widen_scope() {
+        let home: HomeDirCallback = Arc::new(|user| format!("tenants/{user}"));
+        let session = session(AuthConfig::default()).with_home_dir(Some(home));
+
+        for user in ["..", "../..", ".", "", "alice/../bob"] {
+            let auth = AuthContext::new(user, None, AuthMethod::Password);
+            assert!(
+                matches!(
+                    session.user_backend(&auth).await,
+                    Err(BackendError::PermissionDenied)
+                ),
+                "{user}"
+            );
+        }
+        let auth = AuthContext::new("alice", None, AuthMethod::Password);
+        assert!(session.user_backend(&auth).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_max_auth_attempts() {
+        let mut server = password_server(ServerConfig::new().max_auth_attempts(2));
+        let mut session = server.new_client(peer("10.0.0.1:5000"));
+
+        assert!(session.auth_password("alice", "wrong").await.is_ok());
+        assert!(session.auth_password("alice", "wrong").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_connection_limits() {
+        let mut server = password_server(
+            ServerConfig::new()
+                .max_connections(2)
+                .max_connections_per_ip(1),
+        );
+
+        let first = server.new_client(peer("10.0.0.1:5000"));
+        assert!(!first.is_refused());
+        assert!(server.new_client(peer("10.0.0.1:5001")).is_refused());
+        let _second = server.new_client(peer("10.0.0.2:5000"));
+        assert!(server.new_client(peer("10.0.0.3:5000")).is_refused());
+
+        drop(first);
+        let mut third = server.new_client(peer("10.0.0.3:5001"));
+        assert!(!third.is_refused());
+        assert_eq!(
+            third.auth_password("alice", "pw").await.unwrap(),
+            Auth::Accept
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unknown_offered_keys_are_not_failures() {
+        let key = random_key();
+        let allowed = key.clone();
+        let pubkey: PubkeyAuthCallback = Arc::new(move |_, k| *k == allowed);
+        let auth_config = AuthConfig {
+            pubkey: Some(Arc::new(pubkey)),
+            ..Default::default()
+        };
+        let policy = BanPolicy::new(2, Duration::from_secs(60), Duration::from_secs(60));
+        let config = ServerConfig::new().max_auth_attempts(2).ban(policy);
+        let backends = Arc::new(SharedBackend::new(Arc::new(MemoryBackend::new())));
+        let mut server = SshServer::new(backends, auth_config, Arc::new(config));
+        let mut session = server.new_client(peer("10.0.0.1:5000"));
+
+        // An agent holding several keys offers each of them in turn, and
+        // signs with any the server accepts
+        for _ in 0..5 {
+            let other = random_key();
+            let mut auth = session
+                .auth_publickey_offered("alice", &other)
+                .await
+                .unwrap();
+            if auth == Auth::Accept {
+                auth = session.auth_publickey("alice", &other).await.unwrap();
+            }
+            assert!(matches!(auth, Auth::Reject { .. }));
+        }
+        assert_eq!(
+            session.auth_publickey_offered("alice", &key).await.unwrap(),
+            Auth::Accept
+        );
+        assert_eq!(
+            session.auth_publickey("alice", &key).await.unwrap(),
+            Auth::Accept
+        );
+        assert!(!server.new_client(peer("10.0.0.1:5001")).is_refused());
+    }
+
+    #[tokio::test]
+    async fn test_ban_after_failures() {
+        let policy = BanPolicy::new(2, Duration::from_secs(60), Duration::from_secs(60));
+        let mut server = password_server(ServerConfig::new().ban(policy));
+        let mut first = server.new_client(peer("10.0.0.1:5000"));
+        let mut second = server.new_client(peer("10.0.0.1:5001"));
+
+        assert!(first.auth_password("alice", "wrong").await.is_ok());
+        // The second failure from the address bans it
+        assert!(second.auth_password("alice", "wrong").await.is_err());
+
+        // Other sessions from the address are dropped, even with the right password
+        assert!(first.auth_password("alice", "pw").await.is_err());
+        let mut refused = server.new_client(peer("10.0.0.1:5002"));
+        assert!(refused.is_refused());
+        assert!(refused.auth_password("alice", "pw").await.is_err());
+
+        let mut other = server.new_client(peer("10.0.0.2:5000"));
+        assert_eq!(
+            other.auth_password("alice", "pw").await.unwrap(),
+            Auth::Accept
+        );
+    }
+}
@@ -0,0 +1,372 @@
+//! Prometheus metrics, recorded when the `metrics` feature is enabled
+//!
+//! Without the feature the recording functions compile to nothing, so call
+//! sites need no feature gates of their own.
+#![cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+
+#[cfg(feature = "metrics")]
+use std::time::Instant;
+
+#[cfg(feature = "metrics")]
+pub(crate) use handler::MeteredHandler;
+#[cfg(feature = "metrics")]
+pub use prometheus::install_prometheus;
+
+pub(crate) fn connection_admitted() {
+    #[cfg(feature = "metrics")]
+    {
+        metrics::counter!("sftp_connections_total").increment(1);
+        metrics::gauge!("sftp_connections_active").increment(1.0);
+    }
+}
+
+pub(crate) fn connection_closed() {
+    #[cfg(feature = "metrics")]
+    metrics::gauge!("sftp_connections_active").decrement(1.0);
+}
+
+pub(crate) fn connection_refused(reason: &'static str) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!("sftp_connections_refused_total", "reason" => reason).increment(1);
+}
+
+pub(crate) fn auth_attempt(method: &'static str, success: bool) {
+    #[cfg(feature = "metrics")]
+    {
+        let result = if success { "success" } else { "failure" };
+        metrics::counter!("sftp_auth_attempts_total", "method" => method, "result" => result)
+            .increment(1);
+    }
+}
+
+/// Time a backend call that started at `started`
+#[cfg(feature = "metrics")]
+pub(crate) fn backend_operation(op: &'static str, started: Instant, success: bool) {
+    let result = if success { "success" } else { "failure" };
+    metrics::histogram!("sftp_backend_operation_duration_seconds", "op" => op, "result" => result)
+        .record(started.elapsed().as_secs_f64());
+}
+
+#[cfg(feature = "metrics")]
+mod handler {
+    use russh_sftp::protocol::{
+        Attrs, Data, FileAttributes, Handle, Name, OpenFlags, Packet, Status, StatusCode, Version,
+    };
+    use russh_sftp::server::Handler;
+    use std::collections::{HashMap, HashSet};
+
+    /// Count a request and its outcome
+    fn observe<T>(op: &'static str, result: Result<T, StatusCode>) -> Result<T, StatusCode> {
+        let status = match result {
+            Ok(_) => "Ok".to_string(),
+            Err(code) => format!("{code:?}"),
+        };
+        metrics::counter!("sftp_operations_total", "op" => op, "status" => status).increment(1);
+        result
+    }
+
+    /// SFTP handler wrapper counting requests, bytes and open handles
+    pub(crate) struct MeteredHandler<H> {
+        inner: H,
+        /// Handles this session holds open, released from the gauge on drop
+        handles: HashSet<String>,
+    }
+
+    impl<H> MeteredHandler<H> {
+        pub(crate) fn new(inner: H) -> Self {
+            Self {
+                inner,
+                handles: HashSet::new(),
+            }
+        }
+
+        fn opened(&mut self, result: &Result<Handle, StatusCode>) {
+            if let Ok(handle) = result {
+                if self.handles.insert(handle.handle.clone()) {
+                    metrics::gauge!("sftp_open_handles").increment(1.0);
+                }
+            }
+        }
+    }
+
+    impl<H> Drop for MeteredHandler<H> {
+        fn drop(&mut self) {
+            metrics::gauge!("sftp_open_handles").decrement(self.handles.len() as f64);
+        }
+    }
+
+    impl<H> Handler for MeteredHandler<H>
+    where
+        H: Handler<Error = StatusCode> + Send,
+    {
+        type Error = StatusCode;
+
+        fn unimplemented(&self) -> Self::Error {
+            self.inner.unimplemented()
+        }
+
+        async fn init(
+            &mut self,
+            version: u32,
+            extensions: HashMap<String, String>,
+        ) -> Result<Version, Self::Error> {
+            observe("init", self.inner.init(version, extensions).await)
+        }
+
+        async fn open(
+            &mut self,
+            id: u32,
+            filename: String,
+            pflags: OpenFlags,
+            attrs: FileAttributes,
+        ) -> Result<Handle, Self::Error> {
+            let result = self.inner.open(id, filename, pflags, attrs).await;
+            self.opened(&result);
+            observe("open", result)
+        }
+
+        async fn close(&mut self, id: u32, handle: String) -> Result<Status, Self::Error> {
+            // The handle is gone whether or not the close succeeded
+            if self.handles.remove(&handle) {
+                metrics::gauge!("sftp_open_handles").decrement(1.0);
+            }
+            observe("close", self.inner.close(id, handle).await)
+        }
+
+        async fn read(
+            &mut self,
+            id: u32,
+            handle: String,
+            offset: u64,
+            len: u32,
+        ) -> Result<Data, Self::Error> {
+            let result = self.inner.read(id, handle, offset, len).await;
+            if let Ok(ref data) = result {
+                metrics::counter!("sftp_read_bytes_total").increment(data.data.len() as u64);
+            }
+            observe("read", result)
+        }
+
+        async fn write(
+            &mut self,
+            id: u32,
+            handle: String,
+            offset: u64,
+            data: Vec<u8>,
+        ) -> Result<Status, Self::Error> {
+            let len = data.len() as u64;
+            let result = self.inner.write(id, handle, offset, data).await;
+            if result.is_ok() {
+                metrics::counter!("sftp_written_bytes_total").increment(len);
+            }
+            observe("write", result)
+        }
+
+        async fn lstat(&mut self, id: u32, path: String) -> Result<Attrs, Self::Error> {
+            observe("lstat", self.inner.lstat(id, path).await)
+        }
+
+        async fn fstat(&mut self, id: u32, handle: String) -> Result<Attrs, Self::Error> {
+            observe("fstat", self.inner.fstat(id, handle).await)
+        }
+
+        async fn setstat(
+            &mut self,
+            id: u32,
+            path: String,
+            attrs: FileAttributes,
+        ) -> Result<Status, Self::Error> {
+            observe("setstat", self.inner.setstat(id, path, attrs).await)
+        }
+
+        async fn fsetstat(
+            &mut self,
+            id: u32,
+            handle: String,
+            attrs: FileAttributes,
+        ) -> Result<Status, Self::Error> {
+            observe("fsetstat", self.inner.fsetstat(id, handle, attrs).await)
+        }
+
+        async fn opendir(&mut self, id: u32, path: String) -> Result<Handle, Self::Error> {
+            let result = self.inner.opendir(id, path).await;
+            self.opened(&result);
+            observe("opendir", result)
+        }
+
+        async fn readdir(&mut self, id: u32, handle: String) -> Result<Name, Self::Error> {
+            observe("readdir", self.inner.readdir(id, handle).await)
+        }
+
+        async fn remove(&mut self, id: u32, filename: String) -> Result<Status, Self::Error> {
+            observe("remove", self.inner.remove(id, filename).await)
+        }
+
+        async fn mkdir(
+            &mut self,
+            id: u32,
+            path: String,
+            attrs: FileAttributes,
+        ) -> Result<Status, Self::Error> {
+            observe("mkdir", self.inner.mkdir(id, path, attrs).await)
+        }
+
+        async fn rmdir(&mut self, id: u32, path: String) -> Result<Status, Self::Error> {
+            observe("rmdir", self.inner.rmdir(id, path).await)
+        }
+
+        async fn realpath(&mut self, id: u32, path: String) -> Result<Name, Self::Error> {
+            observe("realpath", self.inner.realpath(id, path).await)
+        }
+
+        async fn stat(&mut self, id: u32, path: String) -> Result<Attrs, Self::Error> {
+            observe("stat", self.inner.stat(id, path).await)
+        }
+
+        async fn rename(
+            &mut self,
+            id: u32,
+            oldpath: String,
+            newpath: String,
+        ) -> Result<Status, Self::Error> {
+            observe("rename", self.inner.rename(id, oldpath, newpath).await)
+        }
+
+        async fn readlink(&mut self, id: u32, path: String) -> Result<Name, Self::Error> {
+            observe("readlink", self.inner.readlink(id, path).await)
+        }
+
+        async fn symlink(
+            &mut self,
+            id: u32,
+            linkpath: String,
+            targetpath: String,
+        ) -> Result<Status, Self::Error> {
+            observe(
+                "symlink",
+                self.inner.symlink(id, linkpath, targetpath).await,
+            )
+        }
+
+        async fn extended(
+            &mut self,
+            id: u32,
+            request: String,
+            data: Vec<u8>,
+        ) -> Result<Packet, Self::Error> {
+            observe("extended", self.inner.extended(id, request, data).await)
+        }
+    }
+}
+
+#[cfg(feature = "metrics")]
+mod prometheus {
+    use metrics_exporter_prometheus::{BuildError, Matcher, PrometheusBuilder};
+    use std::net::SocketAddr;
+
+    /// Backend latency buckets, from a local disk hit to a slow S3 multipart commit
+    const LATENCY_BUCKETS: &[f64] = &[
+        0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+    ];
+
+    /// Install the Prometheus recorder and serve `/metrics` over HTTP on `addr`
+    ///
+    /// Must be called from within a Tokio runtime, before the server starts.
+    pub fn install_prometheus(addr: SocketAddr) -> Result<(), BuildError> {
+        PrometheusBuilder::new()
+            .with_http_listener(addr)
+            .set_buckets_for_metric(Matcher::Suffix("_seconds".into()), LATENCY_BUCKETS)?
+            .install()?;
+        describe();
+        Ok(())
+    }
+
+    fn describe() {
+        use metrics::{describe_counter, describe_gauge, describe_histogram, Unit};
+
+        describe_counter!("sftp_connections_total", "SSH connections accepted");
+        describe_gauge!("sftp_connections_active", "SSH connections currently open");
+        describe_counter!(
+            "sftp_connections_refused_total",
+            "SSH connections turned away by limits or bans"
+        );
+        describe_counter!(
+            "sftp_auth_attempts_total",
+            "Login attempts by method and result"
+        );
+        describe_counter!(
+            "sftp_operations_total",
+            "SFTP requests by operation and status code"
+        );
+        describe_counter!(
+            "sftp_read_bytes_total",
+            Unit::Bytes,
+            "Bytes sent to clients"
+        );
+        describe_counter!(
+            "sftp_written_bytes_total",
+            Unit::Bytes,
+            "Bytes received from clients"
+        );
+        describe_gauge!("sftp_open_handles", "Open SFTP file and directory handles");
+        describe_histogram!(
+            "sftp_backend_operation_duration_seconds",
+            Unit::Seconds,
+            "Storage backend call latency by operation"
+        );
+    }
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use super::*;
+    use crate::backend::{Backend, MemoryBackend, MeteredBackend};
+    use crate::sftp_handler::SftpHandler;
+    use metrics_exporter_prometheus::PrometheusBuilder;
+    use russh_sftp::protocol::{FileAttributes, OpenFlags};
+    use russh_sftp::server::Handler;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_sftp_and_backend_metrics() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        metrics::with_local_recorder(&recorder, || {
+            runtime.block_on(async {
+                let backend = Arc::new(MeteredBackend::new(Arc::new(MemoryBackend::new())));
+                let mut handler = MeteredHandler::new(SftpHandler::new(backend.clone()));
+                let flags = OpenFlags::WRITE | OpenFlags::CREATE;
+                let file = handler
+                    .open(1, "/a.txt".into(), flags, FileAttributes::default())
+                    .await
+                    .unwrap()
+                    .handle;
+                handler
+                    .write(2, file.clone(), 0, b"hello".to_vec())
+                    .await
+                    .unwrap();
+                handler.close(3, file).await.unwrap();
+                assert!(handler.stat(4, "/missing".into()).await.is_err());
+                let _dir = handler.opendir(5, "/".into()).await.unwrap();
+                assert!(backend.file_info("a.txt").await.is_ok());
+            })
+        });
+
+        let rendered = handle.render();
+        let has = |line: &str| rendered.lines().any(|l| l == line);
+        assert!(has(r#"sftp_operations_total{op="open",status="Ok"} 1"#));
+        assert!(has(
+            r#"sftp_operations_total{op="stat",status="NoSuchFile"} 1"#
+        ));
+        assert!(has("sftp_written_bytes_total 5"));
+        // The directory left open is released when the session ends
+        assert!(has("sftp_open_handles 0"));
+        assert!(rendered.contains(
+            r#"sftp_backend_operation_duration_seconds{op="file_info",result="success""#
+        ));
+    }
+}